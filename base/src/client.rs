@@ -3,14 +3,17 @@ use serde::{de::DeserializeOwned, Serialize};
 use tokio::time::sleep;
 
 use crate::{
-    io::send_message,
-    message::{Message, MessageId, MessagePayload},
+    codec::{Codec, CodecSelector, Encoded, MaybeEncoded},
+    message::{Envelope, Message, MessageId, MessagePayload},
     node::{Node, NodeId},
     outgoing::Outgoing,
     serve::MessageHandler,
+    shutdown::Shutdown,
 };
 use std::{marker::PhantomData, sync::Arc, time::Duration};
 
+type OutgoingRequest<Req, Res> = Message<MaybeEncoded<MessagePayload<Req, Res>>>;
+
 #[derive(Debug)]
 pub struct Client<Req, Res> {
     inner: Arc<ClientInner<Req, Res>>,
@@ -20,6 +23,7 @@ pub struct Client<Req, Res> {
 struct ClientInner<Req, Res> {
     node: Node,
     outgoing: Outgoing<Res>,
+    codec_selector: CodecSelector<Req>,
     // Send + Sync, covariant with Req
     _marker: PhantomData<fn() -> Req>,
 }
@@ -34,12 +38,17 @@ impl<Req, Res> Clone for Client<Req, Res> {
 
 impl<Req, Res> Client<Req, Res> {
     pub fn new(node: &Node) -> Self {
+        Self::with_codec(node, |_| Codec::Json)
+    }
+
+    pub fn with_codec(node: &Node, codec_selector: CodecSelector<Req>) -> Self {
         const REQUEST_TTL: Duration = Duration::from_secs(3);
 
         Self {
             inner: Arc::new(ClientInner {
                 node: node.clone(),
                 outgoing: Outgoing::new(REQUEST_TTL),
+                codec_selector,
                 _marker: PhantomData,
             }),
         }
@@ -49,6 +58,10 @@ impl<Req, Res> Client<Req, Res> {
         &self.inner.node
     }
 
+    fn shutdown(&self) -> &Shutdown {
+        self.inner.node.shutdown()
+    }
+
     pub(crate) fn complete(&self, request_id: MessageId, response: Res) -> Result<()> {
         self.inner.outgoing.complete(request_id, response)
     }
@@ -65,33 +78,19 @@ where
             "can't send message to self"
         );
 
-        let (message, request_id) = self
-            .inner
-            .node
-            .build_message_to::<MessagePayload<Req, Res>>(
-                to,
-                None,
-                MessagePayload::Request(request),
-            );
+        let (message, request_id) = self.build_request(to, request)?;
 
         let pending = self.inner.outgoing.push(request_id);
-        send_message::<MessagePayload<Req, Res>>(&message)?;
-        pending
-            .wait()
-            .await
+        self.inner.node.output().send(&message).await?;
+        self.shutdown()
+            .drainable(pending.wait())
+            .await?
             .ok_or_else(|| anyhow!("request timed out"))
     }
 
     pub async fn send_no_reply(&self, to: NodeId, request: Req) -> Result<()> {
-        let (message, _) = self
-            .inner
-            .node
-            .build_message_to::<MessagePayload<Req, Res>>(
-                to,
-                None,
-                MessagePayload::Request(request),
-            );
-        send_message::<MessagePayload<Req, Res>>(&message)?;
+        let (message, _) = self.build_request(to, request)?;
+        self.inner.node.output().send(&message).await?;
         Ok(())
     }
 
@@ -103,40 +102,67 @@ where
     ) -> Result<Res> {
         const DELAY: Duration = Duration::from_secs(1);
 
-        let (message, msg_id) = self
-            .inner
-            .node
-            .build_message_to::<MessagePayload<Req, Res>>(
-                to,
-                None,
-                MessagePayload::Request(request),
-            );
+        let (message, msg_id) = self.build_request(to, request)?;
 
         for _ in 0..max_attempts {
             let pending = self.inner.outgoing.push(msg_id);
-            send_message::<MessagePayload<Req, Res>>(&message)?;
-            if let Some(response) = pending.wait().await {
+            self.inner.node.output().send(&message).await?;
+            if let Some(response) = self.shutdown().drainable(pending.wait()).await? {
                 return Ok(response);
             }
 
-            sleep(DELAY).await;
+            self.shutdown().drainable(sleep(DELAY)).await?;
         }
 
         bail!("retries exhaused")
     }
+
+    // Compact codecs and clocks are only used between cluster nodes, Maelstrom services
+    // and clients always get plain JSON.
+    fn build_request(
+        &self,
+        to: NodeId,
+        request: Req,
+    ) -> Result<(OutgoingRequest<Req, Res>, MessageId)> {
+        let codec = (self.inner.codec_selector)(&request);
+        let is_node = self.inner.node.node_ids().contains(&to);
+        let (mut message, msg_id) =
+            self.inner
+                .node
+                .build_message_to(to, None, MessagePayload::Request(request));
+
+        let clocks = self.inner.node.clocks();
+        if is_node && clocks.is_piggybacking() {
+            message.body.clock = Some(clocks.tick());
+        }
+
+        let message = if codec != Codec::Json && is_node {
+            let encoded = Encoded::encode(codec, &message.body.payload)?;
+            message.map_payload(|_| MaybeEncoded::Encoded(encoded))
+        } else {
+            message.map_payload(MaybeEncoded::Plain)
+        };
+        Ok((message, msg_id))
+    }
 }
 
 impl<Req, Res> MessageHandler for Client<Req, Res>
 where
     Res: DeserializeOwned + Send + 'static,
 {
-    type MessagePayload = Res;
+    // Message ids are unique per node, so several clients can be served together.
+    fn accepts(&self, envelope: &Envelope<'_>) -> bool {
+        envelope
+            .in_reply_to
+            .is_some_and(|request_id| self.inner.outgoing.is_pending(request_id))
+    }
 
-    fn handle(&self, message: Message<Self::MessagePayload>) -> Result<()> {
-        let Some(request_id) = message.body.in_reply_to else {
+    fn handle(&self, envelope: Envelope<'_>) -> Result<()> {
+        let Some(request_id) = envelope.in_reply_to else {
             bail!("message does not contain `in_reply_to`");
         };
 
+        let message = envelope.into_message::<Res>()?;
         self.complete(request_id, message.body.payload)
     }
 }
@@ -0,0 +1,441 @@
+use std::{
+    cmp::{self, Ordering},
+    collections::BTreeMap,
+    sync::{
+        atomic::{self, AtomicBool, AtomicU64},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::NodeId;
+
+#[derive(Debug, Default)]
+pub struct LamportClock {
+    time: AtomicU64,
+}
+
+impl LamportClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now(&self) -> u64 {
+        self.time.load(atomic::Ordering::Acquire)
+    }
+
+    // Local event or send.
+    pub fn tick(&self) -> u64 {
+        self.time.fetch_add(1, atomic::Ordering::AcqRel) + 1
+    }
+
+    // Receive: advances past both local and remote time.
+    pub fn observe(&self, remote: u64) -> u64 {
+        let prev = self
+            .time
+            .fetch_update(
+                atomic::Ordering::AcqRel,
+                atomic::Ordering::Acquire,
+                |local| Some(cmp::max(local, remote) + 1),
+            )
+            .expect("update never fails");
+        cmp::max(prev, remote) + 1
+    }
+}
+
+// Partially ordered: `partial_cmp` returns `None` for concurrent clocks. Missing entries
+// are zero, both when comparing and when checking for equality.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct VectorClock(BTreeMap<NodeId, u64>);
+
+impl VectorClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, node_id: &NodeId) -> u64 {
+        self.0.get(node_id).copied().unwrap_or(0)
+    }
+
+    pub fn increment(&mut self, node_id: &NodeId) -> u64 {
+        let time = self.0.entry(node_id.clone()).or_insert(0);
+        *time += 1;
+        *time
+    }
+
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (node_id, time) in &other.0 {
+            let local = self.0.entry(node_id.clone()).or_insert(0);
+            *local = cmp::max(*local, *time);
+        }
+    }
+
+    pub fn is_concurrent(&self, other: &VectorClock) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&NodeId, u64)> {
+        self.0.iter().map(|(node_id, time)| (node_id, *time))
+    }
+}
+
+impl PartialEq for VectorClock {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl Eq for VectorClock {}
+
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let mut ordering = Ordering::Equal;
+        let node_ids = self.0.keys().chain(other.0.keys());
+        for node_id in node_ids {
+            match (ordering, self.get(node_id).cmp(&other.get(node_id))) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, cmp) => ordering = cmp,
+                (ordering, cmp) if ordering != cmp => return None,
+                _ => {}
+            }
+        }
+        Some(ordering)
+    }
+}
+
+// Source of physical time for `HybridClock`, replaced by `ManualClock` in simulations.
+pub trait PhysicalClock: Send + Sync + std::fmt::Debug {
+    // Milliseconds since UNIX epoch.
+    fn now_millis(&self) -> u64;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl PhysicalClock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        since_epoch.as_millis() as u64
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    millis: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(millis: u64) -> Self {
+        Self {
+            millis: AtomicU64::new(millis),
+        }
+    }
+
+    pub fn set(&self, millis: u64) {
+        self.millis.store(millis, atomic::Ordering::Release);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.millis
+            .fetch_add(duration.as_millis() as u64, atomic::Ordering::AcqRel);
+    }
+}
+
+impl PhysicalClock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.millis.load(atomic::Ordering::Acquire)
+    }
+}
+
+// Physical time in milliseconds plus a logical counter that orders events within the
+// same millisecond (or while physical clock lags behind timestamps observed from others).
+#[derive(
+    Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct HlcTimestamp {
+    pub wall: u64,
+    pub logical: u16,
+}
+
+impl HlcTimestamp {
+    // Packs into a single integer preserving order (48 bits of wall time are enough
+    // for the next few thousand years).
+    pub fn to_u64(self) -> u64 {
+        (self.wall << 16) | u64::from(self.logical)
+    }
+
+    pub fn from_u64(packed: u64) -> Self {
+        Self {
+            wall: packed >> 16,
+            logical: packed as u16,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self.logical.checked_add(1) {
+            Some(logical) => Self {
+                wall: self.wall,
+                logical,
+            },
+            // Logical counter overflow, borrow from the future.
+            None => Self {
+                wall: self.wall + 1,
+                logical: 0,
+            },
+        }
+    }
+}
+
+// Hybrid logical clock: timestamps are close to physical time, yet strictly increase
+// and respect causality between nodes exchanging them.
+#[derive(Debug)]
+pub struct HybridClock {
+    physical: Arc<dyn PhysicalClock>,
+    last: Mutex<HlcTimestamp>,
+}
+
+impl HybridClock {
+    pub fn new(physical: Arc<dyn PhysicalClock>) -> Self {
+        Self {
+            physical,
+            last: Mutex::default(),
+        }
+    }
+
+    pub fn system() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+
+    pub fn peek(&self) -> HlcTimestamp {
+        *self.lock()
+    }
+
+    // Local event or send.
+    pub fn now(&self) -> HlcTimestamp {
+        let physical = self.physical.now_millis();
+        let mut last = self.lock();
+        *last = if physical > last.wall {
+            HlcTimestamp {
+                wall: physical,
+                logical: 0,
+            }
+        } else {
+            last.next()
+        };
+        *last
+    }
+
+    // Receive event.
+    pub fn observe(&self, remote: HlcTimestamp) -> HlcTimestamp {
+        let physical = self.physical.now_millis();
+        let mut last = self.lock();
+        *last = if physical > last.wall && physical > remote.wall {
+            HlcTimestamp {
+                wall: physical,
+                logical: 0,
+            }
+        } else {
+            cmp::max(*last, remote).next()
+        };
+        *last
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HlcTimestamp> {
+        self.last.lock().expect("lock panic")
+    }
+}
+
+// Clocks piggybacked on messages sent between nodes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LogicalTime {
+    pub lamport: u64,
+    pub vector: VectorClock,
+    #[serde(default)]
+    pub hybrid: HlcTimestamp,
+}
+
+// Logical clocks of a node. When piggybacking is enabled, `Client` attaches them to
+// every request sent to another node and `serve` merges clocks from incoming messages.
+#[derive(Debug)]
+pub struct NodeClocks {
+    node_id: NodeId,
+    lamport: LamportClock,
+    vector: Mutex<VectorClock>,
+    hybrid: HybridClock,
+    piggybacking: AtomicBool,
+}
+
+impl NodeClocks {
+    // `physical` drives the hybrid clock, e.g. `ManualClock` in simulations.
+    pub(crate) fn new(node_id: NodeId, physical: Arc<dyn PhysicalClock>) -> Self {
+        Self {
+            node_id,
+            lamport: LamportClock::new(),
+            vector: Mutex::default(),
+            hybrid: HybridClock::new(physical),
+            piggybacking: AtomicBool::new(false),
+        }
+    }
+
+    pub fn lamport(&self) -> &LamportClock {
+        &self.lamport
+    }
+
+    pub fn hybrid(&self) -> &HybridClock {
+        &self.hybrid
+    }
+
+    pub fn vector(&self) -> VectorClock {
+        self.lock_vector().clone()
+    }
+
+    pub fn now(&self) -> LogicalTime {
+        LogicalTime {
+            lamport: self.lamport.now(),
+            vector: self.vector(),
+            hybrid: self.hybrid.peek(),
+        }
+    }
+
+    // Local event or send.
+    pub fn tick(&self) -> LogicalTime {
+        let mut vector = self.lock_vector();
+        vector.increment(&self.node_id);
+        LogicalTime {
+            lamport: self.lamport.tick(),
+            vector: vector.clone(),
+            hybrid: self.hybrid.now(),
+        }
+    }
+
+    // Receive event.
+    pub fn observe(&self, remote: &LogicalTime) -> LogicalTime {
+        let mut vector = self.lock_vector();
+        vector.merge(&remote.vector);
+        vector.increment(&self.node_id);
+        LogicalTime {
+            lamport: self.lamport.observe(remote.lamport),
+            vector: vector.clone(),
+            hybrid: self.hybrid.observe(remote.hybrid),
+        }
+    }
+
+    pub fn set_piggybacking(&self, enabled: bool) {
+        self.piggybacking.store(enabled, atomic::Ordering::Relaxed);
+    }
+
+    pub fn is_piggybacking(&self) -> bool {
+        self.piggybacking.load(atomic::Ordering::Relaxed)
+    }
+
+    fn lock_vector(&self) -> MutexGuard<'_, VectorClock> {
+        self.vector.lock().expect("lock panic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(json: &str) -> VectorClock {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn vector_clock_missing_entries_are_zero() {
+        assert_eq!(vector(r#"{"a":0}"#), vector("{}"));
+        assert_eq!(
+            vector(r#"{"a":0}"#).partial_cmp(&vector("{}")),
+            Some(Ordering::Equal)
+        );
+        assert_ne!(vector(r#"{"a":1}"#), vector("{}"));
+    }
+
+    #[test]
+    fn vector_clock_order() {
+        let a = vector(r#"{"a":1,"b":2}"#);
+        let b = vector(r#"{"a":2,"b":2}"#);
+        let c = vector(r#"{"a":0,"b":3}"#);
+        assert!(a < b);
+        assert!(b > a);
+        assert!(a.is_concurrent(&c));
+        assert!(b.is_concurrent(&c));
+
+        let mut merged = b.clone();
+        merged.merge(&c);
+        assert_eq!(merged, vector(r#"{"a":2,"b":3}"#));
+        assert!(b < merged && c < merged);
+    }
+
+    fn hybrid(millis: u64) -> (Arc<ManualClock>, HybridClock) {
+        let physical = Arc::new(ManualClock::new(millis));
+        (physical.clone(), HybridClock::new(physical))
+    }
+
+    fn timestamp(wall: u64, logical: u16) -> HlcTimestamp {
+        HlcTimestamp { wall, logical }
+    }
+
+    #[test]
+    fn hybrid_clock_is_monotonic() {
+        let (physical, clock) = hybrid(100);
+        assert_eq!(clock.now(), timestamp(100, 0));
+        // Physical time standing still or going backwards only advances the counter.
+        assert_eq!(clock.now(), timestamp(100, 1));
+        physical.set(50);
+        assert_eq!(clock.now(), timestamp(100, 2));
+        physical.set(101);
+        assert_eq!(clock.now(), timestamp(101, 0));
+        assert_eq!(clock.peek(), timestamp(101, 0));
+    }
+
+    #[test]
+    fn hybrid_clock_observe() {
+        let (physical, clock) = hybrid(100);
+        clock.now();
+
+        // Timestamps from the future are followed, not the local physical clock.
+        assert_eq!(clock.observe(timestamp(200, 5)), timestamp(200, 6));
+        assert_eq!(clock.now(), timestamp(200, 7));
+        // Older ones still advance the clock.
+        assert_eq!(clock.observe(timestamp(150, 9)), timestamp(200, 8));
+        assert_eq!(clock.observe(timestamp(200, 10)), timestamp(200, 11));
+        // Physical time catching up resets the counter.
+        physical.set(300);
+        assert_eq!(clock.observe(timestamp(250, 3)), timestamp(300, 0));
+    }
+
+    #[test]
+    fn hybrid_clock_counter_overflow() {
+        let (_, clock) = hybrid(100);
+        let observed = clock.observe(timestamp(100, u16::MAX));
+        assert_eq!(observed, timestamp(101, 0));
+        assert!(clock.now() > observed);
+    }
+
+    #[test]
+    fn hlc_timestamp_packing_preserves_order() {
+        let timestamps = [timestamp(1, 0), timestamp(1, 1), timestamp(2, 0)];
+        for pair in timestamps.windows(2) {
+            assert!(pair[0].to_u64() < pair[1].to_u64());
+        }
+        for timestamp in timestamps {
+            assert_eq!(HlcTimestamp::from_u64(timestamp.to_u64()), timestamp);
+        }
+    }
+
+    #[test]
+    fn node_clocks_use_injected_physical_clock() {
+        let physical = Arc::new(ManualClock::new(42));
+        let clocks = NodeClocks::new(NodeId("n0".into()), physical.clone());
+        assert_eq!(clocks.tick().hybrid, timestamp(42, 0));
+        assert_eq!(clocks.tick().hybrid, timestamp(42, 1));
+
+        physical.advance(Duration::from_millis(8));
+        assert_eq!(clocks.hybrid().now(), timestamp(50, 0));
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    io::Write,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+// Encoding of a message payload. Maelstrom clients and services only understand JSON,
+// compact codecs are meant for node-to-node traffic. Encoded payload is base64-wrapped
+// into a JSON body so Maelstrom can still route it:
+//
+//   {"type": "encoded", "codec": "message_pack", "data": "..."}
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+// Picks codec for each outgoing request, e.g. by matching on its variant.
+pub type CodecSelector<Req> = fn(&Req) -> Codec;
+
+pub(crate) const ENCODED_TYPE: &str = "encoded";
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub(crate) enum MaybeEncoded<P> {
+    Plain(P),
+    Encoded(Encoded),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct Encoded {
+    #[serde(rename = "type")]
+    kind: String,
+    codec: Codec,
+    data: String,
+}
+
+impl Encoded {
+    pub(crate) fn encode<P: Serialize>(codec: Codec, payload: &P) -> Result<Self> {
+        let bytes = match codec {
+            Codec::Json => serde_json::to_vec(payload).context("failed to serialize into JSON")?,
+            Codec::MessagePack => {
+                rmp_serde::to_vec_named(payload).context("failed to serialize into MessagePack")?
+            }
+        };
+
+        let data = BASE64.encode(bytes);
+
+        if STATS.is_enabled() {
+            let mut json_len = CountingWriter(0);
+            serde_json::to_writer(&mut json_len, payload)
+                .context("failed to serialize into JSON")?;
+            STATS.record(json_len.0, data.len());
+        }
+
+        Ok(Self {
+            kind: ENCODED_TYPE.into(),
+            codec,
+            data,
+        })
+    }
+
+    pub(crate) fn decode<P: DeserializeOwned>(&self) -> Result<P> {
+        let bytes = BASE64
+            .decode(&self.data)
+            .context("invalid base64 in encoded payload")?;
+        match self.codec {
+            Codec::Json => serde_json::from_slice(&bytes).context("failed to deserialize JSON"),
+            Codec::MessagePack => {
+                rmp_serde::from_slice(&bytes).context("failed to deserialize MessagePack")
+            }
+        }
+    }
+}
+
+// Sizes of payloads sent with a compact codec while stats are enabled, compared to their
+// JSON size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodecStats {
+    pub messages: u64,
+    pub json_bytes: u64,
+    pub encoded_bytes: u64,
+}
+
+impl std::fmt::Display for CodecStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} encoded messages, {} bytes (as JSON: {} bytes)",
+            self.messages, self.encoded_bytes, self.json_bytes
+        )
+    }
+}
+
+pub fn stats() -> CodecStats {
+    STATS.snapshot()
+}
+
+// Measuring JSON sizes serializes every encoded payload a second time, so stats are only
+// collected once enabled, e.g. while comparing codecs.
+pub fn set_stats_enabled(enabled: bool) {
+    STATS.enabled.store(enabled, Ordering::Relaxed);
+}
+
+static STATS: Stats = Stats {
+    enabled: AtomicBool::new(false),
+    messages: AtomicU64::new(0),
+    json_bytes: AtomicU64::new(0),
+    encoded_bytes: AtomicU64::new(0),
+};
+
+struct Stats {
+    enabled: AtomicBool,
+    messages: AtomicU64,
+    json_bytes: AtomicU64,
+    encoded_bytes: AtomicU64,
+}
+
+impl Stats {
+    fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, json_bytes: usize, encoded_bytes: usize) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.json_bytes
+            .fetch_add(json_bytes as u64, Ordering::Relaxed);
+        self.encoded_bytes
+            .fetch_add(encoded_bytes as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> CodecStats {
+        CodecStats {
+            messages: self.messages.load(Ordering::Relaxed),
+            json_bytes: self.json_bytes.load(Ordering::Relaxed),
+            encoded_bytes: self.encoded_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct CountingWriter(usize);
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::message::MessagePayload;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Tagged {
+        Add { element: u64 },
+        Merge { state: Vec<u64>, seq: Option<u64> },
+        Read,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(untagged)]
+    enum Untagged {
+        Values(Vec<u64>),
+        Named { name: String, value: i64 },
+    }
+
+    fn round_trip<P>(payload: &P) -> P
+    where
+        P: Serialize + DeserializeOwned,
+    {
+        let encoded = Encoded::encode(Codec::MessagePack, payload).unwrap();
+        // Goes through JSON on the wire.
+        let wire = serde_json::to_string(&encoded).unwrap();
+        let encoded: Encoded = serde_json::from_str(&wire).unwrap();
+        assert_eq!(encoded.codec, Codec::MessagePack);
+        encoded.decode().unwrap()
+    }
+
+    #[test]
+    fn message_pack_round_trip_of_tagged_payloads() {
+        let payloads = [
+            Tagged::Add { element: u64::MAX },
+            Tagged::Merge {
+                state: vec![1, 2, 3],
+                seq: None,
+            },
+            Tagged::Read,
+        ];
+        for payload in payloads {
+            assert_eq!(round_trip(&payload), payload);
+        }
+    }
+
+    #[test]
+    fn message_pack_round_trip_of_untagged_payloads() {
+        let payloads = [
+            Untagged::Values(vec![0, 1 << 40]),
+            Untagged::Named {
+                name: "n1".into(),
+                value: -1,
+            },
+        ];
+        for payload in payloads {
+            assert_eq!(round_trip(&payload), payload);
+        }
+
+        // Requests and responses are told apart by their shape.
+        let request = MessagePayload::<Tagged, Untagged>::Request(Tagged::Read);
+        assert!(matches!(
+            round_trip(&request),
+            MessagePayload::Request(Tagged::Read)
+        ));
+        let response = MessagePayload::<Tagged, Untagged>::Response(Untagged::Values(vec![7]));
+        assert!(matches!(
+            round_trip(&response),
+            MessagePayload::Response(Untagged::Values(values)) if values == [7]
+        ));
+    }
+
+    #[test]
+    fn json_codec_round_trip() {
+        let payload = json!({ "type": "read", "key": 1 });
+        let encoded = Encoded::encode(Codec::Json, &payload).unwrap();
+        assert_eq!(encoded.decode::<serde_json::Value>().unwrap(), payload);
+    }
+}
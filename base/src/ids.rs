@@ -0,0 +1,294 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{ensure, Result};
+use serde::{Serialize, Serializer};
+
+use crate::{
+    clock::{PhysicalClock, SystemClock},
+    node::{Autoincrement, Node},
+};
+
+// Generators below need no coordination between nodes, so they keep issuing unique
+// ids under partitions. Uniqueness comes from the node's position in the cluster.
+
+// "n1-1", "n1-2", ... Never collides, but ids are strings and aren't time ordered.
+#[derive(Debug)]
+pub struct NodePrefixedIds {
+    prefix: String,
+    autoincrement: Autoincrement,
+}
+
+impl NodePrefixedIds {
+    pub fn new(node: &Node) -> Self {
+        Self {
+            prefix: node.node_id().to_string(),
+            autoincrement: Autoincrement::new(),
+        }
+    }
+
+    pub fn next(&self) -> String {
+        format!("{}-{}", self.prefix, self.autoincrement.next())
+    }
+}
+
+// 64-bit ids: 41 bits of milliseconds since 2023-01-01, 10 bits of node index and a
+// 12 bit sequence within a millisecond. When sequence is exhausted, or the physical
+// clock goes backwards, ids borrow time from the next millisecond instead of blocking.
+#[derive(Debug)]
+pub struct SnowflakeIds {
+    node_index: u64,
+    clock: Arc<dyn PhysicalClock>,
+    last: Mutex<(u64, u64)>,
+}
+
+impl SnowflakeIds {
+    const EPOCH_MILLIS: u64 = 1_672_531_200_000;
+    const NODE_BITS: u32 = 10;
+    const SEQUENCE_BITS: u32 = 12;
+
+    pub fn new(node: &Node) -> Result<Self> {
+        Self::with_clock(node, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(node: &Node, clock: Arc<dyn PhysicalClock>) -> Result<Self> {
+        let node_index = node.node_index() as u64;
+        ensure!(
+            node_index < 1 << Self::NODE_BITS,
+            "snowflake ids support at most {} nodes",
+            1 << Self::NODE_BITS
+        );
+
+        Ok(Self {
+            node_index,
+            clock,
+            last: Mutex::default(),
+        })
+    }
+
+    pub fn next(&self) -> u64 {
+        let now = self.clock.now_millis().saturating_sub(Self::EPOCH_MILLIS);
+
+        let mut last = self.last.lock().expect("lock panic");
+        let (millis, sequence) = &mut *last;
+        if now > *millis {
+            *millis = now;
+            *sequence = 0;
+        } else if *sequence + 1 < 1 << Self::SEQUENCE_BITS {
+            *sequence += 1;
+        } else {
+            *millis += 1;
+            *sequence = 0;
+        }
+
+        (*millis << (Self::NODE_BITS + Self::SEQUENCE_BITS))
+            | (self.node_index << Self::SEQUENCE_BITS)
+            | *sequence
+    }
+}
+
+// UUID version 7 (RFC 9562): 48 bits of UNIX milliseconds, 12 bit sequence within a
+// millisecond in `rand_a` and node index followed by a node-local counter in `rand_b`
+// in place of random bits, which makes ids unique without a random number generator.
+#[derive(Debug)]
+pub struct UuidV7Ids {
+    node_index: u64,
+    clock: Arc<dyn PhysicalClock>,
+    last: Mutex<(u64, u64)>,
+    counter: Autoincrement,
+}
+
+impl UuidV7Ids {
+    const NODE_BITS: u32 = 16;
+    const COUNTER_BITS: u32 = 62 - Self::NODE_BITS;
+
+    pub fn new(node: &Node) -> Result<Self> {
+        Self::with_clock(node, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(node: &Node, clock: Arc<dyn PhysicalClock>) -> Result<Self> {
+        let node_index = node.node_index() as u64;
+        ensure!(
+            node_index < 1 << Self::NODE_BITS,
+            "UUIDv7 ids support at most {} nodes",
+            1 << Self::NODE_BITS
+        );
+
+        Ok(Self {
+            node_index,
+            clock,
+            last: Mutex::default(),
+            counter: Autoincrement::new(),
+        })
+    }
+
+    pub fn next(&self) -> Uuid {
+        let now = self.clock.now_millis();
+        let (millis, sequence) = {
+            let mut last = self.last.lock().expect("lock panic");
+            let (millis, sequence) = &mut *last;
+            if now > *millis {
+                *millis = now;
+                *sequence = 0;
+            } else if *sequence < 0xfff {
+                *sequence += 1;
+            } else {
+                *millis += 1;
+                *sequence = 0;
+            }
+            (*millis, *sequence)
+        };
+
+        let counter = self.counter.next() & ((1 << Self::COUNTER_BITS) - 1);
+        let rand_b = (self.node_index << Self::COUNTER_BITS) | counter;
+
+        let uuid = (u128::from(millis & 0xffff_ffff_ffff) << 80)
+            | (0x7 << 76)
+            | (u128::from(sequence) << 64)
+            | (0b10 << 62)
+            | u128::from(rand_b);
+        Uuid(uuid)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uuid(pub u128);
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = format!("{:032x}", self.0);
+        write!(
+            f,
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+}
+
+impl Serialize for Uuid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, hash::Hash, time::Duration};
+
+    use super::*;
+    use crate::{clock::ManualClock, node::NodeId};
+
+    const NOW_MILLIS: u64 = 1_700_000_000_000;
+
+    fn node(index: usize, count: usize) -> Node {
+        let node_ids = (0..count)
+            .map(|i| serde_json::from_value::<NodeId>(format!("n{i}").into()).unwrap())
+            .collect::<Vec<_>>();
+        Node::new(node_ids[index].clone(), node_ids)
+    }
+
+    // Issues `count` ids on each of `nodes`, with physical time standing still, going
+    // backwards and jumping forward along the way.
+    fn issue<G, T>(
+        nodes: usize,
+        count: usize,
+        generator: impl Fn(&Node, Arc<ManualClock>) -> G,
+        next: impl Fn(&G) -> T,
+    ) -> Vec<Vec<T>> {
+        (0..nodes)
+            .map(|index| {
+                let clock = Arc::new(ManualClock::new(NOW_MILLIS));
+                let generator = generator(&node(index, nodes), clock.clone());
+                (0..count)
+                    .map(|i| {
+                        match i % 1000 {
+                            300 => clock.set(NOW_MILLIS - 10),
+                            600 => clock.advance(Duration::from_millis(5)),
+                            _ => {}
+                        }
+                        next(&generator)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn assert_unique_and_ordered<T: Ord + Clone + Hash>(ids: &[Vec<T>]) {
+        for node_ids in ids {
+            assert!(node_ids.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        let all = ids.iter().flatten().cloned().collect::<HashSet<_>>();
+        assert_eq!(all.len(), ids.iter().map(Vec::len).sum::<usize>());
+    }
+
+    #[test]
+    fn node_prefixed_ids_are_unique() {
+        let ids = (0..3)
+            .flat_map(|index| {
+                let ids = NodePrefixedIds::new(&node(index, 3));
+                (0..100).map(move |_| ids.next())
+            })
+            .collect::<HashSet<_>>();
+        assert_eq!(ids.len(), 300);
+    }
+
+    #[test]
+    fn snowflake_ids_are_unique_and_ordered() {
+        // More ids than fit into a millisecond's sequence.
+        let ids = issue(
+            3,
+            10_000,
+            |node, clock| SnowflakeIds::with_clock(node, clock).unwrap(),
+            SnowflakeIds::next,
+        );
+        assert_unique_and_ordered(&ids);
+    }
+
+    #[test]
+    fn snowflake_ids_follow_physical_time() {
+        let clock = Arc::new(ManualClock::new(NOW_MILLIS));
+        let ids = SnowflakeIds::with_clock(&node(1, 2), clock.clone()).unwrap();
+        let first = ids.next();
+        clock.advance(Duration::from_millis(1));
+        let second = ids.next();
+        assert_eq!(first >> 22, NOW_MILLIS - SnowflakeIds::EPOCH_MILLIS);
+        assert_eq!(second >> 22, (first >> 22) + 1);
+        assert_eq!((first >> 12) & 0x3ff, 1);
+    }
+
+    #[test]
+    fn snowflake_ids_reject_too_many_nodes() {
+        assert!(SnowflakeIds::new(&node(1024, 1025)).is_err());
+    }
+
+    #[test]
+    fn uuid_v7_ids_are_unique_and_ordered() {
+        let ids = issue(
+            3,
+            10_000,
+            |node, clock| UuidV7Ids::with_clock(node, clock).unwrap(),
+            UuidV7Ids::next,
+        );
+        assert_unique_and_ordered(&ids);
+    }
+
+    #[test]
+    fn uuid_v7_layout() {
+        let clock = Arc::new(ManualClock::new(NOW_MILLIS));
+        let uuid = UuidV7Ids::with_clock(&node(2, 3), clock).unwrap().next();
+        assert_eq!((uuid.0 >> 80) as u64, NOW_MILLIS);
+        assert_eq!((uuid.0 >> 76) & 0xf, 7);
+        assert_eq!((uuid.0 >> 62) & 0b11, 0b10);
+
+        let text = uuid.to_string();
+        assert_eq!(text.len(), 36);
+        assert_eq!(&text[14..15], "7");
+        assert_eq!(text.replace('-', ""), format!("{:032x}", uuid.0));
+    }
+}
@@ -2,11 +2,11 @@ use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    io::{recv_one_message, send_message},
+    io::recv_one_message,
     node::{Node, NodeId},
 };
 
-pub fn recv_init() -> Result<Node> {
+pub async fn recv_init() -> Result<Node> {
     let received = recv_one_message::<InitRequest>()
         .context("failed to receive 'init' message")?
         .ok_or_else(|| anyhow!("EOF during init"))?;
@@ -15,11 +15,14 @@ pub fn recv_init() -> Result<Node> {
     if received.dest != node_id {
         bail!("init message has invalid node_id");
     }
+    if !node_ids.contains(&node_id) {
+        bail!("init message node_ids don't contain node_id");
+    }
 
     let node = Node::new(node_id, node_ids);
     let (reply, _) =
         node.build_message_to(received.src, received.body.msg_id, InitResponse::InitOk);
-    send_message(&reply)?;
+    node.output().send(&reply).await?;
     Ok(node)
 }
 
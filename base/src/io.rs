@@ -1,25 +1,148 @@
-use crate::message::Message;
-use anyhow::{Context, Result};
-use futures::{stream, Stream};
+use crate::message::{Envelope, Message};
+use anyhow::{anyhow, Context, Result};
+use futures::{stream, Future, Stream};
 use serde::{de::DeserializeOwned, Serialize};
+use std::io::Write;
+use tokio::sync::{mpsc, oneshot};
 
-pub(crate) fn send_message<P: Serialize>(message: &Message<P>) -> Result<()> {
-    let json = serde_json::to_string(message).context("failed to serialize into JSON")?;
-    println!("{json}");
+// Handle to a dedicated thread that owns stdout. Every message is written as a single
+// line by that thread only, so lines never interleave. Senders wait when the queue is
+// full instead of buffering without bound.
+#[derive(Debug, Clone)]
+pub(crate) struct Output {
+    tx: mpsc::Sender<OutputCommand>,
+}
+
+#[derive(Debug)]
+enum OutputCommand {
+    Write(String),
+    Flush(oneshot::Sender<()>),
+}
+
+impl Output {
+    pub(crate) fn stdout() -> Self {
+        const QUEUE_CAPACITY: usize = 1024;
+        Self::writer(QUEUE_CAPACITY, std::io::stdout())
+    }
+
+    // Yields written lines instead of printing them, for in-process nodes.
+    pub(crate) fn channel() -> (Self, mpsc::UnboundedReceiver<String>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let out = LineSender {
+            tx,
+            buf: Vec::new(),
+        };
+        (Self::writer(1, out), rx)
+    }
+
+    fn writer(capacity: usize, out: impl Write + Send + 'static) -> Self {
+        let (tx, rx) = mpsc::channel(capacity);
+        std::thread::spawn(move || {
+            if let Err(error) = write_batches(rx, out) {
+                log::error!("output writer failed: {error:?}");
+            }
+        });
+        Self { tx }
+    }
+
+    // Serializes eagerly, so the returned future doesn't borrow `message`.
+    pub(crate) fn send<P: Serialize>(
+        &self,
+        message: &Message<P>,
+    ) -> impl Future<Output = Result<()>> + '_ {
+        let json = serde_json::to_string(message).context("failed to serialize into JSON");
+        async move {
+            self.tx
+                .send(OutputCommand::Write(json?))
+                .await
+                .map_err(|_| anyhow!("output writer has stopped"))
+        }
+    }
+
+    // Resolves once everything sent before has been written and flushed.
+    pub(crate) async fn flush(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(OutputCommand::Flush(tx))
+            .await
+            .map_err(|_| anyhow!("output writer has stopped"))?;
+        rx.await.context("output writer has stopped")
+    }
+}
+
+fn write_batches(mut rx: mpsc::Receiver<OutputCommand>, mut out: impl Write) -> Result<()> {
+    const MAX_BATCH_LEN: usize = 256;
+
+    let mut buf = Vec::with_capacity(64 * 1024);
+    let mut flushed = Vec::new();
+
+    while let Some(command) = rx.blocking_recv() {
+        let mut next = Some(command);
+        let mut batch_len = 0;
+        while let Some(command) = next.take() {
+            match command {
+                OutputCommand::Write(line) => {
+                    buf.extend_from_slice(line.as_bytes());
+                    buf.push(b'\n');
+                    batch_len += 1;
+                }
+                OutputCommand::Flush(tx) => flushed.push(tx),
+            }
+            if batch_len < MAX_BATCH_LEN {
+                next = rx.try_recv().ok();
+            }
+        }
+
+        out.write_all(&buf).context("failed to write to stdout")?;
+        out.flush().context("failed to flush stdout")?;
+        buf.clear();
+
+        for tx in flushed.drain(..) {
+            // Nobody waiting for the flush anymore, that's fine.
+            let _ = tx.send(());
+        }
+    }
     Ok(())
 }
 
-pub(crate) fn recv_messages<P: Send + DeserializeOwned + 'static>(
-) -> impl Stream<Item = Result<Message<P>>> {
-    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
-    std::thread::spawn(move || {
-        let mut buf = String::with_capacity(1024);
-        loop {
-            let item = read_message::<P>(&mut buf).transpose();
-            if tx.blocking_send(item).is_err() {
-                break;
+// Sends every written line to a channel.
+struct LineSender {
+    tx: mpsc::UnboundedSender<String>,
+    buf: Vec<u8>,
+}
+
+impl Write for LineSender {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        while let Some(end) = self.buf.iter().position(|byte| *byte == b'\n') {
+            let mut line = self.buf.drain(..=end).collect::<Vec<_>>();
+            line.pop();
+            let line = String::from_utf8(line)
+                .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+            if self.tx.send(line).is_err() {
+                return Err(std::io::ErrorKind::BrokenPipe.into());
             }
         }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Yields raw lines, parsing is left to `Envelope`, which borrows from them.
+pub(crate) fn recv_lines() -> impl Stream<Item = Result<String>> {
+    let (tx, mut rx) = mpsc::channel(1);
+    std::thread::spawn(move || loop {
+        let mut buf = String::with_capacity(1024);
+        let item = read_line(&mut buf)
+            .map(|n| (n > 0).then_some(buf))
+            .transpose();
+        let is_eof = item.is_none();
+        if tx.blocking_send(item).is_err() || is_eof {
+            break;
+        }
     });
 
     stream::poll_fn(move |ctx| rx.poll_recv(ctx).map(Option::flatten))
@@ -27,14 +150,111 @@ pub(crate) fn recv_messages<P: Send + DeserializeOwned + 'static>(
 
 pub(crate) fn recv_one_message<P: DeserializeOwned>() -> Result<Option<Message<P>>> {
     let mut buf = String::with_capacity(1024);
-    read_message(&mut buf)
+    if read_line(&mut buf)? == 0 {
+        return Ok(None);
+    }
+    Envelope::parse(&buf)?.into_message().map(Some)
+}
+
+/// Returns 0 on EOF.
+fn read_line(buf: &mut String) -> Result<usize> {
+    std::io::stdin()
+        .read_line(buf)
+        .context("failed to read from stdin")
 }
 
-fn read_message<P: DeserializeOwned>(buf: &mut String) -> Result<Option<Message<P>>> {
-    buf.clear();
-    std::io::stdin().read_line(buf)?;
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::message::MessageBody;
+
+    // Records lines of every batch, the first write waits until `gate` opens.
+    struct Recorder {
+        batches: Arc<Mutex<Vec<Vec<String>>>>,
+        gate: Option<std::sync::mpsc::Receiver<()>>,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if let Some(gate) = self.gate.take() {
+                let _ = gate.recv();
+            }
+            let batch = String::from_utf8(buf.to_vec()).expect("UTF-8");
+            let lines = batch.lines().map(str::to_owned).collect();
+            self.batches.lock().unwrap().push(lines);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn message(i: usize) -> Message<serde_json::Value> {
+        let node_id: crate::node::NodeId = serde_json::from_value("n0".into()).unwrap();
+        Message {
+            src: node_id.clone(),
+            dest: node_id,
+            body: MessageBody {
+                msg_id: None,
+                in_reply_to: None,
+                clock: None,
+                payload: serde_json::json!({ "type": "test", "i": i }),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_queued_lines_in_order_and_in_batches() {
+        const CAPACITY: usize = 4;
+
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let (open, gate) = std::sync::mpsc::channel();
+        let recorder = Recorder {
+            batches: batches.clone(),
+            gate: Some(gate),
+        };
+        let output = Output::writer(CAPACITY, recorder);
+
+        // The writer takes the first line and gets stuck writing it, the queue fills up.
+        output.send(&message(0)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        for i in 1..=CAPACITY {
+            output.send(&message(i)).await.unwrap();
+        }
+        let blocked = timeout(Duration::from_millis(50), output.send(&message(99))).await;
+        assert!(blocked.is_err(), "senders wait once the queue is full");
+
+        open.send(()).unwrap();
+        for i in CAPACITY + 1..20 {
+            output.send(&message(i)).await.unwrap();
+        }
+        output.flush().await.unwrap();
+
+        let batches = batches.lock().unwrap().clone();
+        let lines = batches.concat();
+        let expected = (0..20)
+            .map(|i| serde_json::to_string(&message(i)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, expected);
+        // What queued up while the writer was stuck goes out in one write.
+        assert!(batches[1].len() >= CAPACITY, "{batches:?}");
+    }
 
-    let message = serde_json::from_str(buf)
-        .with_context(|| format!("failed to deserialize from JSON: '{buf}'"))?;
-    Ok(message)
+    #[tokio::test]
+    async fn channel_yields_lines() {
+        let (output, mut lines) = Output::channel();
+        output.send(&message(1)).await.unwrap();
+        output.send(&message(2)).await.unwrap();
+        output.flush().await.unwrap();
+        assert_eq!(lines.recv().await, serde_json::to_string(&message(1)).ok());
+        assert_eq!(lines.recv().await, serde_json::to_string(&message(2)).ok());
+    }
 }
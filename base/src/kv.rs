@@ -0,0 +1,319 @@
+use std::{cmp, fmt::Debug, time::Duration};
+
+use anyhow::{bail, Result};
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use tokio::time::sleep;
+
+use crate::{
+    client::Client,
+    node::{Node, NodeId},
+};
+
+// Protocol of Maelstrom's key-value services (`lin-kv`, `seq-kv`, `lww-kv`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KvRequest<K, V> {
+    Read {
+        key: K,
+    },
+    Write {
+        key: K,
+        value: V,
+    },
+    Cas {
+        key: K,
+        #[serde(flatten)]
+        params: CasParams<V>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CasParams<V> {
+    pub from: V,
+    pub to: V,
+    // Missing key is created with `to` value, `from` is ignored then.
+    #[serde(default)]
+    pub create_if_not_exists: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KvResponse<V> {
+    ReadOk { value: V },
+    WriteOk,
+    CasOk,
+    Error { code: ErrorCode, text: String },
+}
+
+impl<V> KvResponse<V> {
+    pub fn error(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self::Error {
+            code,
+            text: text.into(),
+        }
+    }
+}
+
+// Maelstrom error codes.
+#[derive(Serialize_repr, Deserialize_repr, PartialEq, Eq, Debug, Clone, Copy)]
+#[repr(u32)]
+pub enum ErrorCode {
+    // Operation may or may not have taken effect.
+    Timeout = 0,
+    NodeNotFound = 1,
+    NotSupported = 10,
+    // Operation definitely didn't take effect.
+    TemporarilyUnavailable = 11,
+    MalformedRequest = 12,
+    Crash = 13,
+    Abort = 14,
+    KeyDoesNotExist = 20,
+    KeyAlreadyExists = 21,
+    PreconditionFailed = 22,
+    TxnConflict = 30,
+}
+
+// Error returned by a key-value service, can be downcast from `anyhow::Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvError {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl std::fmt::Display for KvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "kv error {:?}: {}", self.code, self.text)
+    }
+}
+
+impl std::error::Error for KvError {}
+
+#[derive(Debug)]
+pub struct KvClient<K, V> {
+    client: Client<KvRequest<K, V>, KvResponse<V>>,
+    service: NodeId,
+}
+
+impl<K, V> Clone for KvClient<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            service: self.service.clone(),
+        }
+    }
+}
+
+impl<K, V> KvClient<K, V>
+where
+    K: Serialize + DeserializeOwned + Clone + Debug,
+    V: Serialize + DeserializeOwned + Clone + Debug,
+{
+    pub fn new(node: &Node, service: NodeId) -> Self {
+        Self {
+            client: Client::new(node),
+            service,
+        }
+    }
+
+    pub fn lin_kv(node: &Node) -> Self {
+        Self::new(node, NodeId::lin_kv())
+    }
+
+    pub fn seq_kv(node: &Node) -> Self {
+        Self::new(node, NodeId::seq_kv())
+    }
+
+    pub fn lww_kv(node: &Node) -> Self {
+        Self::new(node, NodeId::lww_kv())
+    }
+
+    pub fn node(&self) -> &Node {
+        self.client.node()
+    }
+
+    pub fn service(&self) -> &NodeId {
+        &self.service
+    }
+
+    // `None` if key doesn't exist.
+    pub async fn read(&self, key: K) -> Result<Option<V>> {
+        match self.send(KvRequest::Read { key }).await? {
+            KvResponse::ReadOk { value } => Ok(Some(value)),
+            KvResponse::Error {
+                code: ErrorCode::KeyDoesNotExist,
+                ..
+            } => Ok(None),
+            response => self.unexpected(response),
+        }
+    }
+
+    pub async fn write(&self, key: K, value: V) -> Result<()> {
+        match self.send(KvRequest::Write { key, value }).await? {
+            KvResponse::WriteOk => Ok(()),
+            response => self.unexpected(response),
+        }
+    }
+
+    // `false` if current value doesn't match `from`.
+    pub async fn cas(&self, key: K, params: CasParams<V>) -> Result<bool> {
+        match self.send(KvRequest::Cas { key, params }).await? {
+            KvResponse::CasOk => Ok(true),
+            KvResponse::Error {
+                code: ErrorCode::PreconditionFailed,
+                ..
+            } => Ok(false),
+            response => self.unexpected(response),
+        }
+    }
+
+    // Applies `f` to the current value, `default` if key doesn't exist, with read + CAS,
+    // retrying with backoff until there are no concurrent updates. `f` returns `None` to
+    // leave value as is. Returns the value written, if any.
+    pub async fn update_with_cas<F>(&self, key: K, default: V, mut f: F) -> Result<Option<V>>
+    where
+        F: FnMut(&V) -> Option<V>,
+    {
+        const MIN_BACKOFF: Duration = Duration::from_millis(5);
+        const MAX_BACKOFF: Duration = Duration::from_millis(500);
+
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let current = self.read(key.clone()).await?;
+            let from = current.unwrap_or_else(|| default.clone());
+            let Some(to) = f(&from) else {
+                return Ok(None);
+            };
+
+            // Missing key is as good as `default`: a concurrent update that creates it
+            // first makes this CAS fail, as if the key was there all along.
+            let params = CasParams {
+                from,
+                to: to.clone(),
+                create_if_not_exists: true,
+            };
+            if self.cas(key.clone(), params).await? {
+                return Ok(Some(to));
+            }
+
+            // Jitter, so that contending updates don't keep colliding.
+            let delay = rand::thread_rng().gen_range(Duration::ZERO..=backoff);
+            self.node().shutdown().drainable(sleep(delay)).await?;
+            backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+        }
+    }
+
+    pub fn client(&self) -> Client<KvRequest<K, V>, KvResponse<V>> {
+        self.client.clone()
+    }
+
+    async fn send(&self, request: KvRequest<K, V>) -> Result<KvResponse<V>> {
+        self.client.send(self.service.clone(), request).await
+    }
+
+    fn unexpected<T>(&self, response: KvResponse<V>) -> Result<T> {
+        match response {
+            KvResponse::Error { code, text } => Err(KvError { code, text }.into()),
+            response => bail!("unexpected response from {}: {response:?}", self.service),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use tokio::{sync::mpsc::UnboundedReceiver, time::timeout};
+
+    use super::*;
+    use crate::{clock::SystemClock, io::Output, message::Envelope};
+
+    fn kv_client() -> (KvClient<String, u64>, UnboundedReceiver<String>) {
+        let node_id: NodeId = serde_json::from_value("n0".into()).unwrap();
+        let (output, lines) = Output::channel();
+        let node = Node::with_output(
+            node_id.clone(),
+            vec![node_id],
+            output,
+            Arc::new(SystemClock),
+        );
+        (KvClient::lin_kv(&node), lines)
+    }
+
+    // Answers requests of `kv` the way lin-kv does. Requests that arrive together are
+    // answered together, so that concurrent updates read the same value.
+    async fn serve_kv(kv: KvClient<String, u64>, mut lines: UnboundedReceiver<String>) {
+        const BATCH_WINDOW: Duration = Duration::from_millis(20);
+
+        let mut store = HashMap::new();
+        while let Some(line) = lines.recv().await {
+            let mut batch = vec![line];
+            while let Ok(Some(line)) = timeout(BATCH_WINDOW, lines.recv()).await {
+                batch.push(line);
+            }
+
+            for line in batch {
+                let message = Envelope::parse(&line)
+                    .and_then(Envelope::into_message::<KvRequest<String, u64>>)
+                    .unwrap();
+                let response = match message.body.payload {
+                    KvRequest::Read { key } => match store.get(&key) {
+                        Some(&value) => KvResponse::ReadOk { value },
+                        None => KvResponse::error(ErrorCode::KeyDoesNotExist, "no such key"),
+                    },
+                    KvRequest::Write { key, value } => {
+                        store.insert(key, value);
+                        KvResponse::WriteOk
+                    }
+                    KvRequest::Cas { key, params } => match store.get(&key) {
+                        None if !params.create_if_not_exists => {
+                            KvResponse::error(ErrorCode::KeyDoesNotExist, "no such key")
+                        }
+                        Some(value) if *value != params.from => {
+                            KvResponse::error(ErrorCode::PreconditionFailed, "value changed")
+                        }
+                        _ => {
+                            store.insert(key, params.to);
+                            KvResponse::CasOk
+                        }
+                    },
+                };
+                kv.client()
+                    .complete(message.body.msg_id.unwrap(), response)
+                    .unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_updates_are_not_lost() {
+        let (kv, lines) = kv_client();
+        tokio::spawn(serve_kv(kv.clone(), lines));
+        kv.write("x".into(), 10).await.unwrap();
+
+        let add = |delta| kv.update_with_cas("x".into(), 0, move |value| Some(value + delta));
+        let (a, b, c) = tokio::join!(add(1), add(2), add(3));
+        let written = [a, b, c].map(|result| result.unwrap().unwrap());
+        assert!(written.contains(&16), "{written:?}");
+        assert_eq!(kv.read("x".into()).await.unwrap(), Some(16));
+
+        let unchanged = kv.update_with_cas("x".into(), 0, |_| None).await.unwrap();
+        assert_eq!(unchanged, None);
+        assert_eq!(kv.read("x".into()).await.unwrap(), Some(16));
+    }
+
+    // Like adds of `g-counter-kv` that are the first ones on a node: all of them create
+    // the key, none of them may overwrite another.
+    #[tokio::test]
+    async fn concurrent_first_updates_are_not_lost() {
+        let (kv, lines) = kv_client();
+        tokio::spawn(serve_kv(kv.clone(), lines));
+
+        let add = || kv.update_with_cas("counter".into(), 0, |value| Some(value + 5));
+        let (a, b) = tokio::join!(add(), add());
+        a.unwrap();
+        b.unwrap();
+        assert_eq!(kv.read("counter".into()).await.unwrap(), Some(10));
+    }
+}
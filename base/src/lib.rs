@@ -3,7 +3,14 @@ pub(crate) mod message;
 pub(crate) mod outgoing;
 
 pub mod client;
+pub mod clock;
+pub mod codec;
+pub mod ids;
 pub mod init;
+pub mod kv;
 pub mod node;
 pub mod serve;
+pub mod shutdown;
+pub mod sim;
+pub mod sync;
 pub mod utils;
@@ -1,7 +1,13 @@
-use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::{borrow::Cow, fmt::Debug};
 
-use crate::node::NodeId;
+use crate::{
+    clock::LogicalTime,
+    codec::{Encoded, ENCODED_TYPE},
+    node::NodeId,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message<P> {
@@ -17,6 +23,8 @@ pub struct MessageId(pub(crate) u64);
 pub struct MessageBody<P> {
     pub msg_id: Option<MessageId>,
     pub in_reply_to: Option<MessageId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<LogicalTime>,
 
     #[serde(flatten)]
     pub payload: P,
@@ -29,24 +37,108 @@ pub enum MessagePayload<Req, Res> {
     Response(Res),
 }
 
-impl<P> Message<P> {
-    pub(crate) fn replace_payload<U>(self, payload: U) -> (P, Message<U>) {
-        let prev = self.body.payload;
-        let new = Message {
+// Message with only the routing fields parsed. Body is kept as raw JSON until a
+// handler that accepts the message parses it, exactly once, into its own payload type.
+#[derive(Debug)]
+pub struct Envelope<'a> {
+    pub src: NodeId,
+    pub dest: NodeId,
+    pub msg_id: Option<MessageId>,
+    pub in_reply_to: Option<MessageId>,
+    pub clock: Option<LogicalTime>,
+    pub kind: Cow<'a, str>,
+    body: &'a RawValue,
+}
+
+#[derive(Deserialize)]
+struct RawMessage<'a> {
+    src: NodeId,
+    dest: NodeId,
+    #[serde(borrow)]
+    body: &'a RawValue,
+}
+
+#[derive(Deserialize)]
+struct Header<'a> {
+    msg_id: Option<MessageId>,
+    in_reply_to: Option<MessageId>,
+    #[serde(default)]
+    clock: Option<LogicalTime>,
+    #[serde(rename = "type", borrow)]
+    kind: Cow<'a, str>,
+}
+
+impl<'a> Envelope<'a> {
+    pub(crate) fn parse(line: &'a str) -> Result<Self> {
+        let RawMessage { src, dest, body } = serde_json::from_str(line)
+            .with_context(|| format!("failed to deserialize from JSON: '{line}'"))?;
+        let Header {
+            msg_id,
+            in_reply_to,
+            clock,
+            kind,
+        } = serde_json::from_str(body.get())
+            .with_context(|| format!("invalid message body: '{}'", body.get()))?;
+
+        Ok(Self {
+            src,
+            dest,
+            msg_id,
+            in_reply_to,
+            clock,
+            kind,
+            body,
+        })
+    }
+
+    pub fn into_message<P: DeserializeOwned>(self) -> Result<Message<P>> {
+        let payload = if self.kind == ENCODED_TYPE {
+            self.decode_payload()?
+        } else {
+            self.parse_payload()?
+        };
+
+        Ok(Message {
             src: self.src,
             dest: self.dest,
             body: MessageBody {
-                msg_id: self.body.msg_id,
-                in_reply_to: self.body.in_reply_to,
+                msg_id: self.msg_id,
+                in_reply_to: self.in_reply_to,
+                clock: self.clock,
                 payload,
             },
-        };
+        })
+    }
+
+    fn parse_payload<P: DeserializeOwned>(&self) -> Result<P> {
+        serde_json::from_str(self.body.get()).with_context(|| {
+            format!(
+                "failed to deserialize '{}' payload from JSON: '{}'",
+                self.kind,
+                self.body.get()
+            )
+        })
+    }
 
-        (prev, new)
+    fn decode_payload<P: DeserializeOwned>(&self) -> Result<P> {
+        let encoded: Encoded = self.parse_payload()?;
+        encoded
+            .decode()
+            .with_context(|| format!("failed to decode payload from {}", self.src))
     }
+}
 
-    pub(crate) fn with_payload<U>(self, payload: U) -> Message<U> {
-        let (_, msg) = self.replace_payload(payload);
-        msg
+impl<P> Message<P> {
+    pub(crate) fn map_payload<U>(self, f: impl FnOnce(P) -> U) -> Message<U> {
+        Message {
+            src: self.src,
+            dest: self.dest,
+            body: MessageBody {
+                msg_id: self.body.msg_id,
+                in_reply_to: self.body.in_reply_to,
+                clock: self.body.clock,
+                payload: f(self.body.payload),
+            },
+        }
     }
 }
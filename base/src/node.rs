@@ -4,7 +4,12 @@ use std::sync::{
     Arc,
 };
 
-use crate::message::{Message, MessageBody, MessageId};
+use crate::{
+    clock::{NodeClocks, PhysicalClock, SystemClock},
+    io::Output,
+    message::{Message, MessageBody, MessageId},
+    shutdown::Shutdown,
+};
 
 // Builds messages (assigns correct src/dst node ids, issues messages ids)
 #[derive(Debug, Clone)]
@@ -17,15 +22,30 @@ struct NodeInner {
     node_id: NodeId,
     node_ids: Vec<NodeId>,
     autoincrement: Autoincrement,
+    shutdown: Shutdown,
+    output: Output,
+    clocks: NodeClocks,
 }
 
 impl Node {
     pub(crate) fn new(node_id: NodeId, node_ids: Vec<NodeId>) -> Self {
+        Self::with_output(node_id, node_ids, Output::stdout(), Arc::new(SystemClock))
+    }
+
+    pub(crate) fn with_output(
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
+        output: Output,
+        physical: Arc<dyn PhysicalClock>,
+    ) -> Self {
         Self {
             inner: Arc::new(NodeInner {
+                clocks: NodeClocks::new(node_id.clone(), physical),
                 node_id,
                 node_ids,
                 autoincrement: Autoincrement::new(),
+                shutdown: Shutdown::new(),
+                output,
             }),
         }
     }
@@ -38,6 +58,27 @@ impl Node {
         &self.inner.node_ids
     }
 
+    // Position of this node in `node_ids`.
+    pub fn node_index(&self) -> usize {
+        self.inner
+            .node_ids
+            .iter()
+            .position(|node_id| *node_id == self.inner.node_id)
+            .expect("node_ids contain this node")
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.inner.shutdown
+    }
+
+    pub fn clocks(&self) -> &NodeClocks {
+        &self.inner.clocks
+    }
+
+    pub(crate) fn output(&self) -> &Output {
+        &self.inner.output
+    }
+
     pub(crate) fn build_message_to<P>(
         &self,
         dest: NodeId,
@@ -51,6 +92,7 @@ impl Node {
             body: MessageBody {
                 msg_id: Some(msg_id),
                 in_reply_to,
+                clock: None,
                 payload,
             },
         };
@@ -58,8 +100,8 @@ impl Node {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
-pub struct NodeId(String);
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct NodeId(pub(crate) String);
 
 impl std::fmt::Display for NodeId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -71,21 +113,43 @@ impl NodeId {
     pub fn lin_kv() -> Self {
         Self("lin-kv".into())
     }
+
+    pub fn seq_kv() -> Self {
+        Self("seq-kv".into())
+    }
+
+    pub fn lww_kv() -> Self {
+        Self("lww-kv".into())
+    }
+
+    pub fn lin_tso() -> Self {
+        Self("lin-tso".into())
+    }
 }
 
 #[derive(Debug)]
-struct Autoincrement {
+pub struct Autoincrement {
     next: AtomicU64,
 }
 
+impl Default for Autoincrement {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Autoincrement {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             next: AtomicU64::new(1),
         }
     }
 
+    pub fn next(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+
     fn new_message_id(&self) -> MessageId {
-        MessageId(self.next.fetch_add(1, Ordering::Relaxed))
+        MessageId(self.next())
     }
 }
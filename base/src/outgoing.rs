@@ -48,6 +48,13 @@ impl<R> Outgoing<R> {
         PendingResponse { rx, ttl: self.ttl }
     }
 
+    pub(crate) fn is_pending(&self, request_id: MessageId) -> bool {
+        self.queue
+            .lock()
+            .expect("lock panic")
+            .contains_key(&request_id)
+    }
+
     pub(crate) fn complete(&self, request_id: MessageId, response: R) -> Result<()> {
         let Some(item) = self.queue.lock().expect("lock panic").remove(&request_id) else {
             bail!("pending request not found {request_id:?}");
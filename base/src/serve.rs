@@ -1,6 +1,6 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use futures::{
     future::{ready, BoxFuture},
     FutureExt, StreamExt,
@@ -8,53 +8,119 @@ use futures::{
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
-    io::{recv_messages, send_message},
-    message::Message,
+    codec,
+    io::recv_lines,
+    message::{Envelope, Message},
     node::{Node, NodeId},
-    utils::async_spawn,
+    utils::drain_spawn,
 };
 
 pub trait MessageHandler {
-    type MessagePayload: DeserializeOwned + Send + 'static;
+    // Routing decision, made before the payload is parsed.
+    fn accepts(&self, envelope: &Envelope<'_>) -> bool;
 
-    fn handle(&self, message: Message<Self::MessagePayload>) -> Result<()>;
+    fn handle(&self, envelope: Envelope<'_>) -> Result<()>;
+
+    // Called right before `serve` returns, once all tasks have stopped or the grace period
+    // is over.
+    fn on_shutdown(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
-pub async fn serve<H: MessageHandler>(handler: H) -> Result<()> {
-    let mut incoming = recv_messages::<H::MessagePayload>();
-    while let Some(message) = incoming.next().await.transpose()? {
-        if let Err(error) = handler.handle(message) {
+pub async fn serve<H: MessageHandler>(node: &Node, handler: H) -> Result<()> {
+    let result = recv_and_handle(node, &handler).await;
+    shutdown(node, &handler).await?;
+    result
+}
+
+async fn recv_and_handle<H: MessageHandler>(node: &Node, handler: &H) -> Result<()> {
+    let mut incoming = recv_lines();
+    while let Some(line) = incoming.next().await.transpose()? {
+        let envelope = Envelope::parse(&line)?;
+        if let Err(error) = dispatch(node, handler, envelope) {
             log::error!("error processing message: {error:?}");
         }
     }
     Ok(())
 }
 
-macro_rules! impl_tuple_handler {
-    ($payload:ident, $($idx:tt => $t:ident),+) => {
-        #[derive(serde::Deserialize)]
-        #[serde(untagged)]
-        pub enum $payload<$($t),+>{
-            $( $t($t) ),+
+// Hands an incoming message to the handler that accepts it.
+pub(crate) fn dispatch<H: MessageHandler>(
+    node: &Node,
+    handler: &H,
+    envelope: Envelope<'_>,
+) -> Result<()> {
+    if let Some(clock) = &envelope.clock {
+        if node.clocks().is_piggybacking() {
+            node.clocks().observe(clock);
         }
+    }
+
+    if handler.accepts(&envelope) {
+        handler.handle(envelope)
+    } else {
+        Err(unexpected(&envelope))
+    }
+}
+
+async fn shutdown<H: MessageHandler>(node: &Node, handler: &H) -> Result<()> {
+    const GRACE_PERIOD: Duration = Duration::from_secs(1);
 
+    log::info!("shutting down");
+    node.shutdown().trigger();
+    if !node.shutdown().wait_for_tasks(GRACE_PERIOD).await {
+        log::warn!("some tasks are still running after {GRACE_PERIOD:?}, cancelling them");
+        node.shutdown().abort();
+    }
+
+    if let Err(error) = handler.on_shutdown() {
+        log::error!("shutdown hook failed: {error:?}");
+    }
+
+    let codec_stats = codec::stats();
+    if codec_stats.messages > 0 {
+        log::info!("codec stats: {codec_stats}");
+    }
+    node.output().flush().await
+}
+
+fn unexpected(envelope: &Envelope<'_>) -> anyhow::Error {
+    anyhow!(
+        "no handler for '{}' message from {}",
+        envelope.kind,
+        envelope.src
+    )
+}
+
+macro_rules! impl_tuple_handler {
+    ($($idx:tt => $t:ident),+) => {
         impl<$($t),+> MessageHandler for ($($t),+)
         where $($t: MessageHandler),+
         {
-            type MessagePayload = $payload<$($t::MessagePayload),+>;
+            fn accepts(&self, envelope: &Envelope<'_>) -> bool {
+                $(self.$idx.accepts(envelope))||+
+            }
 
-            fn handle(&self, message: Message<Self::MessagePayload>) -> Result<()> {
-                let (payload, message) = message.replace_payload(());
-                match payload {
-                    $($payload::$t(p) => self.$idx.handle(message.with_payload(p))),+
-                }
+            fn handle(&self, envelope: Envelope<'_>) -> Result<()> {
+                $(
+                    if self.$idx.accepts(&envelope) {
+                        return self.$idx.handle(envelope);
+                    }
+                )+
+                Err(unexpected(&envelope))
+            }
+
+            fn on_shutdown(&self) -> Result<()> {
+                let results = [$(self.$idx.on_shutdown()),+];
+                results.into_iter().collect()
             }
         }
     };
 }
 
-impl_tuple_handler!(TuplePayload2, 0 => T1, 1 => T2);
-impl_tuple_handler!(TuplePayload3, 0 => T1, 1 => T2, 2 => T3);
+impl_tuple_handler!(0 => T1, 1 => T2);
+impl_tuple_handler!(0 => T1, 1 => T2, 2 => T3);
 
 pub trait RequestHandler: Send + Sync + 'static {
     type Request: DeserializeOwned + Send + 'static;
@@ -65,6 +131,11 @@ pub trait RequestHandler: Send + Sync + 'static {
         from: NodeId,
         request: Self::Request,
     ) -> BoxFuture<'a, Result<Option<Self::Response>>>;
+
+    // Last chance to persist state before the process exits.
+    fn on_shutdown(self: &Arc<Self>) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -105,10 +176,15 @@ where
             .handle(message.src.clone(), message.body.payload)
             .await?
         {
-            let (reply, _) = self
-                .node
-                .build_message_to(message.src, message.body.msg_id, response);
-            send_message(&reply)?;
+            let (mut reply, _) =
+                self.node
+                    .build_message_to(message.src, message.body.msg_id, response);
+            // Replies carry clocks as well, so that callers learn what happened here.
+            let clocks = self.node.clocks();
+            if clocks.is_piggybacking() && self.node.node_ids().contains(&reply.dest) {
+                reply.body.clock = Some(clocks.tick());
+            }
+            self.node.output().send(&reply).await?;
         }
         Ok(())
     }
@@ -118,13 +194,23 @@ impl<H> MessageHandler for Service<H>
 where
     H: RequestHandler + Send + ?Sized,
 {
-    type MessagePayload = H::Request;
+    fn accepts(&self, envelope: &Envelope<'_>) -> bool {
+        envelope.in_reply_to.is_none()
+    }
 
-    fn handle(&self, message: Message<Self::MessagePayload>) -> Result<()> {
+    fn handle(&self, envelope: Envelope<'_>) -> Result<()> {
+        let message = envelope.into_message()?;
         let service = self.clone();
-        async_spawn(async move { service.handle_request(message).await });
+        // Requests that made it in get their replies, even once shutdown is triggered.
+        drain_spawn(self.node.shutdown(), async move {
+            service.handle_request(message).await
+        });
         Ok(())
     }
+
+    fn on_shutdown(&self) -> Result<()> {
+        self.request_handler.on_shutdown()
+    }
 }
 
 impl<Req, Res> RequestHandler for dyn Fn(Req) -> Result<Res> + Send + Sync
@@ -158,3 +244,50 @@ where
         node,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{client::Client, sim::Network};
+
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Request {
+        Ping,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Response {
+        PingOk,
+    }
+
+    #[tokio::test]
+    async fn clocks_are_piggybacked_on_requests_and_replies() {
+        let network = Network::new();
+        let nodes = ["n0", "n1"].map(|id| network.node(id, &["n0", "n1"]));
+        let client = Client::<Request, Response>::new(&nodes[0]);
+        network.serve(&nodes[0], client.clone());
+        let service = make_service(nodes[1].clone(), |Request::Ping| Ok(Response::PingOk));
+        network.serve(&nodes[1], service);
+
+        for node in &nodes {
+            node.clocks().set_piggybacking(true);
+        }
+        // Events the other node learns about only through the reply.
+        for _ in 0..10 {
+            nodes[1].clocks().tick();
+        }
+
+        client
+            .send(nodes[1].node_id().clone(), Request::Ping)
+            .await
+            .unwrap();
+        let (caller, callee) = (nodes[0].clocks().now(), nodes[1].clocks().now());
+        assert!(caller.lamport > callee.lamport);
+        assert!(caller.vector > callee.vector);
+        assert!(caller.hybrid > callee.hybrid);
+    }
+}
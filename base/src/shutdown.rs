@@ -0,0 +1,117 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::Future;
+use tokio::{sync::watch, time::timeout};
+
+// Shared by everything running on behalf of a node. Triggered once stdin is closed:
+// periodic and background tasks stop, while requests being handled drain until they are
+// done or the grace period is over. Then the shutdown is aborted and whatever is still
+// running fails with `Cancelled`.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    inner: Arc<ShutdownInner>,
+}
+
+#[derive(Debug)]
+struct ShutdownInner {
+    triggered: watch::Sender<bool>,
+    aborted: watch::Sender<bool>,
+    running_tasks: watch::Sender<usize>,
+}
+
+impl Shutdown {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Arc::new(ShutdownInner {
+                triggered: watch::Sender::new(false),
+                aborted: watch::Sender::new(false),
+                running_tasks: watch::Sender::new(0),
+            }),
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.inner.triggered.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.inner.triggered.borrow()
+    }
+
+    pub async fn triggered(&self) {
+        wait_for(&self.inner.triggered).await;
+    }
+
+    // Grace period is over, draining tasks get cancelled too.
+    pub(crate) fn abort(&self) {
+        self.trigger();
+        self.inner.aborted.send_replace(true);
+    }
+
+    pub async fn aborted(&self) {
+        wait_for(&self.inner.aborted).await;
+    }
+
+    /// Runs `future` to completion unless shutdown is triggered first. Future that is
+    /// already complete wins over shutdown, so handled requests still get their replies.
+    pub async fn cancellable<F: Future>(&self, future: F) -> Result<F::Output, Cancelled> {
+        tokio::select! {
+            biased;
+            output = future => Ok(output),
+            _ = self.triggered() => Err(Cancelled),
+        }
+    }
+
+    /// Like `cancellable`, but keeps running after shutdown is triggered, until it's
+    /// aborted. For requests being handled and whatever they wait for.
+    pub async fn drainable<F: Future>(&self, future: F) -> Result<F::Output, Cancelled> {
+        tokio::select! {
+            biased;
+            output = future => Ok(output),
+            _ = self.aborted() => Err(Cancelled),
+        }
+    }
+
+    pub(crate) fn track_task(&self) -> TaskGuard {
+        self.inner.running_tasks.send_modify(|count| *count += 1);
+        TaskGuard {
+            shutdown: self.clone(),
+        }
+    }
+
+    /// Returns `false` if some tasks are still running after `grace` period.
+    pub(crate) async fn wait_for_tasks(&self, grace: Duration) -> bool {
+        let mut rx = self.inner.running_tasks.subscribe();
+        let all_stopped = timeout(grace, rx.wait_for(|count| *count == 0)).await;
+        all_stopped.is_ok()
+    }
+}
+
+pub(crate) struct TaskGuard {
+    shutdown: Shutdown,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.shutdown
+            .inner
+            .running_tasks
+            .send_modify(|count| *count -= 1);
+    }
+}
+
+// Sender is owned by `Shutdown`, it can't be dropped while we're waiting.
+async fn wait_for(flag: &watch::Sender<bool>) {
+    let _ = flag.subscribe().wait_for(|flag| *flag).await;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("cancelled: node is shutting down")
+    }
+}
+
+impl std::error::Error for Cancelled {}
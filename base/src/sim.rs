@@ -0,0 +1,166 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::Result;
+use futures::{future::ready, future::BoxFuture, FutureExt};
+use serde_json::Value;
+
+use crate::{
+    clock::{PhysicalClock, SystemClock},
+    io::Output,
+    kv::{ErrorCode, KvRequest, KvResponse},
+    message::Envelope,
+    node::{Node, NodeId},
+    serve::{dispatch, MessageHandler, RequestHandler, Service},
+};
+
+type Handler = Arc<dyn Fn(Envelope<'_>) -> Result<()> + Send + Sync>;
+
+// In-process cluster for tests and simulations. Nodes exchange messages through channels
+// instead of stdin/stdout, their physical clocks can be injected and links between them
+// cut. Messages sent over a link arrive in order, messages to nodes that nobody serves
+// (or over a cut link) are dropped.
+#[derive(Clone, Default)]
+pub struct Network {
+    inner: Arc<Mutex<NetworkInner>>,
+}
+
+#[derive(Default)]
+struct NetworkInner {
+    handlers: HashMap<NodeId, Handler>,
+    // (from, to)
+    cut: HashSet<(NodeId, NodeId)>,
+}
+
+pub fn node_id(id: &str) -> NodeId {
+    NodeId(id.into())
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn node(&self, node_id: &str, node_ids: &[&str]) -> Node {
+        self.node_with_clock(node_id, node_ids, Arc::new(SystemClock))
+    }
+
+    // Must be called within a Tokio runtime, messages are delivered by a spawned task.
+    pub fn node_with_clock(
+        &self,
+        id: &str,
+        node_ids: &[&str],
+        physical: Arc<dyn PhysicalClock>,
+    ) -> Node {
+        let (output, mut lines) = Output::channel();
+        let node_ids = node_ids.iter().map(|id| node_id(id)).collect();
+        let node = Node::with_output(node_id(id), node_ids, output, physical);
+
+        let network = self.clone();
+        tokio::spawn(async move {
+            while let Some(line) = lines.recv().await {
+                if let Err(error) = network.deliver(&line) {
+                    log::error!("error processing message: {error:?}");
+                }
+            }
+        });
+        node
+    }
+
+    // Handles messages sent to `node`, like `serve` does.
+    pub fn serve<H>(&self, node: &Node, handler: H)
+    where
+        H: MessageHandler + Send + Sync + 'static,
+    {
+        let dispatcher = node.clone();
+        let handler: Handler = Arc::new(move |envelope| dispatch(&dispatcher, &handler, envelope));
+        self.lock().handlers.insert(node.node_id().clone(), handler);
+    }
+
+    // Serves an in-memory `KvService` as `service`, e.g. "lin-kv".
+    pub fn serve_kv(&self, service: &str) {
+        let node = self.node(service, &[]);
+        let kv = Arc::new(KvService::default());
+        self.serve(&node, Service::new(&node, kv));
+    }
+
+    pub fn cut(&self, from: &NodeId, to: &NodeId) {
+        self.lock().cut.insert((from.clone(), to.clone()));
+    }
+
+    pub fn heal(&self) {
+        self.lock().cut.clear();
+    }
+
+    fn deliver(&self, line: &str) -> Result<()> {
+        let envelope = Envelope::parse(line)?;
+        let handler = {
+            let inner = self.lock();
+            let link = (envelope.src.clone(), envelope.dest.clone());
+            if inner.cut.contains(&link) {
+                return Ok(());
+            }
+            match inner.handlers.get(&envelope.dest) {
+                Some(handler) => handler.clone(),
+                None => return Ok(()),
+            }
+        };
+        handler(envelope)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, NetworkInner> {
+        self.inner.lock().expect("lock panic")
+    }
+}
+
+// Linearizable key-value store speaking the protocol of Maelstrom's KV services.
+#[derive(Debug, Default)]
+pub struct KvService {
+    // Keys are kept as JSON, so that both strings and integers work.
+    store: Mutex<HashMap<String, Value>>,
+}
+
+impl KvService {
+    fn execute(&self, request: KvRequest<Value, Value>) -> KvResponse<Value> {
+        let mut store = self.store.lock().expect("lock panic");
+        match request {
+            KvRequest::Read { key } => match store.get(&key.to_string()) {
+                Some(value) => KvResponse::ReadOk {
+                    value: value.clone(),
+                },
+                None => KvResponse::error(ErrorCode::KeyDoesNotExist, "key does not exist"),
+            },
+            KvRequest::Write { key, value } => {
+                store.insert(key.to_string(), value);
+                KvResponse::WriteOk
+            }
+            KvRequest::Cas { key, params } => match store.get(&key.to_string()) {
+                None if !params.create_if_not_exists => {
+                    KvResponse::error(ErrorCode::KeyDoesNotExist, "key does not exist")
+                }
+                Some(value) if *value != params.from => {
+                    KvResponse::error(ErrorCode::PreconditionFailed, "value has changed")
+                }
+                _ => {
+                    store.insert(key.to_string(), params.to);
+                    KvResponse::CasOk
+                }
+            },
+        }
+    }
+}
+
+impl RequestHandler for KvService {
+    type Request = KvRequest<Value, Value>;
+    type Response = KvResponse<Value>;
+
+    fn handle<'a>(
+        self: &'a Arc<Self>,
+        _from: NodeId,
+        request: Self::Request,
+    ) -> BoxFuture<'a, Result<Option<Self::Response>>> {
+        ready(Ok(Some(self.execute(request)))).boxed()
+    }
+}
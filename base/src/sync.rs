@@ -0,0 +1,236 @@
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+// Set reconciliation with invertible Bloom lookup tables (IBLT).
+//
+// Each node encodes its set into a table of a size agreed upon, one sends its table to
+// the other, which subtracts its own. Common keys cancel out, so what is left encodes
+// only the symmetric difference and decodes as long as the table has ~1.5 cells per
+// differing key. Since the difference isn't known upfront, callers start small and
+// retry with the next of `Iblt::SIZES` until decoding succeeds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Iblt {
+    cells: Vec<Cell>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Cell {
+    count: i64,
+    key_sum: u64,
+    hash_sum: u64,
+}
+
+// Keys that are only in one of the two sets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Difference {
+    // In the table that was subtracted from.
+    pub local: Vec<u64>,
+    // In the table that was subtracted.
+    pub remote: Vec<u64>,
+}
+
+const HASHES: usize = 3;
+const SEEDS: [u64; HASHES] = [
+    0x51_7cc1_b727_220a,
+    0x2545_f491_4f6c_dd1d,
+    0x9e6c_63d0_676a_9a99,
+];
+const CHECKSUM_SEED: u64 = 0x6a09_e667_f3bc_c908;
+
+impl Iblt {
+    // Table sizes to try in turn, each fits ~4x more differing keys than the previous.
+    pub const SIZES: [usize; 4] = [60, 240, 960, 3840];
+
+    // `cells` is rounded up to a multiple of the number of hash functions.
+    pub fn new(cells: usize) -> Self {
+        let cells = cells.max(1).div_ceil(HASHES) * HASHES;
+        Self {
+            cells: vec![Cell::default(); cells],
+        }
+    }
+
+    pub fn from_keys(cells: usize, keys: impl IntoIterator<Item = u64>) -> Self {
+        let mut iblt = Self::new(cells);
+        for key in keys {
+            iblt.insert(key);
+        }
+        iblt
+    }
+
+    pub fn size(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn insert(&mut self, key: u64) {
+        self.toggle(key, 1);
+    }
+
+    pub fn remove(&mut self, key: u64) {
+        self.toggle(key, -1);
+    }
+
+    // Leaves only the keys that differ between the two tables.
+    pub fn subtract(&mut self, other: &Iblt) -> Result<()> {
+        ensure!(
+            self.size() == other.size(),
+            "IBLT sizes differ: {} and {}",
+            self.size(),
+            other.size()
+        );
+        for (cell, other) in self.cells.iter_mut().zip(&other.cells) {
+            cell.count -= other.count;
+            cell.key_sum ^= other.key_sum;
+            cell.hash_sum ^= other.hash_sum;
+        }
+        Ok(())
+    }
+
+    // `None` if the table is too small for the keys it holds.
+    pub fn decode(mut self) -> Option<Difference> {
+        let mut difference = Difference::default();
+        let mut pure = (0..self.size())
+            .filter(|index| self.cells[*index].is_pure())
+            .collect::<Vec<_>>();
+
+        while let Some(index) = pure.pop() {
+            let cell = self.cells[index];
+            // Peeling other cells may have changed this one since it was queued.
+            if !cell.is_pure() {
+                continue;
+            }
+
+            let key = cell.key_sum;
+            if cell.count > 0 {
+                difference.local.push(key);
+            } else {
+                difference.remote.push(key);
+            }
+            for index in self.indices(key) {
+                self.cells[index].toggle(key, -cell.count);
+                if self.cells[index].is_pure() {
+                    pure.push(index);
+                }
+            }
+        }
+
+        self.cells.iter().all(Cell::is_empty).then_some(difference)
+    }
+
+    // Responder side of a round: subtracts the peer's table from one built over `keys`.
+    // `Difference::local` is then what the peer is missing, `remote` what we are.
+    pub fn difference(&self, keys: impl IntoIterator<Item = u64>) -> Result<Option<Difference>> {
+        let mut local = Self::from_keys(self.size(), keys);
+        local.subtract(self)?;
+        Ok(local.decode())
+    }
+
+    fn toggle(&mut self, key: u64, count: i64) {
+        for index in self.indices(key) {
+            self.cells[index].toggle(key, count);
+        }
+    }
+
+    // One cell in each of `HASHES` equal partitions, so a key never hits a cell twice.
+    fn indices(&self, key: u64) -> [usize; HASHES] {
+        let partition = self.size() / HASHES;
+        std::array::from_fn(|i| i * partition + (mix(key ^ SEEDS[i]) % partition as u64) as usize)
+    }
+}
+
+impl Cell {
+    fn toggle(&mut self, key: u64, count: i64) {
+        self.count += count;
+        self.key_sum ^= key;
+        self.hash_sum ^= mix(key ^ CHECKSUM_SEED);
+    }
+
+    // Holds exactly one key (from either side).
+    fn is_pure(&self) -> bool {
+        self.count.abs() == 1 && self.hash_sum == mix(self.key_sum ^ CHECKSUM_SEED)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count == 0 && self.key_sum == 0 && self.hash_sum == 0
+    }
+}
+
+// splitmix64 finalizer: spreads nearby values over the whole range.
+pub fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut keys: Vec<u64>) -> Vec<u64> {
+        keys.sort_unstable();
+        keys
+    }
+
+    // Common keys and keys that only one of the sides has.
+    fn sets(common: u64, only_local: u64, only_remote: u64) -> (Vec<u64>, Vec<u64>) {
+        let local = (0..common + only_local).collect();
+        let remote = (0..common)
+            .chain(1_000_000..1_000_000 + only_remote)
+            .collect();
+        (local, remote)
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        for (only_local, only_remote) in [(0, 0), (1, 0), (0, 1), (20, 20), (100, 50)] {
+            let (local, remote) = sets(10_000, only_local, only_remote);
+            let size = Iblt::SIZES[2];
+            let mut iblt = Iblt::from_keys(size, local.iter().copied());
+            iblt.subtract(&Iblt::from_keys(size, remote.iter().copied()))
+                .unwrap();
+
+            let difference = iblt.decode().expect("table is large enough");
+            let expected_local = (10_000..10_000 + only_local).collect::<Vec<_>>();
+            let expected_remote = (1_000_000..1_000_000 + only_remote).collect::<Vec<_>>();
+            assert_eq!(sorted(difference.local), expected_local);
+            assert_eq!(sorted(difference.remote), expected_remote);
+        }
+    }
+
+    #[test]
+    fn difference_is_from_responder_side() {
+        let (local, remote) = sets(500, 3, 5);
+        let peer = Iblt::from_keys(Iblt::SIZES[0], remote);
+        let difference = peer.difference(local).unwrap().unwrap();
+        assert_eq!(sorted(difference.local), vec![500, 501, 502]);
+        assert_eq!(difference.remote.len(), 5);
+    }
+
+    #[test]
+    fn decode_fails_on_undersized_tables() {
+        let (local, remote) = sets(100, 200, 200);
+        let peer = Iblt::from_keys(Iblt::SIZES[0], remote.iter().copied());
+        assert_eq!(peer.difference(local.iter().copied()).unwrap(), None);
+
+        // The next size up is enough.
+        let peer = Iblt::from_keys(Iblt::SIZES[2], remote);
+        assert!(peer.difference(local).unwrap().is_some());
+    }
+
+    #[test]
+    fn removed_keys_cancel_out() {
+        let mut iblt = Iblt::from_keys(60, [1, 2, 3]);
+        for key in [1, 2, 3] {
+            iblt.remove(key);
+        }
+        assert_eq!(iblt, Iblt::new(60));
+        assert_eq!(iblt.decode(), Some(Difference::default()));
+    }
+
+    #[test]
+    fn sizes_must_match() {
+        let mut iblt = Iblt::new(60);
+        assert!(iblt.subtract(&Iblt::new(240)).is_err());
+        assert_eq!(Iblt::new(61).size(), 63);
+    }
+}
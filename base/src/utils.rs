@@ -4,6 +4,8 @@ use anyhow::{Context, Result};
 use futures::Future;
 use tokio::time::{interval, MissedTickBehavior};
 
+use crate::shutdown::{Cancelled, Shutdown};
+
 pub fn init_log() -> Result<()> {
     stderrlog::new()
         .verbosity(stderrlog::LogLevelNum::Trace)
@@ -11,23 +13,49 @@ pub fn init_log() -> Result<()> {
         .context("failed to init log")
 }
 
-pub fn async_spawn<F>(future: F)
+// Task gets dropped at its next await point once `shutdown` is triggered.
+pub fn async_spawn<F>(shutdown: &Shutdown, future: F)
 where
     F: Future<Output = Result<()>> + Send + 'static,
 {
+    let guard = shutdown.track_task();
+    let shutdown = shutdown.clone();
     tokio::spawn(async move {
-        if let Err(error) = future.await {
-            log::error!("async task error: {error:?}");
-        }
+        let _guard = guard;
+        log_result(shutdown.cancellable(future).await);
     });
 }
 
-pub fn every<F, Fut>(period: Duration, mut f: F)
+// Unlike `async_spawn`, task keeps running once `shutdown` is triggered, it's only dropped
+// if it isn't done by the end of the grace period. For work that is owed to someone, e.g.
+// replies to requests.
+pub fn drain_spawn<F>(shutdown: &Shutdown, future: F)
+where
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    let guard = shutdown.track_task();
+    let shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        let _guard = guard;
+        log_result(shutdown.drainable(future).await);
+    });
+}
+
+fn log_result(result: Result<Result<()>, Cancelled>) {
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) if error.is::<Cancelled>() => log::debug!("async task cancelled"),
+        Ok(Err(error)) => log::error!("async task error: {error:?}"),
+        Err(Cancelled) => log::debug!("async task cancelled"),
+    }
+}
+
+pub fn every<F, Fut>(shutdown: &Shutdown, period: Duration, mut f: F)
 where
     F: FnMut() -> Fut + Send + 'static,
     Fut: Future<Output = Result<()>> + Send,
 {
-    async_spawn(async move {
+    async_spawn(shutdown, async move {
         let mut interval = interval(period);
         interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
         interval.reset(); // Skip first immediate tick.
@@ -38,3 +66,78 @@ where
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use tokio::{sync::oneshot, time::sleep};
+
+    use super::*;
+
+    const GRACE: Duration = Duration::from_millis(200);
+
+    type BoxFuture = futures::future::BoxFuture<'static, Result<()>>;
+
+    // Task that completes once `release` fires, and records whether it did.
+    fn spawn_waiting(
+        spawn: fn(&Shutdown, BoxFuture),
+        shutdown: &Shutdown,
+    ) -> (oneshot::Sender<()>, Arc<AtomicBool>) {
+        let (release, released) = oneshot::channel();
+        let done = Arc::new(AtomicBool::new(false));
+        let task_done = done.clone();
+        spawn(
+            shutdown,
+            Box::pin(async move {
+                let _ = released.await;
+                task_done.store(true, Ordering::SeqCst);
+                Ok(())
+            }),
+        );
+        (release, done)
+    }
+
+    #[tokio::test]
+    async fn trigger_cancels_background_tasks_and_drains_the_rest() {
+        let shutdown = Shutdown::new();
+        let (_background, background_done) = spawn_waiting(async_spawn, &shutdown);
+        let (release, drained_done) = spawn_waiting(drain_spawn, &shutdown);
+        sleep(Duration::from_millis(10)).await;
+
+        shutdown.trigger();
+        sleep(Duration::from_millis(10)).await;
+        assert!(!shutdown.wait_for_tasks(Duration::ZERO).await);
+
+        release.send(()).unwrap();
+        assert!(shutdown.wait_for_tasks(GRACE).await);
+        assert!(drained_done.load(Ordering::SeqCst));
+        assert!(!background_done.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn abort_cancels_draining_tasks() {
+        let shutdown = Shutdown::new();
+        let (_release, done) = spawn_waiting(drain_spawn, &shutdown);
+        sleep(Duration::from_millis(10)).await;
+
+        shutdown.trigger();
+        assert!(!shutdown.wait_for_tasks(GRACE).await);
+
+        shutdown.abort();
+        assert!(shutdown.wait_for_tasks(GRACE).await);
+        assert!(!done.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn draining_requests_complete_after_trigger() {
+        let shutdown = Shutdown::new();
+        shutdown.trigger();
+        let reply = shutdown.drainable(async { 42 }).await;
+        assert_eq!(reply, Ok(42));
+        assert_eq!(shutdown.cancellable(sleep(GRACE)).await, Err(Cancelled));
+    }
+}
@@ -0,0 +1,91 @@
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{Context, Result};
+
+use crate::{engine::EngineKind, topology::Topology};
+
+// Tunables, overridable with environment variables (Maelstrom nodes inherit its
+// environment).
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub engine: EngineKind,
+    // How long new values accumulate before being gossiped to neighbors in one batch.
+    pub gossip_interval: Duration,
+    // How often a node compares digests with one of its peers.
+    pub anti_entropy_interval: Duration,
+    pub topology: Topology,
+    // Gossip: how long to wait for a batch to be acknowledged before resending it (the
+    // wait doubles with every retry), and how many values each neighbor's outbox holds.
+    pub retransmit_timeout: Duration,
+    pub outbox_capacity: usize,
+    // Plumtree: how long IHAVE announcements are batched for, and how long to wait
+    // for an announced value before grafting the peer that announced it.
+    pub lazy_push_interval: Duration,
+    pub graft_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            engine: EngineKind::Gossip,
+            gossip_interval: Duration::from_millis(100),
+            anti_entropy_interval: Duration::from_secs(1),
+            topology: Topology::Maelstrom,
+            retransmit_timeout: Duration::from_millis(500),
+            outbox_capacity: 100_000,
+            lazy_push_interval: Duration::from_millis(500),
+            graft_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            engine: env_parse("BROADCAST_ENGINE", default.engine)?,
+            gossip_interval: env_millis("BROADCAST_GOSSIP_INTERVAL_MS", default.gossip_interval)?,
+            anti_entropy_interval: env_millis(
+                "BROADCAST_ANTI_ENTROPY_INTERVAL_MS",
+                default.anti_entropy_interval,
+            )?,
+            topology: env_parse("BROADCAST_TOPOLOGY", default.topology)?,
+            retransmit_timeout: env_millis(
+                "BROADCAST_RETRANSMIT_TIMEOUT_MS",
+                default.retransmit_timeout,
+            )?,
+            outbox_capacity: env_parse("BROADCAST_OUTBOX_CAPACITY", default.outbox_capacity)?,
+            lazy_push_interval: env_millis(
+                "BROADCAST_LAZY_PUSH_INTERVAL_MS",
+                default.lazy_push_interval,
+            )?,
+            graft_timeout: env_millis("BROADCAST_GRAFT_TIMEOUT_MS", default.graft_timeout)?,
+        })
+    }
+}
+
+fn env_millis(name: &str, default: Duration) -> Result<Duration> {
+    match std::env::var(name) {
+        Ok(value) => {
+            let millis = value
+                .parse()
+                .with_context(|| format!("invalid {name}: {value:?}"))?;
+            Ok(Duration::from_millis(millis))
+        }
+        Err(_) => Ok(default),
+    }
+}
+
+fn env_parse<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    anyhow::Error: From<T::Err>,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(anyhow::Error::from)
+            .with_context(|| format!("invalid {name}: {value:?}")),
+        Err(_) => Ok(default),
+    }
+}
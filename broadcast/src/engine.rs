@@ -0,0 +1,56 @@
+use std::{
+    fmt::Debug,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Result};
+use base::{client::Client, node::NodeId};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{config::Config, messages::Messages};
+
+pub type SharedMessages = Arc<Mutex<Messages>>;
+
+// Engines never reply to each other, lost messages are up to the engine (or
+// anti-entropy) to recover.
+pub type EngineClient<E> = Client<<E as Engine>::Message, ()>;
+
+// Strategy for spreading values between nodes. Whoever learns a value first records it
+// in the shared `Messages`, engines only decide which peers to tell and when.
+pub trait Engine: Send + Sync + Sized + 'static {
+    type Message: Serialize + DeserializeOwned + Debug + Send + 'static;
+
+    const NAME: &'static str;
+
+    fn new(client: &EngineClient<Self>, messages: &SharedMessages, config: &Config) -> Self;
+
+    // Spawns timers (flushing batches, repairs).
+    fn start(self: &Arc<Self>);
+
+    fn set_neighbors(&self, neighbors: Vec<NodeId>);
+
+    // Spreads values that are new to this node, `sender` (if any) already has them.
+    fn broadcast(&self, sender: Option<&NodeId>, values: &[u64]);
+
+    // Handles a message from another node's engine.
+    fn handle(&self, from: NodeId, message: Self::Message);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    Gossip,
+    Plumtree,
+}
+
+impl FromStr for EngineKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "gossip" => Ok(Self::Gossip),
+            "plumtree" => Ok(Self::Plumtree),
+            _ => bail!("unknown engine {s:?}"),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::Result;
+use base::{node::NodeId, utils::every};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::{
+    config::Config,
+    engine::{Engine, EngineClient, SharedMessages},
+    outbox::Outbox,
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    // Acknowledgements ride along with values going the other way, or are sent on their
+    // own when there is nothing else to send.
+    BroadcastBatch {
+        messages: Vec<u64>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        acks: Vec<u64>,
+    },
+}
+
+// Flooding: new values are queued in the outbox of every neighbor (except the one they
+// came from) and flushed as one batch per neighbor every gossip interval.
+pub struct Gossip {
+    config: Config,
+    client: EngineClient<Self>,
+    messages: SharedMessages,
+    state: Mutex<GossipState>,
+}
+
+#[derive(Default)]
+struct GossipState {
+    outboxes: HashMap<NodeId, Outbox>,
+    // Received values not acknowledged yet, per neighbor.
+    acks: HashMap<NodeId, Vec<u64>>,
+}
+
+impl Gossip {
+    const MAX_BATCH_SIZE: usize = 1000;
+
+    fn lock(&self) -> MutexGuard<'_, GossipState> {
+        self.state.lock().expect("lock failed")
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let now = Instant::now();
+        let batches = {
+            let state = &mut *self.lock();
+            let mut batches = Vec::new();
+            for (neighbor, outbox) in &mut state.outboxes {
+                let messages = outbox.poll(now, Self::MAX_BATCH_SIZE);
+                let acks = state.acks.remove(neighbor).unwrap_or_default();
+                if !messages.is_empty() || !acks.is_empty() {
+                    batches.push((neighbor.clone(), messages, acks));
+                }
+            }
+            // Acks for nodes that aren't our neighbors (anymore).
+            for (node_id, acks) in state.acks.drain() {
+                batches.push((node_id, Vec::new(), acks));
+            }
+            batches
+        };
+
+        for (neighbor, messages, acks) in batches {
+            self.client
+                .send_no_reply(neighbor, Message::BroadcastBatch { messages, acks })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+impl Engine for Gossip {
+    type Message = Message;
+
+    const NAME: &'static str = "gossip";
+
+    fn new(client: &EngineClient<Self>, messages: &SharedMessages, config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            client: client.clone(),
+            messages: messages.clone(),
+            state: Mutex::default(),
+        }
+    }
+
+    fn start(self: &Arc<Self>) {
+        let weak = Arc::downgrade(self);
+        every(
+            self.client.node().shutdown(),
+            self.config.gossip_interval,
+            move || {
+                let weak = weak.clone();
+                async move {
+                    match weak.upgrade() {
+                        Some(gossip) => gossip.flush().await,
+                        None => Ok(()),
+                    }
+                }
+            },
+        );
+    }
+
+    // Outboxes of remaining neighbors are kept, along with whatever they hold.
+    fn set_neighbors(&self, neighbors: Vec<NodeId>) {
+        let state = &mut *self.lock();
+        state
+            .outboxes
+            .retain(|neighbor, _| neighbors.contains(neighbor));
+        for neighbor in &neighbors {
+            state.outboxes.entry(neighbor.clone()).or_insert_with(|| {
+                Outbox::new(self.config.outbox_capacity, self.config.retransmit_timeout)
+            });
+        }
+    }
+
+    fn broadcast(&self, sender: Option<&NodeId>, values: &[u64]) {
+        let state = &mut *self.lock();
+        for (neighbor, outbox) in &mut state.outboxes {
+            if Some(neighbor) != sender {
+                for value in values {
+                    outbox.push(*value);
+                }
+            }
+        }
+    }
+
+    fn handle(&self, from: NodeId, message: Message) {
+        let Message::BroadcastBatch { messages, acks } = message;
+        {
+            let state = &mut *self.lock();
+            if let Some(outbox) = state.outboxes.get_mut(&from) {
+                outbox.ack(&acks);
+            }
+            if !messages.is_empty() {
+                let pending = state.acks.entry(from.clone()).or_default();
+                pending.extend_from_slice(&messages);
+            }
+        }
+
+        let new = {
+            let mut known = self.messages.lock().expect("lock failed");
+            messages
+                .into_iter()
+                .filter(|value| known.insert(*value))
+                .collect::<Vec<_>>()
+        };
+        if !new.is_empty() {
+            self.broadcast(Some(&from), &new);
+        }
+    }
+}
@@ -0,0 +1,8 @@
+pub mod config;
+pub mod engine;
+pub mod gossip;
+pub mod messages;
+pub mod outbox;
+pub mod plumtree;
+pub mod service;
+pub mod topology;
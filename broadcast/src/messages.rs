@@ -0,0 +1,208 @@
+use base::sync::mix;
+use serde::{Deserialize, Serialize};
+
+// Broadcast values in the order this node learned them. A value's sequence number is its
+// position in that order (starting at 1), so readers can ask for everything after a
+// cursor. Membership and full reads go through sorted disjoint ranges, which stay small
+// since values are mostly consecutive integers. A digest of the set is kept up to date
+// on every insert, so that comparing sets with a peer is cheap.
+#[derive(Default, Debug)]
+pub struct Messages {
+    log: Vec<u64>,
+    ranges: Ranges,
+    digest: Digest,
+}
+
+impl Messages {
+    // `false` if the value is already known.
+    pub fn insert(&mut self, value: u64) -> bool {
+        let is_new = self.ranges.insert(value);
+        if is_new {
+            self.log.push(value);
+            self.digest.insert(value);
+        }
+        is_new
+    }
+
+    pub fn contains(&self, value: u64) -> bool {
+        self.ranges.contains(value)
+    }
+
+    pub fn digest(&self) -> Digest {
+        self.digest
+    }
+
+    pub fn len(&self) -> usize {
+        self.log.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.log.is_empty()
+    }
+
+    // Sequence number of the latest value (0 if there are none).
+    pub fn last_seq(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    // Values with sequence numbers after `seq`, in insertion order.
+    pub fn since(&self, seq: u64) -> &[u64] {
+        let start = usize::try_from(seq).unwrap_or(usize::MAX);
+        self.log.get(start..).unwrap_or_default()
+    }
+
+    // In insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.log.iter().copied()
+    }
+
+    // Sorted.
+    pub fn to_vec(&self) -> Vec<u64> {
+        let mut values = Vec::with_capacity(self.log.len());
+        for &(start, end) in &self.ranges.0 {
+            values.extend(start..=end);
+        }
+        values
+    }
+}
+
+// Sorted, disjoint and non-adjacent inclusive ranges.
+#[derive(Default, Debug)]
+struct Ranges(Vec<(u64, u64)>);
+
+impl Ranges {
+    fn contains(&self, value: u64) -> bool {
+        self.position(value).is_ok()
+    }
+
+    fn insert(&mut self, value: u64) -> bool {
+        // `index` is the first range starting after `value`.
+        let index = match self.position(value) {
+            Ok(_) => return false,
+            Err(index) => index,
+        };
+
+        let extends_prev = index > 0 && self.0[index - 1].1.checked_add(1) == Some(value);
+        let extends_next = index < self.0.len() && value.checked_add(1) == Some(self.0[index].0);
+        match (extends_prev, extends_next) {
+            (true, true) => {
+                self.0[index - 1].1 = self.0[index].1;
+                self.0.remove(index);
+            }
+            (true, false) => self.0[index - 1].1 = value,
+            (false, true) => self.0[index].0 = value,
+            (false, false) => self.0.insert(index, (value, value)),
+        }
+        true
+    }
+
+    // `Ok` with the range containing `value`, or `Err` with where it would be inserted.
+    fn position(&self, value: u64) -> Result<usize, usize> {
+        self.0.binary_search_by(|&(start, end)| {
+            if end < value {
+                std::cmp::Ordering::Less
+            } else if start > value {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+    }
+}
+
+// Order-independent summary of a set: equal sets always have equal digests, different
+// ones almost never do.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Digest {
+    pub count: u64,
+    pub hash: u64,
+}
+
+impl Digest {
+    fn insert(&mut self, value: u64) {
+        self.count += 1;
+        self.hash = self.hash.wrapping_add(mix(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    use super::*;
+
+    fn ranges(values: impl IntoIterator<Item = u64>) -> Ranges {
+        let mut ranges = Ranges::default();
+        for value in values {
+            ranges.insert(value);
+        }
+        ranges
+    }
+
+    #[test]
+    fn ranges_merge_adjacent_values() {
+        assert_eq!(ranges([1, 3, 5]).0, vec![(1, 1), (3, 3), (5, 5)]);
+        assert_eq!(ranges([1, 3, 2]).0, vec![(1, 3)]);
+        assert_eq!(ranges([5, 4, 1, 2, 7]).0, vec![(1, 2), (4, 5), (7, 7)]);
+        assert_eq!(
+            ranges([0, u64::MAX, u64::MAX - 1]).0,
+            vec![(0, 0), (u64::MAX - 1, u64::MAX)]
+        );
+
+        let mut ranges = ranges([1, 2, 4]);
+        assert!(!ranges.insert(2));
+        assert!(ranges.insert(3));
+        assert!(!ranges.insert(3));
+        assert_eq!(ranges.0, vec![(1, 4)]);
+        assert!(ranges.contains(1) && ranges.contains(4));
+        assert!(!ranges.contains(0) && !ranges.contains(5));
+    }
+
+    #[test]
+    fn ranges_match_a_set() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut values = (0..300)
+            .filter(|value| value % 7 != 3)
+            .collect::<Vec<u64>>();
+        values.shuffle(&mut rng);
+
+        let ranges = ranges(values.iter().copied());
+        for pair in ranges.0.windows(2) {
+            assert!(pair[0].1 + 1 < pair[1].0, "{pair:?} should be merged");
+        }
+        for value in 0..310 {
+            assert_eq!(ranges.contains(value), values.contains(&value), "{value}");
+        }
+    }
+
+    #[test]
+    fn since_returns_values_in_insertion_order() {
+        let mut messages = Messages::default();
+        for value in [5, 1, 5, 3] {
+            messages.insert(value);
+        }
+
+        assert_eq!(messages.last_seq(), 3);
+        assert_eq!(messages.since(0), [5, 1, 3]);
+        assert_eq!(messages.since(2), [3]);
+        assert!(messages.since(3).is_empty());
+        assert!(messages.since(u64::MAX).is_empty());
+        assert_eq!(messages.to_vec(), vec![1, 3, 5]);
+    }
+
+    #[test]
+    fn digests_dont_depend_on_order() {
+        let mut a = Messages::default();
+        let mut b = Messages::default();
+        for value in [1, 2, 3] {
+            a.insert(value);
+        }
+        for value in [3, 1, 2, 1] {
+            b.insert(value);
+        }
+        assert_eq!(a.digest(), b.digest());
+
+        b.insert(4);
+        assert_ne!(a.digest(), b.digest());
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
+
+use hashlink::LinkedHashMap;
+use tokio::time::Instant;
+
+// Values queued for one neighbor, kept until the neighbor acknowledges them. Nothing is
+// ever given up on: unacknowledged values are retransmitted with exponential backoff, so
+// they reach the neighbor once a partition heals, without flooding it meanwhile.
+#[derive(Debug)]
+pub struct Outbox {
+    unsent: VecDeque<u64>,
+    // Sent but not acknowledged, ordered by when they were (re)sent.
+    in_flight: LinkedHashMap<u64, Instant>,
+    queued: HashSet<u64>,
+    capacity: usize,
+    timeout: Duration,
+    backoff: Duration,
+    dropped: usize,
+}
+
+impl Outbox {
+    const MAX_BACKOFF_FACTOR: u32 = 16;
+
+    pub fn new(capacity: usize, timeout: Duration) -> Self {
+        Self {
+            unsent: VecDeque::new(),
+            in_flight: LinkedHashMap::new(),
+            queued: HashSet::new(),
+            capacity,
+            timeout,
+            backoff: timeout,
+            dropped: 0,
+        }
+    }
+
+    // Drops the value once the outbox is full, anti-entropy delivers it then.
+    pub fn push(&mut self, value: u64) {
+        if self.queued.len() >= self.capacity {
+            self.dropped += 1;
+            if self.dropped.is_power_of_two() {
+                log::warn!("outbox is full, dropped {} values so far", self.dropped);
+            }
+            return;
+        }
+        if self.queued.insert(value) {
+            self.unsent.push_back(value);
+        }
+    }
+
+    pub fn ack(&mut self, values: &[u64]) {
+        let mut acked = false;
+        for value in values {
+            acked |= self.in_flight.remove(value).is_some();
+            self.queued.remove(value);
+        }
+        if acked {
+            self.backoff = self.timeout;
+        }
+    }
+
+    // Values to send now, at most `limit`: overdue retransmissions first, then new ones.
+    pub fn poll(&mut self, now: Instant, limit: usize) -> Vec<u64> {
+        let mut batch = Vec::new();
+
+        while batch.len() < limit {
+            match self.in_flight.front() {
+                Some((value, sent_at)) if now.duration_since(*sent_at) >= self.backoff => {
+                    batch.push(*value);
+                    self.in_flight.pop_front();
+                }
+                _ => break,
+            }
+        }
+        // Every retransmission without an ack in between waits twice as long.
+        if !batch.is_empty() {
+            self.backoff = (self.backoff * 2).min(self.timeout * Self::MAX_BACKOFF_FACTOR);
+        }
+
+        while batch.len() < limit {
+            match self.unsent.pop_front() {
+                // Acked before it was sent (e.g. delivered by anti-entropy).
+                Some(value) if !self.queued.contains(&value) => {}
+                Some(value) => batch.push(value),
+                None => break,
+            }
+        }
+
+        for value in &batch {
+            self.in_flight.insert(*value, now);
+        }
+        batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn outbox(values: impl IntoIterator<Item = u64>) -> Outbox {
+        let mut outbox = Outbox::new(10, TIMEOUT);
+        for value in values {
+            outbox.push(value);
+        }
+        outbox
+    }
+
+    #[test]
+    fn sends_queued_values_once() {
+        let now = Instant::now();
+        let mut outbox = outbox([1, 2, 2, 3]);
+        assert_eq!(outbox.poll(now, 2), vec![1, 2]);
+        assert_eq!(outbox.poll(now, 2), vec![3]);
+        assert!(outbox.poll(now, 2).is_empty());
+    }
+
+    #[test]
+    fn acked_values_are_not_retransmitted() {
+        let now = Instant::now();
+        let mut outbox = outbox([1, 2, 3]);
+        assert_eq!(outbox.poll(now, 10), vec![1, 2, 3]);
+
+        outbox.ack(&[1, 3]);
+        assert_eq!(outbox.poll(now + TIMEOUT, 10), vec![2]);
+        outbox.ack(&[2]);
+        assert!(outbox.poll(now + TIMEOUT * 100, 10).is_empty());
+    }
+
+    #[test]
+    fn values_acked_before_sent_are_skipped() {
+        let mut outbox = outbox([1, 2, 3]);
+        outbox.ack(&[2]);
+        assert_eq!(outbox.poll(Instant::now(), 10), vec![1, 3]);
+    }
+
+    #[test]
+    fn retransmits_with_backoff() {
+        let start = Instant::now();
+        let mut outbox = outbox([1]);
+        assert_eq!(outbox.poll(start, 10), vec![1]);
+        assert!(outbox.poll(start + TIMEOUT / 2, 10).is_empty());
+
+        // Each retransmission waits twice as long as the previous one.
+        let mut now = start;
+        for wait in [TIMEOUT, TIMEOUT * 2, TIMEOUT * 4] {
+            assert!(outbox
+                .poll(now + wait - Duration::from_millis(1), 10)
+                .is_empty());
+            now += wait;
+            assert_eq!(outbox.poll(now, 10), vec![1]);
+        }
+
+        // Up to a limit.
+        for _ in 0..10 {
+            now += TIMEOUT * Outbox::MAX_BACKOFF_FACTOR;
+            assert_eq!(outbox.poll(now, 10), vec![1]);
+        }
+    }
+
+    #[test]
+    fn ack_resets_backoff() {
+        let start = Instant::now();
+        let mut outbox = outbox([1, 2]);
+        assert_eq!(outbox.poll(start, 1), vec![1]);
+        assert_eq!(outbox.poll(start + TIMEOUT, 1), vec![1]);
+        outbox.ack(&[1]);
+
+        let now = start + TIMEOUT * 2;
+        assert_eq!(outbox.poll(now, 1), vec![2]);
+        assert_eq!(outbox.poll(now + TIMEOUT, 1), vec![2]);
+    }
+
+    #[test]
+    fn drops_values_once_full() {
+        let now = Instant::now();
+        let mut outbox = outbox(0..15);
+        assert_eq!(outbox.dropped, 5);
+        assert_eq!(outbox.poll(now, 100), (0..10).collect::<Vec<_>>());
+
+        // Values in flight take up space until acked.
+        outbox.push(20);
+        outbox.ack(&[0, 1]);
+        outbox.push(21);
+        outbox.push(22);
+        assert_eq!(outbox.dropped, 6);
+        assert_eq!(outbox.poll(now, 100), vec![21, 22]);
+    }
+}
@@ -0,0 +1,477 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use anyhow::Result;
+use base::{node::NodeId, utils::every};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::{
+    config::Config,
+    engine::{Engine, EngineClient, SharedMessages},
+    messages::Messages,
+    outbox::Outbox,
+};
+
+const PRUNE_THRESHOLD: usize = 3;
+const MAX_BATCH_SIZE: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    // Eager push of values, acknowledges values pushed by the receiver.
+    Gossip {
+        messages: Vec<u64>,
+        acks: Vec<u64>,
+    },
+    // Lazy push: announces values without sending them.
+    #[serde(rename = "ihave")]
+    IHave {
+        messages: Vec<u64>,
+    },
+    // Asks for announced values that never arrived, and to be an eager peer again.
+    Graft {
+        messages: Vec<u64>,
+    },
+    // Sender keeps getting only duplicates from us, we should push lazily to it.
+    Prune,
+}
+
+// Plumtree (epidemic broadcast trees): values are pushed eagerly along a spanning tree
+// and only announced (IHAVE) to the remaining neighbors. Eager pushes go through the
+// same outboxes as in the gossip engine, so a lost push is retransmitted until the peer
+// acknowledges it. The tree starts as the whole topology and organises itself: a peer
+// that keeps delivering duplicates gets pruned, and a lazy peer whose announcement
+// wasn't followed by the value in time gets grafted back. Like the gossip engine, every
+// kind of message is batched per peer.
+pub struct Plumtree {
+    config: Config,
+    client: EngineClient<Self>,
+    messages: SharedMessages,
+    state: Mutex<PlumtreeState>,
+}
+
+#[derive(Default)]
+struct PlumtreeState {
+    eager: BTreeSet<NodeId>,
+    lazy: BTreeSet<NodeId>,
+    // Outgoing batches, per peer.
+    outboxes: HashMap<NodeId, Outbox>,
+    // Pushed values not acknowledged yet.
+    acks: HashMap<NodeId, Vec<u64>>,
+    ihave: HashMap<NodeId, Vec<u64>>,
+    graft: HashMap<NodeId, Vec<u64>>,
+    prune: HashSet<NodeId>,
+    // Announced values we don't have yet.
+    missing: HashMap<u64, Missing>,
+    last_lazy_flush: Option<Instant>,
+    // Consecutive batches with nothing new, per peer.
+    redundant: HashMap<NodeId, usize>,
+}
+
+struct Missing {
+    deadline: Instant,
+    // Peers that announced the value, grafted one by one until it arrives.
+    announcers: VecDeque<NodeId>,
+}
+
+impl PlumtreeState {
+    fn make_eager(&mut self, peer: &NodeId) {
+        if self.lazy.remove(peer) {
+            self.eager.insert(peer.clone());
+        }
+    }
+
+    fn make_lazy(&mut self, peer: &NodeId) {
+        if self.eager.remove(peer) {
+            self.lazy.insert(peer.clone());
+        }
+    }
+
+    fn outbox(&mut self, peer: &NodeId, config: &Config) -> &mut Outbox {
+        self.outboxes
+            .entry(peer.clone())
+            .or_insert_with(|| Outbox::new(config.outbox_capacity, config.retransmit_timeout))
+    }
+
+    fn push(&mut self, sender: Option<&NodeId>, values: &[u64], config: &Config) {
+        let eager = self.eager.iter().filter(|peer| Some(*peer) != sender);
+        for peer in eager.cloned().collect::<Vec<_>>() {
+            let outbox = self.outbox(&peer, config);
+            for value in values {
+                outbox.push(*value);
+            }
+        }
+        for peer in &self.lazy {
+            if Some(peer) != sender {
+                let batch = self.ihave.entry(peer.clone()).or_default();
+                batch.extend_from_slice(values);
+            }
+        }
+        for value in values {
+            self.missing.remove(value);
+        }
+    }
+
+    // Grafts the next announcer of every value that is overdue.
+    fn repair(&mut self, messages: &Messages, now: Instant, graft_timeout: Duration) {
+        let mut grafts = Vec::new();
+        self.missing.retain(|value, missing| {
+            if messages.contains(*value) {
+                return false;
+            }
+            if missing.deadline <= now {
+                if let Some(peer) = missing.announcers.pop_front() {
+                    grafts.push((peer.clone(), *value));
+                    missing.announcers.push_back(peer);
+                }
+                missing.deadline = now + graft_timeout;
+            }
+            true
+        });
+
+        for (peer, value) in grafts {
+            self.make_eager(&peer);
+            self.graft.entry(peer).or_default().push(value);
+        }
+    }
+
+    // Takes batches that are due.
+    fn outgoing(
+        &mut self,
+        messages: &Messages,
+        now: Instant,
+        config: &Config,
+    ) -> Vec<(NodeId, Message)> {
+        self.repair(messages, now, config.graft_timeout);
+
+        let mut outgoing = Vec::new();
+        for (peer, outbox) in &mut self.outboxes {
+            let messages = outbox.poll(now, MAX_BATCH_SIZE);
+            let acks = self.acks.remove(peer).unwrap_or_default();
+            if !messages.is_empty() || !acks.is_empty() {
+                outgoing.push((peer.clone(), Message::Gossip { messages, acks }));
+            }
+        }
+        // Acks for peers we never pushed anything to.
+        for (peer, acks) in self.acks.drain() {
+            let messages = Vec::new();
+            outgoing.push((peer, Message::Gossip { messages, acks }));
+        }
+        for (peer, messages) in self.graft.drain() {
+            outgoing.push((peer, Message::Graft { messages }));
+        }
+        for peer in self.prune.drain() {
+            outgoing.push((peer, Message::Prune));
+        }
+        // Announcements aren't urgent, they are batched for longer.
+        let lazy_due = self
+            .last_lazy_flush
+            .is_none_or(|last| now.duration_since(last) >= config.lazy_push_interval);
+        if lazy_due {
+            self.last_lazy_flush = Some(now);
+            for (peer, messages) in self.ihave.drain() {
+                outgoing.push((peer, Message::IHave { messages }));
+            }
+        }
+        outgoing
+    }
+
+    fn on_gossip(
+        &mut self,
+        messages: &mut Messages,
+        from: NodeId,
+        values: Vec<u64>,
+        acks: Vec<u64>,
+        config: &Config,
+    ) {
+        if let Some(outbox) = self.outboxes.get_mut(&from) {
+            outbox.ack(&acks);
+        }
+        if values.is_empty() {
+            return;
+        }
+        // Duplicates are acknowledged too, so that the sender stops retransmitting them.
+        self.acks
+            .entry(from.clone())
+            .or_default()
+            .extend_from_slice(&values);
+
+        let new = values
+            .into_iter()
+            .filter(|value| messages.insert(*value))
+            .collect::<Vec<_>>();
+
+        if !new.is_empty() {
+            self.redundant.remove(&from);
+            self.make_eager(&from);
+            self.push(Some(&from), &new, config);
+            return;
+        }
+
+        // With many values in flight, a single batch of duplicates may just mean that a
+        // needed link lost a race, and pruning every such link breaks the tree apart. The
+        // sender is pruned only once it keeps delivering nothing new.
+        let redundant = self.redundant.entry(from.clone()).or_default();
+        *redundant += 1;
+        if *redundant >= PRUNE_THRESHOLD && self.eager.contains(&from) {
+            log::debug!("pruning {from}");
+            self.redundant.remove(&from);
+            self.make_lazy(&from);
+            self.prune.insert(from);
+        }
+    }
+
+    fn on_ihave(&mut self, messages: &Messages, from: NodeId, values: Vec<u64>, deadline: Instant) {
+        for value in values {
+            if messages.contains(value) {
+                continue;
+            }
+            let missing = self.missing.entry(value).or_insert_with(|| Missing {
+                deadline,
+                announcers: VecDeque::new(),
+            });
+            if !missing.announcers.contains(&from) {
+                missing.announcers.push_back(from.clone());
+            }
+        }
+    }
+
+    fn on_graft(&mut self, messages: &Messages, from: NodeId, values: Vec<u64>, config: &Config) {
+        self.make_eager(&from);
+
+        let outbox = self.outbox(&from, config);
+        for value in values.into_iter().filter(|value| messages.contains(*value)) {
+            outbox.push(value);
+        }
+    }
+
+    fn handle(
+        &mut self,
+        messages: &mut Messages,
+        from: NodeId,
+        message: Message,
+        now: Instant,
+        config: &Config,
+    ) {
+        match message {
+            Message::Gossip {
+                messages: values,
+                acks,
+            } => self.on_gossip(messages, from, values, acks, config),
+            Message::IHave { messages: values } => {
+                self.on_ihave(messages, from, values, now + config.graft_timeout)
+            }
+            Message::Graft { messages: values } => self.on_graft(messages, from, values, config),
+            Message::Prune => {
+                log::debug!("pruned by {from}");
+                self.make_lazy(&from);
+            }
+        }
+    }
+}
+
+impl Plumtree {
+    fn lock(&self) -> MutexGuard<'_, PlumtreeState> {
+        self.state.lock().expect("lock failed")
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let outgoing = {
+            let messages = self.messages.lock().expect("lock failed");
+            self.lock()
+                .outgoing(&messages, Instant::now(), &self.config)
+        };
+
+        for (peer, message) in outgoing {
+            self.client.send_no_reply(peer, message).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Engine for Plumtree {
+    type Message = Message;
+
+    const NAME: &'static str = "plumtree";
+
+    fn new(client: &EngineClient<Self>, messages: &SharedMessages, config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            client: client.clone(),
+            messages: messages.clone(),
+            state: Mutex::default(),
+        }
+    }
+
+    fn start(self: &Arc<Self>) {
+        let weak = Arc::downgrade(self);
+        every(
+            self.client.node().shutdown(),
+            self.config.gossip_interval,
+            move || {
+                let weak = weak.clone();
+                async move {
+                    match weak.upgrade() {
+                        Some(plumtree) => plumtree.flush().await,
+                        None => Ok(()),
+                    }
+                }
+            },
+        );
+    }
+
+    // Every neighbor starts as an eager peer, the tree is carved out by pruning.
+    fn set_neighbors(&self, neighbors: Vec<NodeId>) {
+        let mut state = self.lock();
+        state.eager = neighbors.into_iter().collect();
+        state.lazy.clear();
+    }
+
+    fn broadcast(&self, sender: Option<&NodeId>, values: &[u64]) {
+        self.lock().push(sender, values, &self.config);
+    }
+
+    fn handle(&self, from: NodeId, message: Message) {
+        let mut messages = self.messages.lock().expect("lock failed");
+        self.lock()
+            .handle(&mut messages, from, message, Instant::now(), &self.config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Replica {
+        node_id: NodeId,
+        state: PlumtreeState,
+        messages: Messages,
+    }
+
+    // Replicas connected in a line, so that every value has a single path to take.
+    fn line(n: usize) -> Vec<Replica> {
+        let node_ids = (0..n)
+            .map(|i| serde_json::from_value(format!("n{i}").into()).unwrap())
+            .collect::<Vec<NodeId>>();
+        (0..n)
+            .map(|i| {
+                let neighbors = [i.wrapping_sub(1), i + 1]
+                    .into_iter()
+                    .filter_map(|j| node_ids.get(j).cloned());
+                Replica {
+                    node_id: node_ids[i].clone(),
+                    state: PlumtreeState {
+                        eager: neighbors.collect(),
+                        ..PlumtreeState::default()
+                    },
+                    messages: Messages::default(),
+                }
+            })
+            .collect()
+    }
+
+    // Flushes every replica once per gossip interval for `duration`, and delivers what
+    // they send unless `drop` says otherwise.
+    fn run(
+        replicas: &mut [Replica],
+        config: &Config,
+        start: Instant,
+        duration: Duration,
+        mut drop: impl FnMut(&NodeId, &NodeId, &Message) -> bool,
+    ) {
+        let mut now = start;
+        while now < start + duration {
+            let mut sent = Vec::new();
+            for replica in replicas.iter_mut() {
+                let outgoing = replica.state.outgoing(&replica.messages, now, config);
+                sent.extend(
+                    outgoing
+                        .into_iter()
+                        .map(|(to, m)| (replica.node_id.clone(), to, m)),
+                );
+            }
+            for (from, to, message) in sent {
+                if drop(&from, &to, &message) {
+                    continue;
+                }
+                let replica = replicas.iter_mut().find(|r| r.node_id == to).unwrap();
+                replica
+                    .state
+                    .handle(&mut replica.messages, from, message, now, config);
+            }
+            now += config.gossip_interval;
+        }
+    }
+
+    fn broadcast(replica: &mut Replica, config: &Config, values: &[u64]) {
+        for value in values {
+            replica.messages.insert(*value);
+        }
+        replica.state.push(None, values, config);
+    }
+
+    #[test]
+    fn values_spread_along_the_tree() {
+        let config = Config::default();
+        let mut replicas = line(4);
+        broadcast(&mut replicas[0], &config, &[1, 2, 3]);
+        run(
+            &mut replicas,
+            &config,
+            Instant::now(),
+            config.gossip_interval * 4,
+            // Every link of a line is part of the tree.
+            |_, _, message| {
+                assert!(!matches!(message, Message::IHave { .. }));
+                false
+            },
+        );
+
+        for replica in &replicas {
+            assert_eq!(
+                replica.messages.to_vec(),
+                vec![1, 2, 3],
+                "{}",
+                replica.node_id
+            );
+        }
+    }
+
+    #[test]
+    fn lost_eager_push_is_retransmitted() {
+        let config = Config::default();
+        let mut replicas = line(3);
+        broadcast(&mut replicas[0], &config, &[1, 2, 3]);
+
+        // The only path to the other replicas starts with the first push.
+        let root = replicas[0].node_id.clone();
+        let mut dropped = 0;
+        let duration = config.retransmit_timeout * 4;
+        run(
+            &mut replicas,
+            &config,
+            Instant::now(),
+            duration,
+            |from, _, message| {
+                let drop =
+                    dropped == 0 && *from == root && matches!(message, Message::Gossip { .. });
+                dropped += usize::from(drop);
+                drop
+            },
+        );
+
+        assert_eq!(dropped, 1);
+        for replica in &replicas {
+            assert_eq!(
+                replica.messages.to_vec(),
+                vec![1, 2, 3],
+                "{}",
+                replica.node_id
+            );
+        }
+    }
+}
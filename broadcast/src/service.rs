@@ -0,0 +1,337 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, MutexGuard,
+    },
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base::{
+    client::Client,
+    init::recv_init,
+    node::NodeId,
+    serve::{serve, RequestHandler, Service},
+    sync::Iblt,
+    utils::{async_spawn, every},
+};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    engine::{Engine, SharedMessages},
+    messages::{Digest, Messages},
+    topology::{diameter, Topology},
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Request<M> {
+    Broadcast(BroadcastRequest),
+    Engine(M),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BroadcastRequest {
+    Topology {
+        topology: HashMap<NodeId, Vec<NodeId>>,
+    },
+    Broadcast {
+        message: u64,
+    },
+    // Extension for our own clients: with `since` (a cursor from a previous read) only
+    // values learned after it are returned, in the order they were learned, along with
+    // the next cursor. Plain reads get all values, sorted.
+    Read {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<u64>,
+    },
+    // Anti-entropy: peer merges the messages it's missing and replies with the ones
+    // the sender is missing, or with `None` if the IBLT was too small to decode.
+    Sync {
+        digest: Digest,
+        iblt: Iblt,
+    },
+    // Anti-entropy fallback for differences too large for any IBLT.
+    SyncAll {
+        messages: Vec<u64>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    TopologyOk,
+    BroadcastOk,
+    ReadOk {
+        messages: Vec<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next: Option<u64>,
+    },
+    SyncOk {
+        missing: Option<Vec<u64>>,
+    },
+}
+
+// Values are spread by the engine `E`. Whatever it loses (e.g. during a partition) is
+// recovered by periodic anti-entropy with a peer picked round-robin from the whole
+// cluster.
+pub struct BroadcastService<E: Engine> {
+    config: Config,
+    engine: Arc<E>,
+    messages: SharedMessages,
+    client: Client<Request<E::Message>, Response>,
+    sync_round: AtomicUsize,
+}
+
+impl<E: Engine> BroadcastService<E> {
+    pub async fn run(config: Config) -> Result<()> {
+        log::info!("starting {} engine with {config:?}", E::NAME);
+        let node = recv_init().await?;
+        let client = Client::new(&node);
+        let messages = SharedMessages::default();
+
+        let engine = Arc::new(E::new(&Client::new(&node), &messages, &config));
+        engine.start();
+
+        let service = Arc::new(Self {
+            config,
+            engine,
+            messages,
+            client: client.clone(),
+            sync_round: AtomicUsize::new(0),
+        });
+        service.generate_topology()?;
+        service.start_anti_entropy();
+        serve(&node, (Service::new(&node, service), client)).await
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Messages> {
+        self.messages.lock().expect("lock failed")
+    }
+
+    fn node_id(&self) -> &NodeId {
+        self.client.node().node_id()
+    }
+
+    fn start_anti_entropy(self: &Arc<Self>) {
+        let weak = Arc::downgrade(self);
+        every(
+            self.client.node().shutdown(),
+            self.config.anti_entropy_interval,
+            move || {
+                let weak = weak.clone();
+                async move {
+                    if let Some(service) = weak.upgrade() {
+                        service.start_sync();
+                    }
+                    Ok(())
+                }
+            },
+        );
+    }
+
+    fn generate_topology(&self) -> Result<()> {
+        let node_ids = self.client.node().node_ids();
+        let Some(mut graph) = self.config.topology.generate(node_ids)? else {
+            return Ok(());
+        };
+
+        let edges = graph.values().map(Vec::len).sum::<usize>() / 2;
+        let diameter = diameter(&graph).map_or("inf".to_owned(), |d| d.to_string());
+        let neighbors = graph.remove(self.node_id()).unwrap_or_default();
+        log::info!(
+            "{} topology: {edges} edges, diameter {diameter}, neighbors {neighbors:?}",
+            self.config.topology
+        );
+
+        self.engine.set_neighbors(neighbors);
+        Ok(())
+    }
+
+    fn update_topology(&self, mut topology: HashMap<NodeId, Vec<NodeId>>) -> Result<()> {
+        if self.config.topology != Topology::Maelstrom {
+            log::debug!(
+                "ignoring maelstrom topology, using {}",
+                self.config.topology
+            );
+            return Ok(());
+        }
+
+        let neighbors = topology.remove(self.node_id()).ok_or_else(|| {
+            anyhow!(
+                "topology does not contain this node's neighbors {}",
+                self.node_id()
+            )
+        })?;
+        let neighbors_except_self = neighbors
+            .into_iter()
+            .filter(|neighbor| neighbor != self.node_id())
+            .collect::<Vec<_>>();
+
+        log::info!("maelstrom topology: neighbors {neighbors_except_self:?}");
+        self.engine.set_neighbors(neighbors_except_self);
+        Ok(())
+    }
+
+    // Records values and hands new ones over to the engine.
+    fn receive(&self, sender: Option<&NodeId>, values: impl IntoIterator<Item = u64>) {
+        let new = {
+            let mut messages = self.lock();
+            values
+                .into_iter()
+                .filter(|value| messages.insert(*value))
+                .collect::<Vec<_>>()
+        };
+        if !new.is_empty() {
+            self.engine.broadcast(sender, &new);
+        }
+    }
+
+    fn read(&self, since: Option<u64>) -> Response {
+        let messages = self.lock();
+        match since {
+            Some(since) => Response::ReadOk {
+                messages: messages.since(since).to_vec(),
+                next: Some(messages.last_seq()),
+            },
+            None => Response::ReadOk {
+                messages: messages.to_vec(),
+                next: None,
+            },
+        }
+    }
+
+    // Runs in the background, so that an unreachable peer doesn't delay the next round.
+    fn start_sync(self: &Arc<Self>) {
+        let peers = self
+            .client
+            .node()
+            .node_ids()
+            .iter()
+            .filter(|node_id| *node_id != self.node_id())
+            .collect::<Vec<_>>();
+        if peers.is_empty() {
+            return;
+        }
+
+        // Offset by node index, so that nodes don't all pick the same peer at once.
+        let round = self.sync_round.fetch_add(1, Ordering::Relaxed) + 1;
+        let peer = peers[(self.client.node().node_index() + round) % peers.len()].clone();
+
+        let service = self.clone();
+        async_spawn(self.client.node().shutdown(), async move {
+            service
+                .sync(peer.clone())
+                .await
+                .with_context(|| format!("failed to sync with {peer}"))
+        });
+    }
+
+    async fn sync(&self, peer: NodeId) -> Result<()> {
+        for size in Iblt::SIZES {
+            let (digest, iblt) = {
+                let messages = self.lock();
+                (messages.digest(), Iblt::from_keys(size, messages.iter()))
+            };
+            let request = BroadcastRequest::Sync { digest, iblt };
+            if let Some(missing) = self.send_sync(&peer, request).await? {
+                self.receive(Some(&peer), missing);
+                return Ok(());
+            }
+            log::debug!("sync: difference with {peer} doesn't fit {size} cells");
+        }
+
+        let messages = self.lock().to_vec();
+        log::info!("sync: sending all {} messages to {peer}", messages.len());
+        let Some(missing) = self
+            .send_sync(&peer, BroadcastRequest::SyncAll { messages })
+            .await?
+        else {
+            bail!("{peer} didn't reply with missing messages");
+        };
+        self.receive(Some(&peer), missing);
+        Ok(())
+    }
+
+    async fn send_sync(
+        &self,
+        peer: &NodeId,
+        request: BroadcastRequest,
+    ) -> Result<Option<Vec<u64>>> {
+        let response = self
+            .client
+            .send(peer.clone(), Request::Broadcast(request))
+            .await?;
+        match response {
+            Response::SyncOk { missing } => Ok(missing),
+            response => bail!("unexpected response from {peer}: {response:?}"),
+        }
+    }
+
+    fn on_sync(&self, sender: &NodeId, digest: Digest, iblt: &Iblt) -> Result<Option<Vec<u64>>> {
+        let difference = {
+            let messages = self.lock();
+            if messages.digest() == digest {
+                return Ok(Some(Vec::new()));
+            }
+            iblt.difference(messages.iter())?
+        };
+
+        let Some(difference) = difference else {
+            return Ok(None);
+        };
+        self.receive(Some(sender), difference.remote);
+        Ok(Some(difference.local))
+    }
+
+    fn on_sync_all(&self, sender: &NodeId, theirs: Vec<u64>) -> Vec<u64> {
+        let known = theirs.iter().copied().collect::<HashSet<_>>();
+        let mut missing = self.lock().to_vec();
+        missing.retain(|value| !known.contains(value));
+        self.receive(Some(sender), theirs);
+        missing
+    }
+}
+
+impl<E: Engine> RequestHandler for BroadcastService<E> {
+    type Request = Request<E::Message>;
+    type Response = Response;
+
+    fn handle<'a>(
+        self: &'a Arc<Self>,
+        sender: NodeId,
+        request: Self::Request,
+    ) -> BoxFuture<'a, Result<Option<Response>>> {
+        async move {
+            let request = match request {
+                Request::Broadcast(request) => request,
+                Request::Engine(message) => {
+                    self.engine.handle(sender, message);
+                    return Ok(None);
+                }
+            };
+
+            match request {
+                BroadcastRequest::Topology { topology } => {
+                    self.update_topology(topology)?;
+                    Ok(Some(Response::TopologyOk))
+                }
+                BroadcastRequest::Broadcast { message } => {
+                    self.receive(None, [message]);
+                    Ok(Some(Response::BroadcastOk))
+                }
+                BroadcastRequest::Read { since } => Ok(Some(self.read(since))),
+                BroadcastRequest::Sync { digest, iblt } => Ok(Some(Response::SyncOk {
+                    missing: self.on_sync(&sender, digest, &iblt)?,
+                })),
+                BroadcastRequest::SyncAll { messages } => Ok(Some(Response::SyncOk {
+                    missing: Some(self.on_sync_all(&sender, messages)),
+                })),
+            }
+        }
+        .boxed()
+    }
+}
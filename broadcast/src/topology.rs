@@ -0,0 +1,269 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base::node::NodeId;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+pub type Graph = HashMap<NodeId, Vec<NodeId>>;
+
+// Overlay used for gossip. Every node computes the same graph from `node_ids`, so
+// generated topologies must be deterministic (random graphs use a fixed seed).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Topology {
+    // Whatever Maelstrom sends in the `topology` request.
+    Maelstrom,
+    // Star rooted at the first node: 2 hops between any nodes, n - 1 edges, but the
+    // root relays everything.
+    SpanningTree,
+    // Complete tree with `arity` children per node, in `node_ids` order.
+    Tree { arity: usize },
+    // Nodes are connected when their indices differ in exactly one bit.
+    Hypercube,
+    // Uniformly random connected graph where every node has `degree` neighbors.
+    Random { degree: usize, seed: u64 },
+}
+
+impl Topology {
+    const DEFAULT_SEED: u64 = 0;
+    const MAX_ATTEMPTS: usize = 1000;
+
+    // `None` for `Maelstrom`, which isn't known until the `topology` request.
+    pub fn generate(&self, node_ids: &[NodeId]) -> Result<Option<Graph>> {
+        let n = node_ids.len();
+        let edges = match *self {
+            Self::Maelstrom => return Ok(None),
+            Self::SpanningTree => (1..n).map(|i| (0, i)).collect(),
+            Self::Tree { arity } => {
+                if arity == 0 {
+                    bail!("tree arity must be positive");
+                }
+                (1..n).map(|i| ((i - 1) / arity, i)).collect()
+            }
+            Self::Hypercube => (0..n)
+                .flat_map(|i| {
+                    (0..usize::BITS)
+                        .map(move |bit| i ^ (1 << bit))
+                        .filter(move |j| i < *j && *j < n)
+                        .map(move |j| (i, j))
+                })
+                .collect(),
+            Self::Random { degree, seed } => random_regular(n, degree, seed)?,
+        };
+
+        let mut graph: Graph = node_ids.iter().map(|id| (id.clone(), Vec::new())).collect();
+        for (a, b) in edges {
+            let (a, b) = (&node_ids[a], &node_ids[b]);
+            graph.get_mut(a).expect("node exists").push(b.clone());
+            graph.get_mut(b).expect("node exists").push(a.clone());
+        }
+        Ok(Some(graph))
+    }
+}
+
+impl FromStr for Topology {
+    type Err = anyhow::Error;
+
+    // `maelstrom`, `spanning-tree`, `tree:<arity>`, `hypercube` or
+    // `random:<degree>[:<seed>]`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let mut arg = |name: &str| -> Result<Option<u64>> {
+            parts
+                .next()
+                .map(|value| {
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid {name} in topology {s:?}"))
+                })
+                .transpose()
+        };
+
+        let topology = match kind {
+            "maelstrom" => Self::Maelstrom,
+            "spanning-tree" => Self::SpanningTree,
+            "tree" => Self::Tree {
+                arity: arg("arity")?.ok_or_else(|| anyhow!("tree arity is missing"))? as usize,
+            },
+            "hypercube" => Self::Hypercube,
+            "random" => Self::Random {
+                degree: arg("degree")?.ok_or_else(|| anyhow!("random degree is missing"))? as usize,
+                seed: arg("seed")?.unwrap_or(Self::DEFAULT_SEED),
+            },
+            _ => bail!("unknown topology {s:?}"),
+        };
+        if parts.next().is_some() {
+            bail!("too many arguments in topology {s:?}");
+        }
+        Ok(topology)
+    }
+}
+
+impl std::fmt::Display for Topology {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Maelstrom => f.write_str("maelstrom"),
+            Self::SpanningTree => f.write_str("spanning-tree"),
+            Self::Tree { arity } => write!(f, "tree:{arity}"),
+            Self::Hypercube => f.write_str("hypercube"),
+            Self::Random { degree, seed } => write!(f, "random:{degree}:{seed}"),
+        }
+    }
+}
+
+// Longest shortest path between any two nodes, `None` if the graph is disconnected.
+pub fn diameter(graph: &Graph) -> Option<usize> {
+    let mut diameter = 0;
+    for start in graph.keys() {
+        let mut distances = HashMap::from([(start, 0)]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(node) = queue.pop_front() {
+            let distance = distances[node];
+            for neighbor in &graph[node] {
+                if !distances.contains_key(neighbor) {
+                    distances.insert(neighbor, distance + 1);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        if distances.len() < graph.len() {
+            return None;
+        }
+        diameter = diameter.max(distances.into_values().max().unwrap_or(0));
+    }
+    Some(diameter)
+}
+
+// Pairing model: every node gets `degree` stubs, random pairs of stubs are joined
+// unless that would create a loop or a duplicate edge. Dead ends and disconnected graphs
+// start over.
+fn random_regular(n: usize, degree: usize, seed: u64) -> Result<Vec<(usize, usize)>> {
+    if n <= 1 {
+        return Ok(Vec::new());
+    }
+    if degree >= n || degree == 0 || !(n * degree).is_multiple_of(2) {
+        bail!("no {degree}-regular graph on {n} nodes");
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..Topology::MAX_ATTEMPTS {
+        let mut stubs = (0..n)
+            .flat_map(|i| std::iter::repeat_n(i, degree))
+            .collect::<Vec<_>>();
+        let mut edges = HashSet::new();
+        let mut failures = 0;
+
+        while !stubs.is_empty() && failures < Topology::MAX_ATTEMPTS {
+            let i = rng.gen_range(0..stubs.len());
+            let j = rng.gen_range(0..stubs.len());
+            let edge = (stubs[i].min(stubs[j]), stubs[i].max(stubs[j]));
+            if edge.0 == edge.1 || edges.contains(&edge) {
+                failures += 1;
+                continue;
+            }
+
+            edges.insert(edge);
+            stubs.swap_remove(i.max(j));
+            stubs.swap_remove(i.min(j));
+        }
+
+        if stubs.is_empty() && is_connected(n, &edges) {
+            let mut edges = edges.into_iter().collect::<Vec<_>>();
+            edges.sort_unstable();
+            return Ok(edges);
+        }
+    }
+    bail!("failed to generate a connected {degree}-regular graph on {n} nodes")
+}
+
+fn is_connected(n: usize, edges: &HashSet<(usize, usize)>) -> bool {
+    let mut adjacent = vec![Vec::new(); n];
+    for &(a, b) in edges {
+        adjacent[a].push(b);
+        adjacent[b].push(a);
+    }
+
+    let mut visited = vec![false; n];
+    let mut stack = vec![0];
+    visited[0] = true;
+    while let Some(node) = stack.pop() {
+        for &neighbor in &adjacent[node] {
+            if !visited[neighbor] {
+                visited[neighbor] = true;
+                stack.push(neighbor);
+            }
+        }
+    }
+    visited.into_iter().all(|visited| visited)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_ids(n: usize) -> Vec<NodeId> {
+        (0..n)
+            .map(|i| serde_json::from_value(format!("n{i}").into()).unwrap())
+            .collect()
+    }
+
+    fn generate(topology: &Topology, n: usize) -> Graph {
+        topology.generate(&node_ids(n)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn hypercube_is_connected() {
+        for n in 1..=40 {
+            let graph = generate(&Topology::Hypercube, n);
+            let dimensions = n.next_power_of_two().trailing_zeros() as usize;
+            assert!(
+                graph
+                    .values()
+                    .all(|neighbors| neighbors.len() <= dimensions),
+                "{n} nodes: too many neighbors"
+            );
+            // Every node is still adjacent to the one without its highest bit.
+            let diameter = diameter(&graph);
+            assert!(
+                diameter.is_some_and(|diameter| diameter <= dimensions),
+                "{n} nodes: diameter {diameter:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn random_regular_graphs() {
+        for (n, degree) in [(2, 1), (4, 3), (5, 2), (10, 3), (25, 4), (50, 7)] {
+            for seed in 0..5 {
+                let topology = Topology::Random { degree, seed };
+                let graph = generate(&topology, n);
+                assert!(diameter(&graph).is_some(), "{topology} on {n} nodes");
+                for (node_id, neighbors) in &graph {
+                    let unique = neighbors.iter().collect::<HashSet<_>>();
+                    assert_eq!(neighbors.len(), degree, "{topology} on {n} nodes");
+                    assert_eq!(unique.len(), degree, "{topology}: duplicate edges");
+                    assert!(!unique.contains(node_id), "{topology}: loop");
+                }
+                assert_eq!(
+                    graph,
+                    generate(&topology, n),
+                    "{topology}: not deterministic"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn random_regular_rejects_impossible_degrees() {
+        for (n, degree) in [(4, 0), (4, 4), (5, 3)] {
+            let topology = Topology::Random { degree, seed: 0 };
+            assert!(
+                topology.generate(&node_ids(n)).is_err(),
+                "{topology} on {n}"
+            );
+        }
+    }
+}
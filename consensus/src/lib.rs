@@ -0,0 +1,76 @@
+use std::{fmt::Debug, sync::Arc};
+
+use base::{client::Client, node::NodeId};
+use futures::future::BoxFuture;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use crate::metrics::Metrics;
+
+mod log;
+pub mod metrics;
+pub mod paxos;
+pub mod raft;
+mod replica;
+
+// Deterministic state machine replicated by a consensus engine: every replica applies
+// the same commands in the same order. Snapshots let engines compact their logs and
+// catch up replicas that fell too far behind.
+pub trait StateMachine: Send + 'static {
+    type Command: Serialize + DeserializeOwned + Clone + Debug + Send + Sync + 'static;
+    type Output: Send + 'static;
+    type Snapshot: Serialize + DeserializeOwned + Clone + Debug + Send + Sync + 'static;
+
+    fn apply(&mut self, command: &Self::Command) -> Self::Output;
+
+    fn snapshot(&self) -> Self::Snapshot;
+
+    fn restore(&mut self, snapshot: Self::Snapshot);
+}
+
+// Consensus protocol driving a `StateMachine`. Services are generic over the engine, so
+// protocols can be swapped and compared under the same workload.
+pub trait Engine: Send + Sync + Sized + 'static {
+    type Machine: StateMachine;
+    type Request: Serialize + DeserializeOwned + Debug + Send + 'static;
+    type Response: Serialize + DeserializeOwned + Debug + Send + 'static;
+
+    const NAME: &'static str;
+
+    fn new(client: &Client<Self::Request, Self::Response>, machine: Self::Machine) -> Self;
+
+    // Spawns timers (elections, heartbeats, replication).
+    fn start(self: &Arc<Self>);
+
+    // Resolves once the command has been applied on this node.
+    fn submit(&self, command: Command<Self>) -> BoxFuture<'_, Result<Output<Self>, SubmitError>>;
+
+    // Handles a message from another replica.
+    fn handle(&self, from: NodeId, request: Self::Request) -> Self::Response;
+
+    fn leader(&self) -> Option<NodeId>;
+
+    fn metrics(&self) -> &Metrics;
+}
+
+pub type Command<E> = <<E as Engine>::Machine as StateMachine>::Command;
+pub type Output<E> = <<E as Engine>::Machine as StateMachine>::Output;
+
+#[derive(Debug)]
+pub enum SubmitError {
+    // Command wasn't accepted, so it will never be applied.
+    NotLeader(Option<NodeId>),
+    // Command was accepted but we don't know whether it will be applied.
+    Indefinite(&'static str),
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotLeader(Some(leader)) => write!(f, "not a leader, leader is {leader}"),
+            Self::NotLeader(None) => f.write_str("not a leader, leader is unknown"),
+            Self::Indefinite(reason) => write!(f, "outcome unknown: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
@@ -0,0 +1,73 @@
+// Replicated log with its prefix compacted into a snapshot. Indices start at 1, entry
+// at index `i` is stored at `entries[i - snapshot_index - 1]`.
+#[derive(Debug)]
+pub(crate) struct Log<E> {
+    snapshot_index: u64,
+    entries: Vec<E>,
+}
+
+impl<E> Log<E> {
+    pub(crate) fn new() -> Self {
+        Self {
+            snapshot_index: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    // Last index covered by the snapshot (0 if there is none).
+    pub(crate) fn snapshot_index(&self) -> u64 {
+        self.snapshot_index
+    }
+
+    pub(crate) fn last_index(&self) -> u64 {
+        self.snapshot_index + self.entries.len() as u64
+    }
+
+    pub(crate) fn last(&self) -> Option<&E> {
+        self.entries.last()
+    }
+
+    // `None` for compacted and missing entries.
+    pub(crate) fn get(&self, index: u64) -> Option<&E> {
+        self.position(index).and_then(|pos| self.entries.get(pos))
+    }
+
+    pub(crate) fn get_mut(&mut self, index: u64) -> Option<&mut E> {
+        self.position(index)
+            .and_then(|pos| self.entries.get_mut(pos))
+    }
+
+    pub(crate) fn push(&mut self, entry: E) -> u64 {
+        self.entries.push(entry);
+        self.last_index()
+    }
+
+    // Entries starting at `index` (which must not be compacted), at most `limit`.
+    pub(crate) fn slice(&self, index: u64, limit: usize) -> &[E] {
+        let start = self.position(index).expect("index is compacted");
+        let start = start.min(self.entries.len());
+        let end = self.entries.len().min(start.saturating_add(limit));
+        &self.entries[start..end]
+    }
+
+    // Removes entries starting at `index`.
+    pub(crate) fn truncate(&mut self, index: u64) {
+        if let Some(pos) = self.position(index) {
+            self.entries.truncate(pos);
+        }
+    }
+
+    // Drops entries up to and including `index`, which are now part of a snapshot.
+    pub(crate) fn compact(&mut self, index: u64) {
+        if index <= self.snapshot_index {
+            return;
+        }
+        let len = (index - self.snapshot_index) as usize;
+        self.entries.drain(..len.min(self.entries.len()));
+        self.snapshot_index = index;
+    }
+
+    fn position(&self, index: u64) -> Option<usize> {
+        (index > self.snapshot_index).then(|| (index - self.snapshot_index - 1) as usize)
+    }
+}
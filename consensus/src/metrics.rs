@@ -0,0 +1,68 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+// Counters for comparing engines under the same workload.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    commands: AtomicU64,
+    latency_micros_total: AtomicU64,
+    latency_micros_max: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    // Commands submitted on this node and applied.
+    pub commands: u64,
+    pub mean_latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl Metrics {
+    pub(crate) fn record_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_received(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_command(&self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        self.commands.fetch_add(1, Ordering::Relaxed);
+        self.latency_micros_total
+            .fetch_add(micros, Ordering::Relaxed);
+        self.latency_micros_max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let commands = self.commands.load(Ordering::Relaxed);
+        let total = self.latency_micros_total.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            commands,
+            mean_latency: Duration::from_micros(total.checked_div(commands).unwrap_or(0)),
+            max_latency: Duration::from_micros(self.latency_micros_max.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl std::fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} messages sent, {} received, {} commands (mean latency {:?}, max {:?})",
+            self.messages_sent,
+            self.messages_received,
+            self.commands,
+            self.mean_latency,
+            self.max_latency
+        )
+    }
+}
@@ -0,0 +1,851 @@
+use std::{
+    cmp,
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use base::{
+    client::Client,
+    node::NodeId,
+    utils::{async_spawn, every},
+};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    log::Log,
+    replica::{self, wait_applied, Peer, Replica},
+    Engine, Metrics, StateMachine, SubmitError,
+};
+
+// Proposal number, unique across nodes. Ordered by round first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ballot {
+    round: u64,
+    node_id: NodeId,
+}
+
+// Value accepted by an acceptor for a slot.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Accepted<C> {
+    ballot: Ballot,
+    command: C,
+}
+
+// Everything an acceptor accepted starting at `first_slot`. Slots before it (down to
+// the requested one) are chosen and covered by the snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Promise<C, Snap> {
+    chosen: u64,
+    snapshot_slot: u64,
+    snapshot: Option<Snap>,
+    first_slot: u64,
+    accepted: Vec<Accepted<C>>,
+}
+
+impl<C, Snap> Promise<C, Snap> {
+    fn get(&self, slot: u64) -> Option<&Accepted<C>> {
+        slot.checked_sub(self.first_slot)
+            .and_then(|pos| self.accepted.get(pos as usize))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PaxosRequest<C, Snap> {
+    // Phase 1 for all slots starting at `from_slot`.
+    Prepare {
+        ballot: Ballot,
+        from_slot: u64,
+    },
+    // Phase 2 for slots following `prev_slot`, also tells which slots are chosen.
+    Accept {
+        ballot: Ballot,
+        prev_slot: u64,
+        commands: Vec<C>,
+        chosen: u64,
+    },
+    // Catches up an acceptor with chosen slots that were compacted.
+    Install {
+        ballot: Ballot,
+        slot: u64,
+        snapshot: Snap,
+    },
+}
+
+// `ballot` is the highest ballot the acceptor promised to, request was rejected if it
+// doesn't match the one sent.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PaxosResponse<C, Snap> {
+    PrepareOk {
+        ballot: Ballot,
+        promise: Option<Promise<C, Snap>>,
+    },
+    AcceptOk {
+        ballot: Ballot,
+        success: bool,
+        // On success: last slot accepted in this ballot. On failure: hint for the leader
+        // where to continue.
+        slot: u64,
+    },
+    InstallOk {
+        ballot: Ballot,
+    },
+}
+
+type Request<S> = PaxosRequest<<S as StateMachine>::Command, <S as StateMachine>::Snapshot>;
+type Response<S> = PaxosResponse<<S as StateMachine>::Command, <S as StateMachine>::Snapshot>;
+
+// Multi-Paxos with a stable leader. A node that suspects the leader failed runs phase 1
+// with a higher ballot for all undecided slots at once, re-proposes values it learned
+// from a majority of acceptors and then runs only phase 2 for new commands. Unlike Raft,
+// any node can become the leader, up to date or not.
+pub struct Paxos<S: StateMachine> {
+    state: Mutex<State<S>>,
+    client: Client<Request<S>, Response<S>>,
+    metrics: Metrics,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+struct State<S: StateMachine> {
+    node_id: NodeId,
+    quorum: usize,
+    role: Role,
+    // Acceptor's promise, also the ballot of a candidate or leader.
+    promised: Option<Ballot>,
+    leader_id: Option<NodeId>,
+    log: Log<Accepted<S::Command>>,
+    // All slots up to this one are chosen and known to this node.
+    chosen: u64,
+    election_deadline: Instant,
+    promises: HashMap<NodeId, Promise<S::Command, S::Snapshot>>,
+    peers: HashMap<NodeId, Peer>,
+    replica: Replica<S>,
+}
+
+impl<S: StateMachine> Paxos<S> {
+    fn lock(&self) -> MutexGuard<'_, State<S>> {
+        self.state.lock().expect("lock panic")
+    }
+
+    fn tick(self: &Arc<Self>) {
+        let mut state = self.lock();
+        match state.role {
+            Role::Leader => {
+                let requests = state.replication_requests();
+                drop(state);
+                for (peer, request) in requests {
+                    self.send_replication(peer, request);
+                }
+            }
+            Role::Follower | Role::Candidate if Instant::now() >= state.election_deadline => {
+                let request = state.start_election();
+                let peers = state.peers.keys().cloned().collect::<Vec<_>>();
+                drop(state);
+                for peer in peers {
+                    self.send_prepare(peer, request.clone());
+                }
+            }
+            Role::Follower | Role::Candidate => {}
+        }
+    }
+
+    fn send_prepare(self: &Arc<Self>, peer: NodeId, request: Request<S>) {
+        let PaxosRequest::Prepare { ballot, .. } = &request else {
+            unreachable!("not a prepare request");
+        };
+
+        let sent_ballot = ballot.clone();
+        let paxos = self.clone();
+        self.metrics.record_sent();
+        async_spawn(self.client.node().shutdown(), async move {
+            let response = paxos.client.send(peer.clone(), request).await?;
+            paxos.metrics.record_received();
+            if let PaxosResponse::PrepareOk { ballot, promise } = response {
+                paxos
+                    .lock()
+                    .handle_promise(sent_ballot, peer, ballot, promise);
+            }
+            Ok(())
+        });
+    }
+
+    // Sends either commands or snapshot, at most one request in flight per peer.
+    fn send_replication(self: &Arc<Self>, peer: NodeId, request: Request<S>) {
+        let (sent_ballot, snapshot_slot) = match &request {
+            PaxosRequest::Accept { ballot, .. } => (ballot.clone(), None),
+            PaxosRequest::Install { ballot, slot, .. } => (ballot.clone(), Some(*slot)),
+            PaxosRequest::Prepare { .. } => unreachable!("not a replication request"),
+        };
+
+        let paxos = self.clone();
+        self.metrics.record_sent();
+        async_spawn(self.client.node().shutdown(), async move {
+            let result = paxos.client.send(peer.clone(), request).await;
+
+            let mut state = paxos.lock();
+            if let Some(peer) = state.peers.get_mut(&peer) {
+                peer.in_flight = false;
+            }
+            let response = result?;
+            paxos.metrics.record_received();
+            match response {
+                PaxosResponse::AcceptOk {
+                    ballot,
+                    success,
+                    slot,
+                } => state.handle_accept_result(sent_ballot, peer, ballot, success, slot),
+                PaxosResponse::InstallOk { ballot } => {
+                    let slot = snapshot_slot.expect("snapshot was sent");
+                    state.handle_accept_result(sent_ballot, peer, ballot, true, slot);
+                }
+                PaxosResponse::PrepareOk { .. } => {}
+            }
+            Ok(())
+        });
+    }
+}
+
+impl<S: StateMachine> Engine for Paxos<S> {
+    type Machine = S;
+    type Request = Request<S>;
+    type Response = Response<S>;
+
+    const NAME: &'static str = "paxos";
+
+    fn new(client: &Client<Self::Request, Self::Response>, machine: S) -> Self {
+        let node = client.node();
+        Self {
+            state: Mutex::new(State::new(node.node_id(), node.node_ids(), machine)),
+            client: client.clone(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    fn start(self: &Arc<Self>) {
+        let weak = Arc::downgrade(self);
+        every(self.client.node().shutdown(), replica::TICK, move || {
+            let weak = weak.clone();
+            async move {
+                if let Some(paxos) = weak.upgrade() {
+                    paxos.tick();
+                }
+                Ok(())
+            }
+        });
+    }
+
+    fn submit(&self, command: S::Command) -> BoxFuture<'_, Result<S::Output, SubmitError>> {
+        async move {
+            let rx = {
+                let mut state = self.lock();
+                if state.role != Role::Leader {
+                    return Err(SubmitError::NotLeader(state.leader_id.clone()));
+                }
+
+                let ballot = state.ballot().clone();
+                let round = ballot.round;
+                let slot = state.log.push(Accepted { ballot, command });
+                let rx = state.replica.wait(slot, round);
+                // Chosen right away in a single node cluster.
+                state.advance_chosen();
+                rx
+            };
+            wait_applied(rx, &self.metrics).await
+        }
+        .boxed()
+    }
+
+    fn handle(&self, _from: NodeId, request: Self::Request) -> Self::Response {
+        self.metrics.record_received();
+        self.metrics.record_sent();
+
+        let mut state = self.lock();
+        match request {
+            PaxosRequest::Prepare { ballot, from_slot } => state.handle_prepare(ballot, from_slot),
+            PaxosRequest::Accept {
+                ballot,
+                prev_slot,
+                commands,
+                chosen,
+            } => state.handle_accept(ballot, prev_slot, commands, chosen),
+            PaxosRequest::Install {
+                ballot,
+                slot,
+                snapshot,
+            } => state.handle_install(ballot, slot, snapshot),
+        }
+    }
+
+    fn leader(&self) -> Option<NodeId> {
+        self.lock().leader_id.clone()
+    }
+
+    fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+impl<S: StateMachine> State<S> {
+    fn new(node_id: &NodeId, node_ids: &[NodeId], machine: S) -> Self {
+        let peers = node_ids
+            .iter()
+            .filter(|peer| *peer != node_id)
+            .map(|peer| (peer.clone(), Peer::default()))
+            .collect();
+
+        Self {
+            node_id: node_id.clone(),
+            quorum: node_ids.len() / 2 + 1,
+            role: Role::Follower,
+            promised: None,
+            leader_id: None,
+            log: Log::new(),
+            chosen: 0,
+            election_deadline: replica::election_deadline(),
+            promises: HashMap::new(),
+            peers,
+            replica: Replica::new(machine),
+        }
+    }
+
+    // Ballot of a candidate or leader.
+    fn ballot(&self) -> &Ballot {
+        self.promised.as_ref().expect("ballot is chosen")
+    }
+
+    fn is_promised(&self, ballot: &Ballot) -> bool {
+        Some(ballot) >= self.promised.as_ref()
+    }
+
+    // Promises to ignore everything older than `ballot`.
+    fn promise(&mut self, ballot: Ballot) {
+        if self.role != Role::Follower && *self.ballot() != ballot {
+            log::info!(
+                "becoming follower, ballot {} by {}",
+                ballot.round,
+                ballot.node_id
+            );
+            if self.role == Role::Leader {
+                // Rounds aren't unique across nodes, so waiters can't tell our proposals
+                // from the next leader's ones.
+                self.replica.drop_waiters(self.chosen + 1);
+            }
+            self.role = Role::Follower;
+        }
+        self.promised = Some(ballot);
+        self.election_deadline = replica::election_deadline();
+    }
+
+    fn start_election(&mut self) -> Request<S> {
+        let round = self.promised.as_ref().map_or(0, |ballot| ballot.round) + 1;
+        log::info!("starting election for round {round}");
+        let ballot = Ballot {
+            round,
+            node_id: self.node_id.clone(),
+        };
+
+        self.role = Role::Candidate;
+        self.promised = Some(ballot.clone());
+        self.leader_id = None;
+        self.election_deadline = replica::election_deadline();
+
+        let from_slot = self.chosen + 1;
+        let promise = self.accepted_since(from_slot);
+        self.promises = HashMap::from([(self.node_id.clone(), promise)]);
+        if self.promises.len() >= self.quorum {
+            self.become_leader();
+        }
+
+        PaxosRequest::Prepare { ballot, from_slot }
+    }
+
+    fn accepted_since(&self, from_slot: u64) -> Promise<S::Command, S::Snapshot> {
+        let snapshot_slot = self.log.snapshot_index();
+        let snapshot = (snapshot_slot >= from_slot)
+            .then(|| self.replica.snapshot().expect("log is compacted").clone());
+        let first_slot = cmp::max(from_slot, snapshot_slot + 1);
+        Promise {
+            chosen: self.chosen,
+            snapshot_slot,
+            snapshot,
+            first_slot,
+            accepted: self.log.slice(first_slot, usize::MAX).to_vec(),
+        }
+    }
+
+    fn handle_prepare(&mut self, ballot: Ballot, from_slot: u64) -> Response<S> {
+        if !self.is_promised(&ballot) {
+            return PaxosResponse::PrepareOk {
+                ballot: self.ballot().clone(),
+                promise: None,
+            };
+        }
+
+        self.promise(ballot.clone());
+        self.leader_id = None;
+        PaxosResponse::PrepareOk {
+            ballot,
+            promise: Some(self.accepted_since(from_slot)),
+        }
+    }
+
+    fn handle_promise(
+        &mut self,
+        sent_ballot: Ballot,
+        peer: NodeId,
+        ballot: Ballot,
+        promise: Option<Promise<S::Command, S::Snapshot>>,
+    ) {
+        if Some(&ballot) > self.promised.as_ref() {
+            self.promise(ballot);
+            self.leader_id = None;
+            return;
+        }
+        if self.role != Role::Candidate || *self.ballot() != sent_ballot || ballot != sent_ballot {
+            return;
+        }
+
+        if let Some(promise) = promise {
+            self.promises.insert(peer, promise);
+            if self.promises.len() >= self.quorum {
+                self.become_leader();
+            }
+        }
+    }
+
+    fn become_leader(&mut self) {
+        let ballot = self.ballot().clone();
+        log::info!("becoming leader in round {}", ballot.round);
+        let promises = std::mem::take(&mut self.promises);
+
+        // Snapshots only contain chosen slots.
+        let latest_snapshot = promises
+            .values()
+            .filter_map(|promise| Some((promise.snapshot_slot, promise.snapshot.as_ref()?)))
+            .max_by_key(|(slot, _)| *slot);
+        if let Some((slot, snapshot)) = latest_snapshot {
+            if slot > self.chosen {
+                self.replica.restore(&mut self.log, slot, snapshot.clone());
+                self.chosen = slot;
+            }
+        }
+
+        // Re-propose value accepted in the highest ballot for every undecided slot. Logs
+        // of acceptors have no gaps, so neither does the result.
+        let mut commands = Vec::new();
+        for slot in self.chosen + 1.. {
+            let highest = promises
+                .values()
+                .filter_map(|promise| promise.get(slot))
+                .max_by(|a, b| a.ballot.cmp(&b.ballot));
+            match highest {
+                Some(accepted) => commands.push(accepted.command.clone()),
+                None => break,
+            }
+        }
+
+        self.log.truncate(self.chosen + 1);
+        self.replica.drop_waiters(self.chosen + 1);
+        for command in commands {
+            self.log.push(Accepted {
+                ballot: ballot.clone(),
+                command,
+            });
+        }
+
+        let last_slot = self.log.last_index();
+        for (node_id, peer) in &mut self.peers {
+            let known_chosen = promises.get(node_id).map_or(self.chosen, |p| p.chosen);
+            *peer = Peer::new(cmp::min(known_chosen, last_slot) + 1);
+        }
+
+        self.role = Role::Leader;
+        self.leader_id = Some(self.node_id.clone());
+        self.advance_chosen();
+    }
+
+    fn handle_accept(
+        &mut self,
+        ballot: Ballot,
+        prev_slot: u64,
+        commands: Vec<S::Command>,
+        leader_chosen: u64,
+    ) -> Response<S> {
+        if !self.is_promised(&ballot) {
+            return PaxosResponse::AcceptOk {
+                ballot: self.ballot().clone(),
+                success: false,
+                slot: 0,
+            };
+        }
+        self.promise(ballot.clone());
+        self.leader_id = Some(ballot.node_id.clone());
+
+        // Undecided slots before the new ones must already be accepted in this ballot,
+        // otherwise they might hold values from older ballots.
+        for slot in self.chosen + 1..=prev_slot {
+            if self
+                .log
+                .get(slot)
+                .is_none_or(|accepted| accepted.ballot != ballot)
+            {
+                return PaxosResponse::AcceptOk {
+                    ballot,
+                    success: false,
+                    slot: slot - 1,
+                };
+            }
+        }
+
+        let last_slot = prev_slot + commands.len() as u64;
+        for (slot, command) in (prev_slot + 1..).zip(commands) {
+            if slot <= self.chosen {
+                continue;
+            }
+            let accepted = Accepted {
+                ballot: ballot.clone(),
+                command,
+            };
+            match self.log.get_mut(slot) {
+                Some(existing) => *existing = accepted,
+                None => {
+                    self.log.push(accepted);
+                }
+            }
+        }
+
+        // Slots up to `last_slot` hold leader's values.
+        let chosen = cmp::min(leader_chosen, last_slot);
+        if chosen > self.chosen {
+            self.chosen = chosen;
+            self.apply_chosen();
+        }
+
+        PaxosResponse::AcceptOk {
+            ballot,
+            success: true,
+            slot: last_slot,
+        }
+    }
+
+    fn handle_install(&mut self, ballot: Ballot, slot: u64, snapshot: S::Snapshot) -> Response<S> {
+        if !self.is_promised(&ballot) {
+            return PaxosResponse::InstallOk {
+                ballot: self.ballot().clone(),
+            };
+        }
+        self.promise(ballot.clone());
+        self.leader_id = Some(ballot.node_id.clone());
+
+        if slot > self.chosen {
+            self.replica.restore(&mut self.log, slot, snapshot);
+            self.chosen = slot;
+        }
+        PaxosResponse::InstallOk { ballot }
+    }
+
+    fn replication_requests(&mut self) -> Vec<(NodeId, Request<S>)> {
+        let now = Instant::now();
+        let ballot = self.ballot().clone();
+        let last_slot = self.log.last_index();
+        let snapshot_slot = self.log.snapshot_index();
+
+        let mut requests = Vec::new();
+        for (node_id, peer) in &mut self.peers {
+            if !peer.poll_send(last_slot, now) {
+                continue;
+            }
+
+            let request = if peer.next_index <= snapshot_slot {
+                let snapshot = self.replica.snapshot().expect("log is compacted");
+                PaxosRequest::Install {
+                    ballot: ballot.clone(),
+                    slot: snapshot_slot,
+                    snapshot: snapshot.clone(),
+                }
+            } else {
+                let commands = self
+                    .log
+                    .slice(peer.next_index, replica::MAX_ENTRIES_PER_REQUEST)
+                    .iter()
+                    .map(|accepted| accepted.command.clone())
+                    .collect();
+                PaxosRequest::Accept {
+                    ballot: ballot.clone(),
+                    prev_slot: peer.next_index - 1,
+                    commands,
+                    chosen: self.chosen,
+                }
+            };
+            requests.push((node_id.clone(), request));
+        }
+        requests
+    }
+
+    fn handle_accept_result(
+        &mut self,
+        sent_ballot: Ballot,
+        peer: NodeId,
+        ballot: Ballot,
+        success: bool,
+        slot: u64,
+    ) {
+        if Some(&ballot) > self.promised.as_ref() {
+            self.promise(ballot);
+            self.leader_id = None;
+            return;
+        }
+        if self.role != Role::Leader || *self.ballot() != sent_ballot || ballot != sent_ballot {
+            return;
+        }
+        let Some(peer) = self.peers.get_mut(&peer) else {
+            return;
+        };
+
+        if success {
+            peer.match_index = cmp::max(peer.match_index, slot);
+            peer.next_index = peer.match_index + 1;
+            self.advance_chosen();
+        } else {
+            peer.next_index = cmp::max(1, cmp::min(peer.next_index - 1, slot + 1));
+        }
+    }
+
+    // Every undecided slot in leader's log was proposed in its ballot, so a slot is
+    // chosen once a majority accepted it.
+    fn advance_chosen(&mut self) {
+        for slot in (self.chosen + 1..=self.log.last_index()).rev() {
+            let acceptors = 1 + self
+                .peers
+                .values()
+                .filter(|peer| peer.match_index >= slot)
+                .count();
+            if acceptors >= self.quorum {
+                self.chosen = slot;
+                break;
+            }
+        }
+        self.apply_chosen();
+    }
+
+    fn apply_chosen(&mut self) {
+        self.replica
+            .apply_committed(&self.log, self.chosen, |accepted| {
+                (accepted.ballot.round, &accepted.command)
+            });
+        self.replica.maybe_compact(&mut self.log);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::sim::node_id;
+
+    use super::*;
+
+    // Records applied commands.
+    #[derive(Debug, Default)]
+    struct Recorder(Vec<u64>);
+
+    impl StateMachine for Recorder {
+        type Command = u64;
+        type Output = ();
+        type Snapshot = Vec<u64>;
+
+        fn apply(&mut self, command: &u64) {
+            self.0.push(*command);
+        }
+
+        fn snapshot(&self) -> Vec<u64> {
+            self.0.clone()
+        }
+
+        fn restore(&mut self, snapshot: Vec<u64>) {
+            self.0 = snapshot;
+        }
+    }
+
+    fn cluster(size: usize) -> Vec<State<Recorder>> {
+        let node_ids: Vec<_> = (0..size).map(|i| node_id(&format!("n{i}"))).collect();
+        node_ids
+            .iter()
+            .map(|id| State::new(id, &node_ids, Recorder::default()))
+            .collect()
+    }
+
+    fn ballot(round: u64, node: &str) -> Ballot {
+        Ballot {
+            round,
+            node_id: node_id(node),
+        }
+    }
+
+    fn accept(state: &mut State<Recorder>, ballot: &Ballot, command: u64) {
+        state.log.push(Accepted {
+            ballot: ballot.clone(),
+            command,
+        });
+    }
+
+    fn commands(state: &State<Recorder>) -> Vec<u64> {
+        (1..=state.log.last_index())
+            .map(|slot| state.log.get(slot).unwrap().command)
+            .collect()
+    }
+
+    fn applied(state: &State<Recorder>) -> &[u64] {
+        &state.replica.machine().0
+    }
+
+    // Runs phase 1 with `acceptors`, returns whether `candidate` became leader.
+    fn elect(cluster: &mut [State<Recorder>], candidate: usize, acceptors: &[usize]) -> bool {
+        let PaxosRequest::Prepare { ballot, from_slot } = cluster[candidate].start_election()
+        else {
+            unreachable!("not a prepare request");
+        };
+
+        for &acceptor in acceptors {
+            let PaxosResponse::PrepareOk {
+                ballot: promised,
+                promise,
+            } = cluster[acceptor].handle_prepare(ballot.clone(), from_slot)
+            else {
+                unreachable!("not a prepare response");
+            };
+            let acceptor_id = cluster[acceptor].node_id.clone();
+            cluster[candidate].handle_promise(ballot.clone(), acceptor_id, promised, promise);
+        }
+        cluster[candidate].role == Role::Leader
+    }
+
+    // Sends replication requests from `leader` to `acceptors` and their responses back.
+    fn replicate(cluster: &mut [State<Recorder>], leader: usize, acceptors: &[usize]) {
+        // Heartbeats are due, whatever is in flight.
+        for peer in cluster[leader].peers.values_mut() {
+            peer.last_sent = None;
+        }
+        let requests = cluster[leader].replication_requests();
+        for (peer, request) in requests {
+            let Some(&acceptor) = acceptors
+                .iter()
+                .find(|acceptor| cluster[**acceptor].node_id == peer)
+            else {
+                continue;
+            };
+            let PaxosRequest::Accept {
+                ballot,
+                prev_slot,
+                commands,
+                chosen,
+            } = request
+            else {
+                unreachable!("not an accept request");
+            };
+
+            let response =
+                cluster[acceptor].handle_accept(ballot.clone(), prev_slot, commands, chosen);
+            let PaxosResponse::AcceptOk {
+                ballot: promised,
+                success,
+                slot,
+            } = response
+            else {
+                unreachable!("not an accept response");
+            };
+            let leader_state = &mut cluster[leader];
+            if let Some(peer) = leader_state.peers.get_mut(&peer) {
+                peer.in_flight = false;
+            }
+            leader_state.handle_accept_result(ballot, peer, promised, success, slot);
+        }
+    }
+
+    fn submit(state: &mut State<Recorder>, command: u64) {
+        let ballot = state.ballot().clone();
+        accept(state, &ballot, command);
+        state.advance_chosen();
+    }
+
+    #[test]
+    fn new_leader_proposes_values_accepted_in_highest_ballot() {
+        let mut cluster = cluster(3);
+        // n0 accepted its own values in round 1, then n1 got another one accepted by n2
+        // for slot 1 in round 2.
+        accept(&mut cluster[0], &ballot(1, "n0"), 10);
+        accept(&mut cluster[0], &ballot(1, "n0"), 11);
+        cluster[0].promised = Some(ballot(1, "n0"));
+        accept(&mut cluster[2], &ballot(2, "n1"), 20);
+        cluster[2].promised = Some(ballot(2, "n1"));
+
+        // n0 learns about round 2 from the rejection, then tries round 3.
+        assert!(!elect(&mut cluster, 0, &[2]));
+        assert!(elect(&mut cluster, 0, &[2]));
+        assert_eq!(cluster[0].ballot().round, 3);
+        assert_eq!(commands(&cluster[0]), vec![20, 11]);
+
+        // Re-proposed in the new ballot, chosen once a majority accepts.
+        assert_eq!(cluster[0].chosen, 0);
+        replicate(&mut cluster, 0, &[2]);
+        assert_eq!(cluster[0].chosen, 2);
+        assert_eq!(applied(&cluster[0]), [20, 11]);
+        assert_eq!(cluster[2].log.get(1).unwrap().ballot, ballot(3, "n0"));
+    }
+
+    #[test]
+    fn lower_ballots_are_rejected() {
+        let mut cluster = cluster(3);
+        assert!(elect(&mut cluster, 0, &[1]));
+        submit(&mut cluster[0], 10);
+
+        // n2 takes over n1 with a higher ballot before n0 replicates anything.
+        assert!(elect(&mut cluster, 2, &[1]));
+        assert_eq!(cluster[2].ballot(), &ballot(1, "n2"));
+        replicate(&mut cluster, 0, &[1]);
+        assert!(cluster[1].log.get(1).is_none());
+        assert_eq!(cluster[0].role, Role::Follower);
+        assert_eq!(cluster[0].promised, Some(ballot(1, "n2")));
+
+        // Stale prepares get no promise.
+        let response = cluster[1].handle_prepare(ballot(1, "n0"), 1);
+        assert!(matches!(
+            response,
+            PaxosResponse::PrepareOk { promise: None, .. }
+        ));
+    }
+
+    #[test]
+    fn new_leader_fills_gaps_in_its_log() {
+        let mut cluster = cluster(3);
+        assert!(elect(&mut cluster, 0, &[1, 2]));
+        for command in [10, 11, 12] {
+            submit(&mut cluster[0], command);
+        }
+        // n2 misses everything, n1 learns that everything is chosen.
+        replicate(&mut cluster, 0, &[1]);
+        replicate(&mut cluster, 0, &[1]);
+        assert_eq!(cluster[1].chosen, 3);
+
+        // n2 is behind, yet learns chosen slots from n1 once it takes over.
+        assert!(elect(&mut cluster, 2, &[1]));
+        assert_eq!(commands(&cluster[2]), vec![10, 11, 12]);
+        submit(&mut cluster[2], 13);
+        replicate(&mut cluster, 2, &[1]);
+        assert_eq!(cluster[2].chosen, 4);
+        assert_eq!(applied(&cluster[2]), [10, 11, 12, 13]);
+
+        // n0 missed the leader change, its log gets overwritten past chosen slots.
+        replicate(&mut cluster, 2, &[0]);
+        assert_eq!(commands(&cluster[0]), vec![10, 11, 12, 13]);
+        replicate(&mut cluster, 2, &[0]);
+        assert_eq!(applied(&cluster[0]), [10, 11, 12, 13]);
+    }
+}
@@ -0,0 +1,920 @@
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use base::{
+    client::Client,
+    node::NodeId,
+    utils::{async_spawn, every},
+};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    log::Log,
+    replica::{self, wait_applied, Peer, Replica},
+    Engine, Metrics, StateMachine, SubmitError,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry<C> {
+    term: u64,
+    command: C,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RaftRequest<C, Snap> {
+    RequestVote {
+        term: u64,
+        candidate_id: NodeId,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    AppendEntries {
+        term: u64,
+        leader_id: NodeId,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry<C>>,
+        leader_commit: u64,
+    },
+    InstallSnapshot {
+        term: u64,
+        leader_id: NodeId,
+        last_included_index: u64,
+        last_included_term: u64,
+        snapshot: Snap,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RaftResponse {
+    RequestVoteOk {
+        term: u64,
+        vote_granted: bool,
+    },
+    AppendEntriesOk {
+        term: u64,
+        success: bool,
+        // On success: last index known to match leader's log. On failure: hint
+        // for the leader where to continue looking for a match.
+        match_index: u64,
+    },
+    InstallSnapshotOk {
+        term: u64,
+    },
+}
+
+type Request<S> = RaftRequest<<S as StateMachine>::Command, <S as StateMachine>::Snapshot>;
+
+// Raft consensus over Maelstrom network. Commands submitted to the leader are applied to
+// the state machine on every node in the same order, once a majority has them in the log.
+pub struct Raft<S: StateMachine> {
+    state: Mutex<State<S>>,
+    client: Client<Request<S>, RaftResponse>,
+    metrics: Metrics,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+struct State<S: StateMachine> {
+    node_id: NodeId,
+    quorum: usize,
+    role: Role,
+    term: u64,
+    voted_for: Option<NodeId>,
+    leader_id: Option<NodeId>,
+    log: Log<Entry<S::Command>>,
+    // Term of the last entry covered by snapshot.
+    snapshot_term: u64,
+    commit_index: u64,
+    election_deadline: Instant,
+    votes: HashSet<NodeId>,
+    peers: HashMap<NodeId, Peer>,
+    replica: Replica<S>,
+}
+
+impl<S: StateMachine> Raft<S> {
+    fn lock(&self) -> MutexGuard<'_, State<S>> {
+        self.state.lock().expect("lock panic")
+    }
+
+    fn tick(self: &Arc<Self>) {
+        let mut state = self.lock();
+        match state.role {
+            Role::Leader => {
+                let requests = state.replication_requests();
+                drop(state);
+                for (peer, request) in requests {
+                    self.send_replication(peer, request);
+                }
+            }
+            Role::Follower | Role::Candidate if Instant::now() >= state.election_deadline => {
+                log::info!("starting election for term {}", state.term + 1);
+                let request = state.start_election();
+                let peers = state.peers.keys().cloned().collect::<Vec<_>>();
+                drop(state);
+                for peer in peers {
+                    self.send_request_vote(peer, request.clone());
+                }
+            }
+            Role::Follower | Role::Candidate => {}
+        }
+    }
+
+    fn send_request_vote(self: &Arc<Self>, peer: NodeId, request: Request<S>) {
+        let RaftRequest::RequestVote {
+            term: sent_term, ..
+        } = &request
+        else {
+            unreachable!("not a vote request");
+        };
+
+        let sent_term = *sent_term;
+        let raft = self.clone();
+        self.metrics.record_sent();
+        async_spawn(self.client.node().shutdown(), async move {
+            let response = raft.client.send(peer.clone(), request).await?;
+            raft.metrics.record_received();
+            if let RaftResponse::RequestVoteOk { term, vote_granted } = response {
+                raft.lock().handle_vote(sent_term, peer, term, vote_granted);
+            }
+            Ok(())
+        });
+    }
+
+    // Sends either entries or snapshot, at most one request in flight per peer.
+    fn send_replication(self: &Arc<Self>, peer: NodeId, request: Request<S>) {
+        let (sent_term, snapshot_index) = match &request {
+            RaftRequest::AppendEntries { term, .. } => (*term, None),
+            RaftRequest::InstallSnapshot {
+                term,
+                last_included_index,
+                ..
+            } => (*term, Some(*last_included_index)),
+            RaftRequest::RequestVote { .. } => unreachable!("not a replication request"),
+        };
+
+        let raft = self.clone();
+        self.metrics.record_sent();
+        async_spawn(self.client.node().shutdown(), async move {
+            let result = raft.client.send(peer.clone(), request).await;
+
+            let mut state = raft.lock();
+            if let Some(peer) = state.peers.get_mut(&peer) {
+                peer.in_flight = false;
+            }
+            let response = result?;
+            raft.metrics.record_received();
+            match response {
+                RaftResponse::AppendEntriesOk {
+                    term,
+                    success,
+                    match_index,
+                } => state.handle_append_result(sent_term, peer, term, success, match_index),
+                RaftResponse::InstallSnapshotOk { term } => {
+                    let index = snapshot_index.expect("snapshot was sent");
+                    state.handle_append_result(sent_term, peer, term, true, index);
+                }
+                RaftResponse::RequestVoteOk { .. } => {}
+            }
+            Ok(())
+        });
+    }
+}
+
+impl<S: StateMachine> Engine for Raft<S> {
+    type Machine = S;
+    type Request = Request<S>;
+    type Response = RaftResponse;
+
+    const NAME: &'static str = "raft";
+
+    fn new(client: &Client<Self::Request, Self::Response>, machine: S) -> Self {
+        let node = client.node();
+        Self {
+            state: Mutex::new(State::new(node.node_id(), node.node_ids(), machine)),
+            client: client.clone(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    fn start(self: &Arc<Self>) {
+        let weak = Arc::downgrade(self);
+        every(self.client.node().shutdown(), replica::TICK, move || {
+            let weak = weak.clone();
+            async move {
+                if let Some(raft) = weak.upgrade() {
+                    raft.tick();
+                }
+                Ok(())
+            }
+        });
+    }
+
+    fn submit(&self, command: S::Command) -> BoxFuture<'_, Result<S::Output, SubmitError>> {
+        async move {
+            let rx = {
+                let mut state = self.lock();
+                if state.role != Role::Leader {
+                    return Err(SubmitError::NotLeader(state.leader_id.clone()));
+                }
+
+                let term = state.term;
+                let index = state.log.push(Entry { term, command });
+                let rx = state.replica.wait(index, term);
+                // Commits right away in a single node cluster.
+                state.advance_commit_index();
+                rx
+            };
+            wait_applied(rx, &self.metrics).await
+        }
+        .boxed()
+    }
+
+    fn handle(&self, _from: NodeId, request: Self::Request) -> RaftResponse {
+        self.metrics.record_received();
+        self.metrics.record_sent();
+
+        let mut state = self.lock();
+        match request {
+            RaftRequest::RequestVote {
+                term,
+                candidate_id,
+                last_log_index,
+                last_log_term,
+            } => state.handle_request_vote(term, candidate_id, last_log_index, last_log_term),
+            RaftRequest::AppendEntries {
+                term,
+                leader_id,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => state.handle_append_entries(
+                term,
+                leader_id,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            ),
+            RaftRequest::InstallSnapshot {
+                term,
+                leader_id,
+                last_included_index,
+                last_included_term,
+                snapshot,
+            } => state.handle_install_snapshot(
+                term,
+                leader_id,
+                last_included_index,
+                last_included_term,
+                snapshot,
+            ),
+        }
+    }
+
+    fn leader(&self) -> Option<NodeId> {
+        self.lock().leader_id.clone()
+    }
+
+    fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+impl<S: StateMachine> State<S> {
+    fn new(node_id: &NodeId, node_ids: &[NodeId], machine: S) -> Self {
+        let peers = node_ids
+            .iter()
+            .filter(|peer| *peer != node_id)
+            .map(|peer| (peer.clone(), Peer::default()))
+            .collect();
+
+        Self {
+            node_id: node_id.clone(),
+            quorum: node_ids.len() / 2 + 1,
+            role: Role::Follower,
+            term: 0,
+            voted_for: None,
+            leader_id: None,
+            log: Log::new(),
+            snapshot_term: 0,
+            commit_index: 0,
+            election_deadline: replica::election_deadline(),
+            votes: HashSet::new(),
+            peers,
+            replica: Replica::new(machine),
+        }
+    }
+
+    fn last_term(&self) -> u64 {
+        self.log
+            .last()
+            .map(|entry| entry.term)
+            .unwrap_or(self.snapshot_term)
+    }
+
+    // `None` for compacted and missing entries.
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.log.snapshot_index() {
+            return Some(self.snapshot_term);
+        }
+        self.log.get(index).map(|entry| entry.term)
+    }
+
+    fn become_follower(&mut self, term: u64) {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.leader_id = None;
+        }
+        if self.role != Role::Follower {
+            log::info!("becoming follower in term {}", self.term);
+            self.role = Role::Follower;
+        }
+        self.election_deadline = replica::election_deadline();
+    }
+
+    fn become_leader(&mut self) {
+        log::info!("becoming leader in term {}", self.term);
+        self.role = Role::Leader;
+        self.leader_id = Some(self.node_id.clone());
+        let next_index = self.log.last_index() + 1;
+        for peer in self.peers.values_mut() {
+            *peer = Peer::new(next_index);
+        }
+    }
+
+    fn start_election(&mut self) -> Request<S> {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.node_id.clone());
+        self.leader_id = None;
+        self.votes = HashSet::from([self.node_id.clone()]);
+        self.election_deadline = replica::election_deadline();
+        if self.votes.len() >= self.quorum {
+            self.become_leader();
+        }
+
+        RaftRequest::RequestVote {
+            term: self.term,
+            candidate_id: self.node_id.clone(),
+            last_log_index: self.log.last_index(),
+            last_log_term: self.last_term(),
+        }
+    }
+
+    fn handle_vote(&mut self, sent_term: u64, peer: NodeId, term: u64, vote_granted: bool) {
+        if term > self.term {
+            self.become_follower(term);
+            return;
+        }
+        if self.role != Role::Candidate || self.term != sent_term || !vote_granted {
+            return;
+        }
+
+        self.votes.insert(peer);
+        if self.votes.len() >= self.quorum {
+            self.become_leader();
+        }
+    }
+
+    fn handle_request_vote(
+        &mut self,
+        term: u64,
+        candidate_id: NodeId,
+        last_log_index: u64,
+        last_log_term: u64,
+    ) -> RaftResponse {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.leader_id = None;
+            self.role = Role::Follower;
+        }
+
+        let is_up_to_date =
+            (last_log_term, last_log_index) >= (self.last_term(), self.log.last_index());
+        let can_vote = self
+            .voted_for
+            .as_ref()
+            .is_none_or(|voted_for| *voted_for == candidate_id);
+        let vote_granted = term == self.term && can_vote && is_up_to_date;
+        if vote_granted {
+            self.voted_for = Some(candidate_id);
+            self.election_deadline = replica::election_deadline();
+        }
+
+        RaftResponse::RequestVoteOk {
+            term: self.term,
+            vote_granted,
+        }
+    }
+
+    fn handle_append_entries(
+        &mut self,
+        term: u64,
+        leader_id: NodeId,
+        mut prev_log_index: u64,
+        mut prev_log_term: u64,
+        mut entries: Vec<Entry<S::Command>>,
+        leader_commit: u64,
+    ) -> RaftResponse {
+        let reject = |term, match_index| RaftResponse::AppendEntriesOk {
+            term,
+            success: false,
+            match_index,
+        };
+
+        if term < self.term {
+            return reject(self.term, 0);
+        }
+        self.become_follower(term);
+        self.leader_id = Some(leader_id);
+
+        // Compacted entries are committed, so they match leader's log.
+        let snapshot_index = self.log.snapshot_index();
+        if prev_log_index < snapshot_index {
+            let skip = (snapshot_index - prev_log_index) as usize;
+            entries.drain(..skip.min(entries.len()));
+            prev_log_index = snapshot_index;
+            prev_log_term = self.snapshot_term;
+        }
+
+        match self.term_at(prev_log_index) {
+            Some(term) if term == prev_log_term => {}
+            Some(conflicting_term) => {
+                // Skip the whole conflicting term at once.
+                let mut first_index = prev_log_index;
+                while first_index - 1 > snapshot_index
+                    && self.term_at(first_index - 1) == Some(conflicting_term)
+                {
+                    first_index -= 1;
+                }
+                return reject(self.term, first_index - 1);
+            }
+            None => return reject(self.term, self.log.last_index()),
+        }
+
+        let last_new_index = prev_log_index + entries.len() as u64;
+        for (index, entry) in (prev_log_index + 1..).zip(entries) {
+            match self.term_at(index) {
+                Some(term) if term == entry.term => {}
+                Some(_) => {
+                    self.truncate(index);
+                    self.log.push(entry);
+                }
+                None => {
+                    self.log.push(entry);
+                }
+            }
+        }
+
+        // A stale request may know of fewer entries than committed already.
+        let commit_index = cmp::min(leader_commit, last_new_index);
+        if commit_index > self.commit_index {
+            self.commit_index = commit_index;
+            self.apply_committed();
+        }
+
+        RaftResponse::AppendEntriesOk {
+            term: self.term,
+            success: true,
+            match_index: last_new_index,
+        }
+    }
+
+    fn handle_install_snapshot(
+        &mut self,
+        term: u64,
+        leader_id: NodeId,
+        last_included_index: u64,
+        last_included_term: u64,
+        snapshot: S::Snapshot,
+    ) -> RaftResponse {
+        if term < self.term {
+            return RaftResponse::InstallSnapshotOk { term: self.term };
+        }
+        self.become_follower(term);
+        self.leader_id = Some(leader_id);
+
+        if last_included_index > self.replica.applied() {
+            // Entries following the snapshot are kept only if they match leader's log.
+            if self.term_at(last_included_index) != Some(last_included_term) {
+                let first_index = self.log.snapshot_index() + 1;
+                self.truncate(first_index);
+            }
+            self.replica
+                .restore(&mut self.log, last_included_index, snapshot);
+            self.snapshot_term = last_included_term;
+            self.commit_index = cmp::max(self.commit_index, last_included_index);
+        }
+
+        RaftResponse::InstallSnapshotOk { term: self.term }
+    }
+
+    // Removes entries starting at `index`.
+    fn truncate(&mut self, index: u64) {
+        self.log.truncate(index);
+        self.replica.drop_waiters(index);
+    }
+
+    fn replication_requests(&mut self) -> Vec<(NodeId, Request<S>)> {
+        let now = Instant::now();
+        let last_index = self.log.last_index();
+        let snapshot_index = self.log.snapshot_index();
+
+        let mut requests = Vec::new();
+        for (node_id, peer) in &mut self.peers {
+            if !peer.poll_send(last_index, now) {
+                continue;
+            }
+
+            let request = if peer.next_index <= snapshot_index {
+                let snapshot = self.replica.snapshot().expect("log is compacted");
+                RaftRequest::InstallSnapshot {
+                    term: self.term,
+                    leader_id: self.node_id.clone(),
+                    last_included_index: snapshot_index,
+                    last_included_term: self.snapshot_term,
+                    snapshot: snapshot.clone(),
+                }
+            } else {
+                let prev_log_index = peer.next_index - 1;
+                let prev_log_term = match prev_log_index {
+                    index if index == snapshot_index => self.snapshot_term,
+                    index => self.log.get(index).expect("entry exists").term,
+                };
+                let entries = self
+                    .log
+                    .slice(peer.next_index, replica::MAX_ENTRIES_PER_REQUEST)
+                    .to_vec();
+                RaftRequest::AppendEntries {
+                    term: self.term,
+                    leader_id: self.node_id.clone(),
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit: self.commit_index,
+                }
+            };
+            requests.push((node_id.clone(), request));
+        }
+        requests
+    }
+
+    fn handle_append_result(
+        &mut self,
+        sent_term: u64,
+        peer: NodeId,
+        term: u64,
+        success: bool,
+        match_index: u64,
+    ) {
+        if term > self.term {
+            self.become_follower(term);
+            return;
+        }
+        if self.role != Role::Leader || self.term != sent_term {
+            return;
+        }
+        let Some(peer) = self.peers.get_mut(&peer) else {
+            return;
+        };
+
+        if success {
+            peer.match_index = cmp::max(peer.match_index, match_index);
+            peer.next_index = peer.match_index + 1;
+            self.advance_commit_index();
+        } else {
+            peer.next_index = cmp::max(1, cmp::min(peer.next_index - 1, match_index + 1));
+        }
+    }
+
+    // Only entries from the current term are committed by counting replicas.
+    fn advance_commit_index(&mut self) {
+        for index in (self.commit_index + 1..=self.log.last_index()).rev() {
+            if self.term_at(index) != Some(self.term) {
+                break;
+            }
+
+            let replicas = 1 + self
+                .peers
+                .values()
+                .filter(|peer| peer.match_index >= index)
+                .count();
+            if replicas >= self.quorum {
+                self.commit_index = index;
+                break;
+            }
+        }
+        self.apply_committed();
+    }
+
+    fn apply_committed(&mut self) {
+        self.replica
+            .apply_committed(&self.log, self.commit_index, |entry| {
+                (entry.term, &entry.command)
+            });
+
+        let applied_term = self.term_at(self.replica.applied());
+        if self.replica.maybe_compact(&mut self.log) {
+            self.snapshot_term = applied_term.expect("applied entry exists");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::sim::node_id;
+
+    use super::*;
+
+    // Records applied commands.
+    #[derive(Debug, Default)]
+    struct Recorder(Vec<u64>);
+
+    impl StateMachine for Recorder {
+        type Command = u64;
+        type Output = ();
+        type Snapshot = Vec<u64>;
+
+        fn apply(&mut self, command: &u64) {
+            self.0.push(*command);
+        }
+
+        fn snapshot(&self) -> Vec<u64> {
+            self.0.clone()
+        }
+
+        fn restore(&mut self, snapshot: Vec<u64>) {
+            self.0 = snapshot;
+        }
+    }
+
+    fn cluster(size: usize) -> Vec<State<Recorder>> {
+        let node_ids: Vec<_> = (0..size).map(|i| node_id(&format!("n{i}"))).collect();
+        node_ids
+            .iter()
+            .map(|id| State::new(id, &node_ids, Recorder::default()))
+            .collect()
+    }
+
+    fn entries(terms: &[u64]) -> Vec<Entry<u64>> {
+        terms
+            .iter()
+            .enumerate()
+            .map(|(i, term)| Entry {
+                term: *term,
+                command: i as u64,
+            })
+            .collect()
+    }
+
+    fn log_terms(state: &State<Recorder>) -> Vec<u64> {
+        (1..=state.log.last_index())
+            .map(|index| state.term_at(index).unwrap())
+            .collect()
+    }
+
+    fn applied(state: &State<Recorder>) -> &[u64] {
+        &state.replica.machine().0
+    }
+
+    // Asks `voters` for their votes, returns whether `candidate` became leader.
+    fn elect(cluster: &mut [State<Recorder>], candidate: usize, voters: &[usize]) -> bool {
+        let RaftRequest::RequestVote {
+            term,
+            candidate_id,
+            last_log_index,
+            last_log_term,
+        } = cluster[candidate].start_election()
+        else {
+            unreachable!("not a vote request");
+        };
+
+        for &voter in voters {
+            let response = cluster[voter].handle_request_vote(
+                term,
+                candidate_id.clone(),
+                last_log_index,
+                last_log_term,
+            );
+            let RaftResponse::RequestVoteOk {
+                term: voter_term,
+                vote_granted,
+            } = response
+            else {
+                unreachable!("not a vote response");
+            };
+            let voter_id = cluster[voter].node_id.clone();
+            cluster[candidate].handle_vote(term, voter_id, voter_term, vote_granted);
+        }
+        cluster[candidate].role == Role::Leader
+    }
+
+    // Sends due replication requests from `leader` to `followers` and their responses back.
+    fn replicate(cluster: &mut [State<Recorder>], leader: usize, followers: &[usize]) {
+        let requests = cluster[leader].replication_requests();
+        for (peer, request) in requests {
+            let Some(&follower) = followers
+                .iter()
+                .find(|follower| cluster[**follower].node_id == peer)
+            else {
+                continue;
+            };
+            let RaftRequest::AppendEntries {
+                term,
+                leader_id,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } = request
+            else {
+                unreachable!("not an append request");
+            };
+
+            let response = cluster[follower].handle_append_entries(
+                term,
+                leader_id,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            );
+            let RaftResponse::AppendEntriesOk {
+                term: follower_term,
+                success,
+                match_index,
+            } = response
+            else {
+                unreachable!("not an append response");
+            };
+            let leader_state = &mut cluster[leader];
+            leader_state.peers.get_mut(&peer).unwrap().in_flight = false;
+            leader_state.handle_append_result(term, peer, follower_term, success, match_index);
+        }
+    }
+
+    fn submit(state: &mut State<Recorder>, command: u64) {
+        let term = state.term;
+        state.log.push(Entry { term, command });
+        state.advance_commit_index();
+    }
+
+    #[test]
+    fn majority_elects_leader_once_per_term() {
+        let mut cluster = cluster(3);
+        assert!(elect(&mut cluster, 0, &[1]));
+        assert_eq!(cluster[0].term, 1);
+        assert_eq!(cluster[1].voted_for, Some(node_id("n0")));
+
+        // n1 already voted in term 1.
+        assert!(!elect(&mut cluster, 2, &[1]));
+        assert_eq!(cluster[2].role, Role::Candidate);
+        assert!(elect(&mut cluster, 1, &[2]));
+        assert_eq!(cluster[1].term, 2);
+
+        // Stale leader steps down once it hears of the new term.
+        replicate(&mut cluster, 1, &[0]);
+        assert_eq!(cluster[0].role, Role::Follower);
+        assert_eq!(cluster[0].leader_id, Some(node_id("n1")));
+    }
+
+    #[test]
+    fn candidates_with_stale_logs_are_rejected() {
+        let mut cluster = cluster(3);
+        cluster[1].log.push(Entry {
+            term: 1,
+            command: 0,
+        });
+        cluster[1].term = 1;
+
+        assert!(!elect(&mut cluster, 0, &[1]));
+        // n2 has nothing either, so it votes for n0 in a later term.
+        assert!(elect(&mut cluster, 0, &[2]));
+        // Everyone voted in term 2 already, n1 wins the next one.
+        assert!(!elect(&mut cluster, 1, &[0, 2]));
+        assert!(elect(&mut cluster, 1, &[0, 2]));
+        assert_eq!(cluster[0].role, Role::Follower);
+    }
+
+    #[test]
+    fn conflicting_entries_are_truncated() {
+        let mut cluster = cluster(2);
+        let follower = &mut cluster[1];
+        follower.term = 2;
+        for entry in entries(&[1, 1, 2, 2]) {
+            follower.log.push(entry);
+        }
+
+        // Previous entry doesn't match: the hint skips the whole conflicting term.
+        let response = follower.handle_append_entries(3, node_id("n0"), 4, 3, entries(&[3]), 0);
+        assert!(matches!(
+            response,
+            RaftResponse::AppendEntriesOk {
+                success: false,
+                match_index: 2,
+                ..
+            }
+        ));
+        // Missing previous entry, the hint is the end of the log.
+        let response = follower.handle_append_entries(3, node_id("n0"), 6, 3, entries(&[3]), 0);
+        assert!(matches!(
+            response,
+            RaftResponse::AppendEntriesOk {
+                success: false,
+                match_index: 4,
+                ..
+            }
+        ));
+
+        // Entries following the first conflicting one are dropped.
+        let response = follower.handle_append_entries(3, node_id("n0"), 2, 1, entries(&[3]), 0);
+        assert!(matches!(
+            response,
+            RaftResponse::AppendEntriesOk {
+                success: true,
+                match_index: 3,
+                ..
+            }
+        ));
+        assert_eq!(log_terms(follower), vec![1, 1, 3]);
+
+        // Matching entries are kept, even if the request is older.
+        follower.handle_append_entries(3, node_id("n0"), 1, 1, entries(&[1]), 0);
+        assert_eq!(log_terms(follower), vec![1, 1, 3]);
+    }
+
+    #[test]
+    fn entries_commit_once_replicated_to_majority() {
+        let mut cluster = cluster(3);
+        assert!(elect(&mut cluster, 0, &[1, 2]));
+        submit(&mut cluster[0], 10);
+        submit(&mut cluster[0], 11);
+        assert_eq!(cluster[0].commit_index, 0);
+
+        replicate(&mut cluster, 0, &[1]);
+        assert_eq!(cluster[0].commit_index, 2);
+        assert_eq!(applied(&cluster[0]), [10, 11]);
+        // Followers learn about commits with the next request.
+        assert_eq!(cluster[1].commit_index, 0);
+        cluster[0]
+            .peers
+            .values_mut()
+            .for_each(|peer| peer.last_sent = None);
+        replicate(&mut cluster, 0, &[1, 2]);
+        assert_eq!(applied(&cluster[1]), [10, 11]);
+        assert_eq!(log_terms(&cluster[2]), vec![1, 1]);
+    }
+
+    #[test]
+    fn entries_of_previous_terms_commit_with_current_ones() {
+        let mut cluster = cluster(3);
+        assert!(elect(&mut cluster, 0, &[1, 2]));
+        submit(&mut cluster[0], 10);
+
+        // A new leader has the entry, but can't tell if it's committed by counting replicas.
+        assert!(elect(&mut cluster, 0, &[1, 2]));
+        cluster[1].log.push(Entry {
+            term: 1,
+            command: 10,
+        });
+        for peer in cluster[0].peers.values_mut() {
+            peer.match_index = 1;
+        }
+        cluster[0].advance_commit_index();
+        assert_eq!(cluster[0].commit_index, 0);
+
+        submit(&mut cluster[0], 11);
+        replicate(&mut cluster, 0, &[1]);
+        assert_eq!(cluster[0].commit_index, 2);
+        assert_eq!(applied(&cluster[0]), [10, 11]);
+    }
+
+    #[test]
+    fn stale_requests_dont_move_commit_index_back() {
+        let mut cluster = cluster(2);
+        let follower = &mut cluster[1];
+        follower.handle_append_entries(1, node_id("n0"), 0, 0, entries(&[1, 1, 1]), 3);
+        assert_eq!(follower.commit_index, 3);
+
+        // Sent before the leader knew the follower has the entries, delivered late.
+        follower.handle_append_entries(1, node_id("n0"), 1, 1, Vec::new(), 4);
+        assert_eq!(follower.commit_index, 3);
+        assert_eq!(applied(follower), [0, 1, 2]);
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use tokio::{sync::oneshot, time::timeout};
+
+use crate::{log::Log, Metrics, StateMachine, SubmitError};
+
+// Timings shared by all engines, so that they can be compared fairly.
+pub(crate) const TICK: Duration = Duration::from_millis(10);
+pub(crate) const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+pub(crate) const MAX_ENTRIES_PER_REQUEST: usize = 100;
+const ELECTION_TIMEOUT_MIN: Duration = Duration::from_millis(300);
+const ELECTION_TIMEOUT_MAX: Duration = Duration::from_millis(600);
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(1);
+// Applied entries kept in the log before it gets compacted into a snapshot.
+const SNAPSHOT_INTERVAL: u64 = 1000;
+
+// Randomized, so that replicas rarely start competing elections.
+pub(crate) fn election_deadline() -> Instant {
+    Instant::now() + rand::thread_rng().gen_range(ELECTION_TIMEOUT_MIN..ELECTION_TIMEOUT_MAX)
+}
+
+// Waits until submitted command is applied.
+pub(crate) async fn wait_applied<T>(
+    rx: oneshot::Receiver<T>,
+    metrics: &Metrics,
+) -> Result<T, SubmitError> {
+    let started = Instant::now();
+    match timeout(SUBMIT_TIMEOUT, rx).await {
+        Ok(Ok(output)) => {
+            metrics.record_command(started.elapsed());
+            Ok(output)
+        }
+        Ok(Err(_)) => Err(SubmitError::Indefinite("entry was overwritten")),
+        Err(_) => Err(SubmitError::Indefinite("timed out")),
+    }
+}
+
+// Replication progress of a peer, as seen by the leader.
+#[derive(Debug, Default)]
+pub(crate) struct Peer {
+    pub(crate) next_index: u64,
+    pub(crate) match_index: u64,
+    pub(crate) in_flight: bool,
+    pub(crate) last_sent: Option<Instant>,
+}
+
+impl Peer {
+    pub(crate) fn new(next_index: u64) -> Self {
+        Self {
+            next_index,
+            ..Self::default()
+        }
+    }
+
+    // Whether to send something now: either there are new entries or a heartbeat is due.
+    // Marks request as in flight. A request in flight for longer than a heartbeat interval
+    // was likely lost, so it doesn't hold back the next one: otherwise followers would
+    // hear nothing until the client gives up on it and start spurious elections.
+    pub(crate) fn poll_send(&mut self, last_index: u64, now: Instant) -> bool {
+        let has_entries = self.next_index <= last_index;
+        let heartbeat_due = self
+            .last_sent
+            .is_none_or(|last_sent| now - last_sent >= HEARTBEAT_INTERVAL);
+        if !heartbeat_due && (self.in_flight || !has_entries) {
+            return false;
+        }
+        self.in_flight = true;
+        self.last_sent = Some(now);
+        true
+    }
+}
+
+// State machine with bookkeeping shared by engines: what has been applied, the latest
+// snapshot and callers waiting for their commands.
+pub(crate) struct Replica<S: StateMachine> {
+    machine: S,
+    applied: u64,
+    // Covers entries up to `Log::snapshot_index`.
+    snapshot: Option<S::Snapshot>,
+    // Index => (term or ballot round the command was proposed in, waiter).
+    waiters: HashMap<u64, (u64, oneshot::Sender<S::Output>)>,
+}
+
+impl<S: StateMachine> Replica<S> {
+    pub(crate) fn new(machine: S) -> Self {
+        Self {
+            machine,
+            applied: 0,
+            snapshot: None,
+            waiters: HashMap::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn machine(&self) -> &S {
+        &self.machine
+    }
+
+    pub(crate) fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    pub(crate) fn snapshot(&self) -> Option<&S::Snapshot> {
+        self.snapshot.as_ref()
+    }
+
+    pub(crate) fn wait(&mut self, index: u64, proposed_in: u64) -> oneshot::Receiver<S::Output> {
+        let (tx, rx) = oneshot::channel();
+        self.waiters.insert(index, (proposed_in, tx));
+        rx
+    }
+
+    // Dropped senders tell waiters that their entries are gone.
+    pub(crate) fn drop_waiters(&mut self, from_index: u64) {
+        self.waiters.retain(|index, _| *index < from_index);
+    }
+
+    // Applies entries up to `commit_index`. `entry` returns proposal term (or round)
+    // and command for an index.
+    pub(crate) fn apply_committed<E>(
+        &mut self,
+        log: &Log<E>,
+        commit_index: u64,
+        entry: impl Fn(&E) -> (u64, &S::Command),
+    ) {
+        while self.applied < commit_index {
+            self.applied += 1;
+            let (proposed_in, command) = entry(log.get(self.applied).expect("entry is committed"));
+            let output = self.machine.apply(command);
+            if let Some((waiter_proposed_in, tx)) = self.waiters.remove(&self.applied) {
+                if waiter_proposed_in == proposed_in {
+                    // Waiter may have given up already.
+                    let _ = tx.send(output);
+                }
+            }
+        }
+    }
+
+    // Snapshots applied entries once enough of them piled up in the log.
+    pub(crate) fn maybe_compact<E>(&mut self, log: &mut Log<E>) -> bool {
+        if self.applied - log.snapshot_index() < SNAPSHOT_INTERVAL {
+            return false;
+        }
+        self.snapshot = Some(self.machine.snapshot());
+        log.compact(self.applied);
+        true
+    }
+
+    // Replaces state with a snapshot received from another replica.
+    pub(crate) fn restore<E>(&mut self, log: &mut Log<E>, index: u64, snapshot: S::Snapshot) {
+        log::info!("restoring snapshot at index {index}");
+        self.machine.restore(snapshot.clone());
+        self.snapshot = Some(snapshot);
+        self.applied = index;
+        self.waiters.retain(|waiter_index, _| *waiter_index > index);
+        log.compact(index);
+    }
+}
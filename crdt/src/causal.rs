@@ -0,0 +1,240 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use base::node::NodeId;
+use serde::{Deserialize, Serialize};
+
+// Unique tag of an update: the `counter`-th update made on replica `node_id`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Dot {
+    pub node_id: NodeId,
+    pub counter: u64,
+}
+
+// Dots a replica has seen, whether their values are still around or were removed since.
+// Dots of each replica mostly arrive in order, those are kept as a version vector and
+// only the ones past a gap are stored one by one.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CausalContext {
+    // Every dot up to the counter, per replica.
+    compact: HashMap<NodeId, u64>,
+    // Dots that arrived ahead of a missing one.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    cloud: HashSet<Dot>,
+}
+
+impl CausalContext {
+    pub fn contains(&self, dot: &Dot) -> bool {
+        self.compact
+            .get(&dot.node_id)
+            .is_some_and(|counter| dot.counter <= *counter)
+            || self.cloud.contains(dot)
+    }
+
+    // Dots of this replica are never sparse, so the next one follows the compact part.
+    pub fn next_dot(&mut self, node_id: &NodeId) -> Dot {
+        let counter = self.compact.entry(node_id.clone()).or_default();
+        *counter += 1;
+        Dot {
+            node_id: node_id.clone(),
+            counter: *counter,
+        }
+    }
+
+    pub fn insert(&mut self, dot: Dot) {
+        if !self.contains(&dot) {
+            self.cloud.insert(dot);
+            self.compact();
+        }
+    }
+
+    pub fn merge(&mut self, other: CausalContext) {
+        for (node_id, counter) in other.compact {
+            let current = self.compact.entry(node_id).or_default();
+            *current = (*current).max(counter);
+        }
+        self.cloud.extend(other.cloud);
+        self.compact();
+    }
+
+    // Moves dots that no longer follow a gap into the version vector.
+    fn compact(&mut self) {
+        let mut cloud = self.cloud.drain().collect::<Vec<_>>();
+        cloud.sort_unstable();
+        for dot in cloud {
+            let counter = self.compact.get(&dot.node_id).copied().unwrap_or(0);
+            if dot.counter == counter + 1 {
+                self.compact.insert(dot.node_id, dot.counter);
+            } else if dot.counter > counter {
+                self.cloud.insert(dot);
+            }
+        }
+    }
+}
+
+// Values tagged with dots, along with the context of every dot ever seen. A dot that's
+// in the context but not among the values was removed, so merging never resurrects
+// removed values, while values added concurrently with a removal survive it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DotKernel<V> {
+    // Counter of the dot to value, per replica.
+    values: HashMap<NodeId, BTreeMap<u64, V>>,
+    context: CausalContext,
+}
+
+impl<V> Default for DotKernel<V> {
+    fn default() -> Self {
+        Self {
+            values: HashMap::new(),
+            context: CausalContext::default(),
+        }
+    }
+}
+
+impl<V: Clone> DotKernel<V> {
+    pub fn iter(&self) -> impl Iterator<Item = (Dot, &V)> {
+        self.values.iter().flat_map(|(node_id, values)| {
+            values.iter().map(|(counter, value)| {
+                let dot = Dot {
+                    node_id: node_id.clone(),
+                    counter: *counter,
+                };
+                (dot, value)
+            })
+        })
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.values.values().flat_map(BTreeMap::values)
+    }
+
+    pub fn context(&self) -> &CausalContext {
+        &self.context
+    }
+
+    // Tags `value` with a new dot of `node_id`, returns the delta.
+    pub fn add(&mut self, node_id: &NodeId, value: V) -> Self {
+        let dot = self.context.next_dot(node_id);
+        let mut delta = Self::default();
+        delta.insert(dot.clone(), value.clone());
+        delta.context.insert(dot.clone());
+        self.insert(dot, value);
+        delta
+    }
+
+    // Removes values matching `predicate`, returns the delta: only a context with the
+    // removed dots, which removes them wherever it's merged.
+    pub fn remove(&mut self, predicate: impl Fn(&V) -> bool) -> Self {
+        self.remove_dots(|_, value| predicate(value))
+    }
+
+    // Like `remove`, but `predicate` also gets the dot of the value.
+    pub fn remove_dots(&mut self, predicate: impl Fn(&Dot, &V) -> bool) -> Self {
+        let mut delta = Self::default();
+        for (node_id, values) in &mut self.values {
+            values.retain(|counter, value| {
+                let dot = Dot {
+                    node_id: node_id.clone(),
+                    counter: *counter,
+                };
+                if !predicate(&dot, value) {
+                    return true;
+                }
+                delta.context.insert(dot);
+                false
+            });
+        }
+        self.values.retain(|_, values| !values.is_empty());
+        delta
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        // Ours survive unless the other replica has seen and removed them.
+        for (node_id, values) in &mut self.values {
+            let theirs = other.values.get(node_id);
+            values.retain(|counter, _| {
+                theirs.is_some_and(|theirs| theirs.contains_key(counter))
+                    || !other.context.contains(&Dot {
+                        node_id: node_id.clone(),
+                        counter: *counter,
+                    })
+            });
+        }
+        // Theirs are added unless we have seen and removed them.
+        for (node_id, values) in other.values {
+            for (counter, value) in values {
+                let dot = Dot {
+                    node_id: node_id.clone(),
+                    counter,
+                };
+                if !self.context.contains(&dot) {
+                    self.insert(dot, value);
+                }
+            }
+        }
+        self.values.retain(|_, values| !values.is_empty());
+        self.context.merge(other.context);
+    }
+
+    fn insert(&mut self, dot: Dot, value: V) {
+        self.values
+            .entry(dot.node_id)
+            .or_default()
+            .insert(dot.counter, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::sim::node_id;
+
+    use super::*;
+
+    fn dot(node: &str, counter: u64) -> Dot {
+        Dot {
+            node_id: node_id(node),
+            counter,
+        }
+    }
+
+    #[test]
+    fn out_of_order_dots_are_compacted_once_gaps_fill() {
+        let mut context = CausalContext::default();
+        context.insert(dot("n1", 3));
+        context.insert(dot("n1", 1));
+        assert!(context.contains(&dot("n1", 3)));
+        assert!(!context.contains(&dot("n1", 2)));
+        assert_eq!(context.compact, HashMap::from([(node_id("n1"), 1)]));
+        assert_eq!(context.cloud, HashSet::from([dot("n1", 3)]));
+
+        context.insert(dot("n1", 2));
+        assert_eq!(context.compact, HashMap::from([(node_id("n1"), 3)]));
+        assert!(context.cloud.is_empty());
+
+        // Same when the gap is filled by a merge.
+        let mut other = CausalContext::default();
+        other.insert(dot("n1", 5));
+        other.insert(dot("n2", 1));
+        context.merge(other.clone());
+        assert_eq!(context.cloud, HashSet::from([dot("n1", 5)]));
+        let mut filler = CausalContext::default();
+        for counter in 1..=4 {
+            filler.insert(dot("n1", counter));
+        }
+        context.merge(filler);
+        assert_eq!(
+            context.compact,
+            HashMap::from([(node_id("n1"), 5), (node_id("n2"), 1)])
+        );
+        assert!(context.cloud.is_empty());
+    }
+
+    #[test]
+    fn next_dot_follows_seen_dots() {
+        let mut context = CausalContext::default();
+        context.insert(dot("n1", 1));
+        context.insert(dot("n1", 2));
+        assert_eq!(context.next_dot(&node_id("n1")), dot("n1", 3));
+        assert_eq!(context.next_dot(&node_id("n2")), dot("n2", 1));
+        assert!(context.contains(&dot("n1", 3)));
+    }
+}
@@ -0,0 +1,31 @@
+use anyhow::{bail, Result};
+use base::utils::init_log;
+use crdt::{
+    crdt::CrdtService,
+    g_counter::GCounter,
+    g_set::GSet,
+    list::Rga,
+    map::CrdtMap,
+    or_set::OrSet,
+    pn_counter::PnCounter,
+    register::{LwwRegister, MvRegister},
+};
+
+// Serves the protocol of the CRDT picked by `CRDT_MAP_VALUE` (g-counter by default) for
+// every key, with a `key` added to requests.
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    init_log()?;
+    let value = std::env::var("CRDT_MAP_VALUE").unwrap_or_else(|_| "g-counter".to_owned());
+    log::info!("serving a map of {value}");
+    match value.as_str() {
+        "g-counter" => CrdtService::<CrdtMap<String, GCounter>>::run().await,
+        "pn-counter" => CrdtService::<CrdtMap<String, PnCounter>>::run().await,
+        "g-set" => CrdtService::<CrdtMap<String, GSet>>::run().await,
+        "or-set" => CrdtService::<CrdtMap<String, OrSet>>::run().await,
+        "lww-register" => CrdtService::<CrdtMap<String, LwwRegister<u64>>>::run().await,
+        "mv-register" => CrdtService::<CrdtMap<String, MvRegister<u64>>>::run().await,
+        "rga" => CrdtService::<CrdtMap<String, Rga<u64>>>::run().await,
+        _ => bail!("unknown CRDT_MAP_VALUE {value:?}"),
+    }
+}
@@ -1,110 +1,339 @@
 use std::{
+    collections::HashSet,
     sync::{Arc, Mutex, MutexGuard},
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use base::{
     client::Client,
+    codec::{self, Codec},
     init::recv_init,
     node::NodeId,
     serve::{serve, RequestHandler, Service},
-    utils::every,
+    sync::Iblt,
+    utils::{async_spawn, every},
 };
 use futures::{future::BoxFuture, FutureExt};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+use crate::delta::{DeltaBuffer, Pending};
+
 pub trait Crdt: Default + Send + 'static {
-    type Add: Serialize + DeserializeOwned + Send;
+    type Update: Serialize + DeserializeOwned + Send;
     type State: Serialize + DeserializeOwned + Clone + Send;
+    type Delta: Serialize + DeserializeOwned + Clone + Send;
     type Query: Serialize + DeserializeOwned + Send;
 
-    fn add(&mut self, node_id: &NodeId, add: Self::Add) -> Result<()>;
+    // Delta-mutator: applies an update made on replica `node_id` (this node) and returns
+    // the delta, the smallest state that, merged elsewhere, has the same effect.
+    fn update(&mut self, node_id: &NodeId, update: Self::Update) -> Result<Self::Delta>;
     fn merge(&mut self, other: Self::State) -> Result<()>;
+    fn merge_delta(&mut self, delta: Self::Delta) -> Result<()>;
     fn state(&self) -> Self::State;
     fn query(&self) -> Self::Query;
+
+    // Set-like CRDTs expose their elements, so that replicas reconcile them with IBLTs
+    // (messages proportional to the difference) instead of shipping whole states. Those
+    // set `SUPPORTS_ELEMENTS`, which is cheaper to check than `elements`.
+    const SUPPORTS_ELEMENTS: bool = false;
+
+    fn elements(&self) -> Option<Vec<u64>> {
+        None
+    }
+
+    fn merge_elements(&mut self, _elements: Vec<u64>) -> Result<()> {
+        bail!("CRDT doesn't support reconciliation by elements")
+    }
 }
 
-#[allow(type_alias_bounds)]
-pub type CrdtClient<C: Crdt> = Client<Request<C::Add, C::State>, Response<C::Query>>;
+// Client protocol of a CRDT binary, served with `update` and `query`.
+pub trait ClientApi: Crdt {
+    type Request: Serialize + DeserializeOwned + Send;
+    type Response: Serialize + DeserializeOwned + Send;
 
-pub struct CrdtService<C: Crdt> {
-    crdt: Mutex<C>,
-    client: CrdtClient<C>, //Client<Request<C::Add, C::State>, Response<C::Query>>,
+    // Replies to a client request, along with the delta if the request updated the CRDT.
+    fn serve(
+        &mut self,
+        node_id: &NodeId,
+        request: Self::Request,
+    ) -> Result<(Self::Response, Option<Self::Delta>)>;
 }
 
+// `add`/`read` protocol of the g-set and g-counter workloads.
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
-pub enum Request<A, S> {
-    Add(A),
+pub enum AddRequest<U> {
+    Add(U),
     Read,
-    Replicate(S),
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
-pub enum Response<Q> {
+pub enum AddResponse<Q> {
     AddOk,
     ReadOk(Q),
 }
 
-impl<C: Crdt> CrdtService<C> {
+impl<U> AddRequest<U> {
+    #[allow(clippy::type_complexity)]
+    pub fn serve<C: Crdt<Update = U>>(
+        self,
+        crdt: &mut C,
+        node_id: &NodeId,
+    ) -> Result<(AddResponse<C::Query>, Option<C::Delta>)> {
+        match self {
+            Self::Add(update) => Ok((AddResponse::AddOk, Some(crdt.update(node_id, update)?))),
+            Self::Read => Ok((AddResponse::ReadOk(crdt.query()), None)),
+        }
+    }
+}
+
+#[allow(type_alias_bounds)]
+pub type CrdtRequest<C: ClientApi> = Request<C::Request, C::State, C::Delta>;
+
+#[allow(type_alias_bounds)]
+pub type CrdtClient<C: ClientApi> = Client<CrdtRequest<C>, Response<C::Response>>;
+
+// Replicates updates as deltas: every local update is buffered and shipped to each peer
+// until the peer acknowledges it. Peers that fall too far behind get the full state, or
+// reconcile elements with IBLTs if the CRDT supports that.
+pub struct CrdtService<C: ClientApi> {
+    replica: Mutex<Replica<C>>,
+    client: CrdtClient<C>,
+}
+
+struct Replica<C: Crdt> {
+    crdt: C,
+    deltas: DeltaBuffer<C::Delta>,
+    // Peers currently being brought up to date by reconciliation.
+    reconciling: HashSet<NodeId>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Request<R, S, D> {
+    Replica(ReplicaRequest<S, D>),
+    Client(R),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Response<R> {
+    Replica(ReplicaResponse),
+    Client(R),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReplicaRequest<S, D> {
+    // Full state, includes every delta up to `seq`.
+    Replicate { seq: u64, state: S },
+    // Deltas following the last acknowledged one, up to `seq`.
+    Delta { seq: u64, deltas: Vec<D> },
+    // Receiver has merged every delta of the sender up to `seq`.
+    DeltaAck { seq: u64 },
+    // Peer merges the elements it's missing and replies with the ones the sender is
+    // missing, or with `None` if the IBLT was too small to decode.
+    Reconcile { iblt: Iblt },
+    // Fallback for differences too large for any IBLT.
+    ReconcileAll { elements: Vec<u64> },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReplicaResponse {
+    ReconcileOk { missing: Option<Vec<u64>> },
+}
+
+impl<C: ClientApi> CrdtService<C> {
+    const REPLICATION_INTERVAL: Duration = Duration::from_millis(500);
+    const MAX_BUFFERED_DELTAS: usize = 10_000;
+    const MAX_DELTAS_PER_MESSAGE: usize = 1000;
+
     fn new(client: &CrdtClient<C>) -> Self {
+        let node = client.node();
+        let peers = node
+            .node_ids()
+            .iter()
+            .filter(|node_id| *node_id != node.node_id())
+            .cloned();
         Self {
-            crdt: Mutex::default(),
+            replica: Mutex::new(Replica {
+                crdt: C::default(),
+                deltas: DeltaBuffer::new(peers, Self::MAX_BUFFERED_DELTAS),
+                reconciling: HashSet::new(),
+            }),
             client: client.clone(),
         }
     }
 
-    fn lock(&self) -> MutexGuard<'_, C> {
-        self.crdt.lock().expect("lock panic")
+    fn lock(&self) -> MutexGuard<'_, Replica<C>> {
+        self.replica.lock().expect("lock panic")
     }
 
-    fn start_replicating(self: &Arc<Self>) {
-        const REPLICATION_INTERVAL: Duration = Duration::from_secs(5);
+    fn node_id(&self) -> &NodeId {
+        self.client.node().node_id()
+    }
 
+    fn start_replicating(self: &Arc<Self>) {
         let service = self.clone();
         let weak = Arc::downgrade(&service);
 
-        every(REPLICATION_INTERVAL, move || {
-            let weak = weak.clone();
-            async move {
-                let Some(service) = weak.upgrade() else {
-                    // Service got dropped.
-                    return Ok(());
-                };
-                service.replicate().await
-            }
-        });
+        every(
+            self.client.node().shutdown(),
+            Self::REPLICATION_INTERVAL,
+            move || {
+                let weak = weak.clone();
+                async move {
+                    let Some(service) = weak.upgrade() else {
+                        // Service got dropped.
+                        return Ok(());
+                    };
+                    service.replicate().await
+                }
+            },
+        );
     }
 
-    async fn replicate(&self) -> Result<()> {
-        let state = self.lock().state();
-        let client = &self.client;
-        for node_id in client.node().node_ids() {
-            if node_id == client.node().node_id() {
-                continue;
+    // Sends every peer the deltas it hasn't acknowledged yet. Unacknowledged deltas are
+    // simply sent again next round, merging is idempotent.
+    async fn replicate(self: &Arc<Self>) -> Result<()> {
+        let mut outgoing = Vec::new();
+        {
+            let replica = &mut *self.lock();
+            for peer in self.client.node().node_ids() {
+                if peer == self.node_id() {
+                    continue;
+                }
+                match replica.deltas.pending(peer, Self::MAX_DELTAS_PER_MESSAGE) {
+                    Pending::UpToDate => {}
+                    Pending::Deltas { seq, deltas } => {
+                        outgoing.push((peer.clone(), ReplicaRequest::Delta { seq, deltas }));
+                    }
+                    Pending::Behind { seq } if C::SUPPORTS_ELEMENTS => {
+                        if replica.reconciling.insert(peer.clone()) {
+                            self.start_reconciling(peer.clone(), seq);
+                        }
+                    }
+                    Pending::Behind { seq } => {
+                        log::info!("{peer} fell behind, sending full state");
+                        let state = replica.crdt.state();
+                        outgoing.push((peer.clone(), ReplicaRequest::Replicate { seq, state }));
+                    }
+                }
             }
-            client
-                .send_no_reply(node_id.clone(), Request::Replicate(state.clone()))
+        }
+
+        for (peer, request) in outgoing {
+            self.client
+                .send_no_reply(peer, Request::Replica(request))
                 .await?;
         }
         Ok(())
     }
 
+    // Brings a peer that fell behind up to date with a message proportional to the
+    // difference, rather than the full state. Runs in the background, as it takes a few
+    // round trips.
+    fn start_reconciling(self: &Arc<Self>, peer: NodeId, seq: u64) {
+        log::info!("{peer} fell behind, reconciling");
+        let service = self.clone();
+        async_spawn(self.client.node().shutdown(), async move {
+            let result = service.reconcile(&peer).await;
+            let mut replica = service.lock();
+            replica.reconciling.remove(&peer);
+            if result.is_ok() {
+                replica.deltas.ack(&peer, seq);
+            }
+            result.with_context(|| format!("failed to reconcile with {peer}"))
+        });
+    }
+
+    async fn reconcile(&self, peer: &NodeId) -> Result<()> {
+        for size in Iblt::SIZES {
+            let elements = self.elements()?;
+            let iblt = Iblt::from_keys(size, elements);
+            if let Some(missing) = self
+                .send_reconcile(peer, ReplicaRequest::Reconcile { iblt })
+                .await?
+            {
+                return self.lock().crdt.merge_elements(missing);
+            }
+        }
+
+        let elements = self.elements()?;
+        let Some(missing) = self
+            .send_reconcile(peer, ReplicaRequest::ReconcileAll { elements })
+            .await?
+        else {
+            bail!("{peer} didn't reply with missing elements");
+        };
+        self.lock().crdt.merge_elements(missing)
+    }
+
+    async fn send_reconcile(
+        &self,
+        peer: &NodeId,
+        request: ReplicaRequest<C::State, C::Delta>,
+    ) -> Result<Option<Vec<u64>>> {
+        match self
+            .client
+            .send(peer.clone(), Request::Replica(request))
+            .await?
+        {
+            Response::Replica(ReplicaResponse::ReconcileOk { missing }) => Ok(missing),
+            _ => bail!("unexpected response from {peer}"),
+        }
+    }
+
+    fn on_reconcile(&self, iblt: &Iblt) -> Result<Option<Vec<u64>>> {
+        let Some(difference) = iblt.difference(self.elements()?)? else {
+            return Ok(None);
+        };
+        self.lock().crdt.merge_elements(difference.remote)?;
+        Ok(Some(difference.local))
+    }
+
+    fn on_reconcile_all(&self, theirs: Vec<u64>) -> Result<Vec<u64>> {
+        let known = theirs.iter().copied().collect::<HashSet<_>>();
+        let mut missing = self.elements()?;
+        missing.retain(|element| !known.contains(element));
+        self.lock().crdt.merge_elements(theirs)?;
+        Ok(missing)
+    }
+
+    fn elements(&self) -> Result<Vec<u64>> {
+        self.lock()
+            .crdt
+            .elements()
+            .ok_or_else(|| anyhow!("CRDT doesn't support reconciliation by elements"))
+    }
+
+    async fn send_ack(&self, peer: NodeId, seq: u64) -> Result<()> {
+        self.client
+            .send_no_reply(peer, Request::Replica(ReplicaRequest::DeltaAck { seq }))
+            .await
+    }
+
     pub async fn run() -> Result<()> {
-        let node = recv_init()?;
-        let client = Client::new(&node);
+        // `CODEC_STATS=1` logs how MessagePack compares to JSON on shutdown.
+        codec::set_stats_enabled(std::env::var("CODEC_STATS").is_ok_and(|value| value == "1"));
+        let node = recv_init().await?;
+        let client = Client::with_codec(&node, |request| match request {
+            Request::Replica(_) => Codec::MessagePack,
+            Request::Client(_) => Codec::Json,
+        });
         let service = Arc::new(Self::new(&client));
 
         service.start_replicating();
-        serve((Service::new(&node, service), client)).await
+        serve(&node, (Service::new(&node, service), client)).await
     }
 }
 
-impl<C: Crdt> RequestHandler for CrdtService<C> {
-    type Request = Request<C::Add, C::State>;
-    type Response = Response<C::Query>;
+impl<C: ClientApi> RequestHandler for CrdtService<C> {
+    type Request = CrdtRequest<C>;
+    type Response = Response<C::Response>;
 
     fn handle<'a>(
         self: &'a Arc<Self>,
@@ -112,19 +341,48 @@ impl<C: Crdt> RequestHandler for CrdtService<C> {
         request: Self::Request,
     ) -> BoxFuture<'a, Result<Option<Self::Response>>> {
         async move {
+            let request = match request {
+                Request::Replica(request) => request,
+                Request::Client(request) => {
+                    let mut replica = self.lock();
+                    let (response, delta) = replica.crdt.serve(self.node_id(), request)?;
+                    if let Some(delta) = delta {
+                        replica.deltas.push(delta);
+                    }
+                    return Ok(Some(Response::Client(response)));
+                }
+            };
+
             match request {
-                Request::Add(add) => {
-                    self.lock().add(&sender, add)?;
-                    Ok(Some(Response::AddOk))
+                ReplicaRequest::Replicate { seq, state } => {
+                    self.lock().crdt.merge(state)?;
+                    self.send_ack(sender, seq).await?;
+                    Ok(None)
                 }
-                Request::Read => {
-                    let query = self.lock().query();
-                    Ok(Some(Response::ReadOk(query)))
+                ReplicaRequest::Delta { seq, deltas } => {
+                    {
+                        let crdt = &mut self.lock().crdt;
+                        for delta in deltas {
+                            crdt.merge_delta(delta)?;
+                        }
+                    }
+                    self.send_ack(sender, seq).await?;
+                    Ok(None)
                 }
-                Request::Replicate(state) => {
-                    self.lock().merge(state)?;
+                ReplicaRequest::DeltaAck { seq } => {
+                    self.lock().deltas.ack(&sender, seq);
                     Ok(None)
                 }
+                ReplicaRequest::Reconcile { iblt } => {
+                    Ok(Some(Response::Replica(ReplicaResponse::ReconcileOk {
+                        missing: self.on_reconcile(&iblt)?,
+                    })))
+                }
+                ReplicaRequest::ReconcileAll { elements } => {
+                    Ok(Some(Response::Replica(ReplicaResponse::ReconcileOk {
+                        missing: Some(self.on_reconcile_all(elements)?),
+                    })))
+                }
             }
         }
         .boxed()
@@ -0,0 +1,193 @@
+use std::collections::{HashMap, VecDeque};
+
+use base::node::NodeId;
+
+// Deltas of local updates, numbered from 1, kept until every peer acknowledged them.
+// A peer that acknowledges seq `n` has merged every delta up to `n`, so it only ever
+// needs the ones after its ack. The buffer is bounded: once a peer falls behind the
+// oldest buffered delta, it has to be brought up to date some other way (full state).
+#[derive(Debug)]
+pub struct DeltaBuffer<D> {
+    deltas: VecDeque<D>,
+    // Seq of `deltas[0]`.
+    first_seq: u64,
+    acked: HashMap<NodeId, u64>,
+    capacity: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Pending<D> {
+    UpToDate,
+    // Deltas following the peer's ack, the last one has seq `seq`.
+    Deltas { seq: u64, deltas: Vec<D> },
+    // Deltas the peer needs were already dropped, it needs everything up to `seq`.
+    Behind { seq: u64 },
+}
+
+impl<D: Clone> DeltaBuffer<D> {
+    pub fn new(peers: impl IntoIterator<Item = NodeId>, capacity: usize) -> Self {
+        Self {
+            deltas: VecDeque::new(),
+            first_seq: 1,
+            acked: peers.into_iter().map(|peer| (peer, 0)).collect(),
+            capacity,
+        }
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.first_seq + self.deltas.len() as u64 - 1
+    }
+
+    pub fn push(&mut self, delta: D) -> u64 {
+        self.deltas.push_back(delta);
+        if self.deltas.len() > self.capacity {
+            self.deltas.pop_front();
+            self.first_seq += 1;
+        }
+        self.last_seq()
+    }
+
+    // Acks may arrive out of order, only the highest one counts.
+    pub fn ack(&mut self, peer: &NodeId, seq: u64) {
+        let last_seq = self.last_seq();
+        let Some(acked) = self.acked.get_mut(peer) else {
+            log::warn!("ack from unknown peer {peer}");
+            return;
+        };
+        *acked = (*acked).max(seq.min(last_seq));
+
+        // Deltas every peer has are no longer needed.
+        let min_acked = self.acked.values().copied().min().unwrap_or(last_seq);
+        while self.first_seq <= min_acked && self.deltas.pop_front().is_some() {
+            self.first_seq += 1;
+        }
+    }
+
+    // At most `limit` deltas, the rest goes out once these are acknowledged.
+    pub fn pending(&self, peer: &NodeId, limit: usize) -> Pending<D> {
+        let acked = self.acked.get(peer).copied().unwrap_or(0);
+        if acked >= self.last_seq() {
+            return Pending::UpToDate;
+        }
+        if acked + 1 < self.first_seq {
+            return Pending::Behind {
+                seq: self.last_seq(),
+            };
+        }
+
+        let start = (acked + 1 - self.first_seq) as usize;
+        let deltas = self
+            .deltas
+            .range(start..)
+            .take(limit)
+            .cloned()
+            .collect::<Vec<_>>();
+        Pending::Deltas {
+            seq: acked + deltas.len() as u64,
+            deltas,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::sim::node_id;
+
+    use super::*;
+
+    fn buffer(peers: &[&str], capacity: usize, deltas: u64) -> DeltaBuffer<u64> {
+        let mut buffer = DeltaBuffer::new(peers.iter().map(|peer| node_id(peer)), capacity);
+        for delta in 1..=deltas {
+            assert_eq!(buffer.push(delta * 10), delta);
+        }
+        buffer
+    }
+
+    #[test]
+    fn pending_deltas_follow_the_ack() {
+        let n1 = node_id("n1");
+        let mut buffer = buffer(&["n1"], 10, 5);
+        assert_eq!(
+            buffer.pending(&n1, 2),
+            Pending::Deltas {
+                seq: 2,
+                deltas: vec![10, 20]
+            }
+        );
+
+        buffer.ack(&n1, 2);
+        assert_eq!(
+            buffer.pending(&n1, 10),
+            Pending::Deltas {
+                seq: 5,
+                deltas: vec![30, 40, 50]
+            }
+        );
+        buffer.ack(&n1, 5);
+        assert_eq!(buffer.pending(&n1, 10), Pending::UpToDate);
+    }
+
+    #[test]
+    fn older_acks_are_ignored() {
+        let n1 = node_id("n1");
+        let mut buffer = buffer(&["n1"], 10, 3);
+        buffer.ack(&n1, 3);
+        buffer.ack(&n1, 1);
+        assert_eq!(buffer.pending(&n1, 10), Pending::UpToDate);
+
+        buffer.push(40);
+        assert_eq!(
+            buffer.pending(&n1, 10),
+            Pending::Deltas {
+                seq: 4,
+                deltas: vec![40]
+            }
+        );
+    }
+
+    #[test]
+    fn peers_behind_evicted_deltas_need_full_state() {
+        let (n1, n2) = (node_id("n1"), node_id("n2"));
+        let mut buffer = buffer(&["n1", "n2"], 3, 2);
+        buffer.ack(&n2, 1);
+        buffer.push(30);
+        buffer.push(40);
+        buffer.push(50);
+
+        assert_eq!(buffer.pending(&n1, 10), Pending::Behind { seq: 5 });
+        assert_eq!(buffer.pending(&n2, 10), Pending::Behind { seq: 5 });
+        // Once brought up to date some other way, deltas flow again.
+        buffer.ack(&n1, 5);
+        buffer.push(60);
+        assert_eq!(
+            buffer.pending(&n1, 10),
+            Pending::Deltas {
+                seq: 6,
+                deltas: vec![60]
+            }
+        );
+    }
+
+    #[test]
+    fn deltas_are_dropped_once_every_peer_has_them() {
+        let (n1, n2) = (node_id("n1"), node_id("n2"));
+        let mut buffer = buffer(&["n1", "n2"], 10, 3);
+        buffer.ack(&n1, 3);
+        assert_eq!(buffer.deltas.len(), 3);
+        buffer.ack(&n2, 2);
+        assert_eq!(buffer.deltas, [30]);
+        buffer.ack(&n2, 3);
+        assert!(buffer.deltas.is_empty());
+
+        // Seqs keep counting.
+        assert_eq!(buffer.last_seq(), 3);
+        assert_eq!(buffer.push(40), 4);
+        assert_eq!(
+            buffer.pending(&n2, 10),
+            Pending::Deltas {
+                seq: 4,
+                deltas: vec![40]
+            }
+        );
+    }
+}
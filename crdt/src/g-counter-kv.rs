@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use base::{
+    ids::SnowflakeIds,
+    init::recv_init,
+    kv::KvClient,
+    node::NodeId,
+    serve::{serve, RequestHandler, Service},
+    utils::init_log,
+};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Add { delta: u64 },
+    Read,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    AddOk,
+    ReadOk { value: u64 },
+}
+
+// Stateless alternative to gossiping `GCounter`: every node keeps its own counter in
+// seq-kv and reads sum counters of all nodes.
+struct KvCounter {
+    seq_kv: KvClient<String, u64>,
+    sentinels: SnowflakeIds,
+}
+
+impl KvCounter {
+    fn new(seq_kv: &KvClient<String, u64>, sentinels: SnowflakeIds) -> Self {
+        Self {
+            seq_kv: seq_kv.clone(),
+            sentinels,
+        }
+    }
+
+    fn node_id(&self) -> &NodeId {
+        self.seq_kv.node().node_id()
+    }
+
+    async fn add(&self, delta: u64) -> Result<()> {
+        // Only this node writes its key, CAS fails on stale reads and concurrent adds. A
+        // missing key counts as 0, so that even first adds can't overwrite each other.
+        self.seq_kv
+            .update_with_cas(format!("counter/{}", self.node_id()), 0, |value| {
+                Some(value + delta)
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn read(&self) -> Result<u64> {
+        // seq-kv may serve stale reads, as long as they are consistent with this node's
+        // own operations. Writing a unique value first forces reads that follow it to
+        // observe a state at least as recent as that write.
+        self.seq_kv
+            .write(
+                format!("sentinel/{}", self.node_id()),
+                self.sentinels.next(),
+            )
+            .await?;
+
+        let mut sum = 0;
+        for node_id in self.seq_kv.node().node_ids() {
+            let counter = self.seq_kv.read(format!("counter/{node_id}")).await?;
+            sum += counter.unwrap_or(0);
+        }
+        Ok(sum)
+    }
+}
+
+impl RequestHandler for KvCounter {
+    type Request = Request;
+    type Response = Response;
+
+    fn handle<'a>(
+        self: &'a Arc<Self>,
+        _sender: NodeId,
+        request: Self::Request,
+    ) -> BoxFuture<'a, Result<Option<Self::Response>>> {
+        async move {
+            match request {
+                Request::Add { delta } => {
+                    self.add(delta).await?;
+                    Ok(Some(Response::AddOk))
+                }
+                Request::Read => Ok(Some(Response::ReadOk {
+                    value: self.read().await?,
+                })),
+            }
+        }
+        .boxed()
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    init_log()?;
+    let node = recv_init().await?;
+    let seq_kv = KvClient::seq_kv(&node);
+    let counter = Arc::new(KvCounter::new(&seq_kv, SnowflakeIds::new(&node)?));
+    serve(&node, (Service::new(&node, counter), seq_kv.client())).await
+}
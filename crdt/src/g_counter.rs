@@ -0,0 +1,94 @@
+use std::{cmp, collections::HashMap};
+
+use anyhow::Result;
+use base::node::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::{AddRequest, AddResponse, ClientApi, Crdt};
+
+#[derive(Serialize, Deserialize)]
+pub struct Add {
+    pub delta: u64,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct Query {
+    pub value: u64,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct State {
+    pub counters: HashMap<NodeId, u64>,
+}
+
+#[derive(Default)]
+pub struct GCounter(State);
+
+impl Crdt for GCounter {
+    type Update = Add;
+    type Delta = State;
+    type Query = Query;
+    type State = State;
+
+    // Each replica only increments its own counter, so the delta is just that counter.
+    fn update(&mut self, node_id: &NodeId, add: Self::Update) -> Result<Self::Delta> {
+        let value = self.0.counters.entry(node_id.clone()).or_default();
+        *value += add.delta;
+        Ok(State {
+            counters: HashMap::from([(node_id.clone(), *value)]),
+        })
+    }
+
+    fn merge(&mut self, other: Self::State) -> Result<()> {
+        let counters = &mut self.0.counters;
+        for (node_id, value) in other.counters {
+            counters
+                .entry(node_id)
+                .and_modify(|v| *v = cmp::max(*v, value))
+                .or_insert(value);
+        }
+        Ok(())
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) -> Result<()> {
+        self.merge(delta)
+    }
+
+    fn query(&self) -> Self::Query {
+        Query {
+            value: self.0.counters.values().copied().sum(),
+        }
+    }
+
+    fn state(&self) -> Self::State {
+        self.0.clone()
+    }
+}
+
+impl ClientApi for GCounter {
+    type Request = AddRequest<Add>;
+    type Response = AddResponse<Query>;
+
+    fn serve(
+        &mut self,
+        node_id: &NodeId,
+        request: Self::Request,
+    ) -> Result<(Self::Response, Option<Self::Delta>)> {
+        request.serve(self, node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::testing::check_convergence;
+
+    #[test]
+    fn converges() {
+        check_convergence::<GCounter>(|rng, _| Add {
+            delta: rng.gen_range(0..10),
+        });
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use base::node::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::{AddRequest, AddResponse, ClientApi, Crdt};
+
+#[derive(Serialize, Deserialize)]
+pub struct Add {
+    pub element: u64,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct State {
+    pub value: HashSet<u64>,
+}
+
+#[derive(Default)]
+pub struct GSet(State);
+
+impl Crdt for GSet {
+    type Update = Add;
+    type Delta = State;
+    type Query = State;
+    type State = State;
+
+    const SUPPORTS_ELEMENTS: bool = true;
+
+    fn update(&mut self, _node_id: &NodeId, add: Self::Update) -> Result<Self::Delta> {
+        self.0.value.insert(add.element);
+        Ok(State {
+            value: HashSet::from([add.element]),
+        })
+    }
+
+    fn merge(&mut self, other: Self::State) -> Result<()> {
+        self.0.value.extend(other.value);
+        Ok(())
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) -> Result<()> {
+        self.merge(delta)
+    }
+
+    fn query(&self) -> Self::Query {
+        self.0.clone()
+    }
+
+    fn state(&self) -> Self::State {
+        self.0.clone()
+    }
+
+    fn elements(&self) -> Option<Vec<u64>> {
+        Some(self.0.value.iter().copied().collect())
+    }
+
+    fn merge_elements(&mut self, elements: Vec<u64>) -> Result<()> {
+        self.0.value.extend(elements);
+        Ok(())
+    }
+}
+
+impl ClientApi for GSet {
+    type Request = AddRequest<Add>;
+    type Response = AddResponse<State>;
+
+    fn serve(
+        &mut self,
+        node_id: &NodeId,
+        request: Self::Request,
+    ) -> Result<(Self::Response, Option<Self::Delta>)> {
+        request.serve(self, node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::testing::check_convergence;
+
+    #[test]
+    fn converges() {
+        check_convergence::<GSet>(|rng, _| Add {
+            element: rng.gen_range(0..100),
+        });
+    }
+}
@@ -1 +1,13 @@
+pub mod causal;
 pub mod crdt;
+pub mod delta;
+pub mod g_counter;
+pub mod g_set;
+pub mod list;
+pub mod map;
+pub mod or_set;
+pub mod pn_counter;
+pub mod register;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,408 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Result};
+use base::{kv::ErrorCode, node::NodeId};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::crdt::{ClientApi, Crdt};
+
+// Unique id of a list element. Ids are ordered by a Lamport counter, ties broken by node
+// id, and an element always gets a higher id than every id its replica has seen.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ElementId {
+    pub counter: u64,
+    pub node_id: NodeId,
+}
+
+impl std::fmt::Display for ElementId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.node_id, self.counter)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Element<V> {
+    pub id: ElementId,
+    pub value: V,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Insert<V> {
+    pub id: ElementId,
+    // `None` inserts at the head.
+    pub after: Option<ElementId>,
+    pub value: V,
+}
+
+// Both the state and deltas: inserts and ids of deleted elements.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Ops<V> {
+    pub inserts: Vec<Insert<V>>,
+    pub deletes: Vec<ElementId>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ListUpdate<V> {
+    Insert { after: Option<ElementId>, value: V },
+    Delete { id: ElementId },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ListRequest<V> {
+    Insert {
+        #[serde(default)]
+        after: Option<ElementId>,
+        value: V,
+    },
+    Delete {
+        id: ElementId,
+    },
+    Read,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ListResponse<V> {
+    InsertOk { id: ElementId },
+    DeleteOk,
+    ReadOk { value: Vec<Element<V>> },
+    Error { code: ErrorCode, text: String },
+}
+
+// Replicated growable array (RGA). Every element is inserted right after an existing one
+// and the elements form a tree: the list is its pre-order traversal, with the children
+// of an element newest first. Concurrent inserts after the same element thus end up in
+// the same order everywhere. Deleted elements stay in the tree as tombstones, as later
+// inserts may still refer to them.
+#[derive(Debug)]
+pub struct Rga<V> {
+    counter: u64,
+    // Element to the one it was inserted after, and its value.
+    elements: HashMap<ElementId, (Option<ElementId>, V)>,
+    // Children of each element (`None` for the head), newest first.
+    children: HashMap<Option<ElementId>, Vec<ElementId>>,
+    deleted: HashSet<ElementId>,
+    // Inserts merged before the element they follow, by that element.
+    pending: HashMap<ElementId, Vec<Insert<V>>>,
+}
+
+impl<V> Default for Rga<V> {
+    fn default() -> Self {
+        Self {
+            counter: 0,
+            elements: HashMap::new(),
+            children: HashMap::new(),
+            deleted: HashSet::new(),
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<V: Clone> Rga<V> {
+    pub fn contains(&self, id: &ElementId) -> bool {
+        self.elements.contains_key(id)
+    }
+
+    pub fn insert(
+        &mut self,
+        node_id: &NodeId,
+        after: Option<ElementId>,
+        value: V,
+    ) -> Result<Ops<V>> {
+        if let Some(after) = after.as_ref().filter(|after| !self.contains(after)) {
+            bail!("element {after} doesn't exist");
+        }
+        let insert = Insert {
+            id: ElementId {
+                counter: self.counter + 1,
+                node_id: node_id.clone(),
+            },
+            after,
+            value,
+        };
+        self.apply_insert(insert.clone());
+        Ok(Ops {
+            inserts: vec![insert],
+            deletes: Vec::new(),
+        })
+    }
+
+    pub fn delete(&mut self, id: ElementId) -> Result<Ops<V>> {
+        if !self.contains(&id) {
+            bail!("element {id} doesn't exist");
+        }
+        self.deleted.insert(id.clone());
+        Ok(Ops {
+            inserts: Vec::new(),
+            deletes: vec![id],
+        })
+    }
+
+    pub fn merge_ops(&mut self, ops: Ops<V>) {
+        for insert in ops.inserts {
+            self.apply_insert(insert);
+        }
+        self.deleted.extend(ops.deletes);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Element<&V>> {
+        let mut stack = self.children_of(&None).rev().collect::<Vec<_>>();
+        std::iter::from_fn(move || loop {
+            let id = stack.pop()?;
+            let key = Some(id.clone());
+            stack.extend(self.children_of(&key).rev());
+            if !self.deleted.contains(id) {
+                let (_, value) = &self.elements[id];
+                return Some(Element {
+                    id: id.clone(),
+                    value,
+                });
+            }
+        })
+    }
+
+    fn children_of(&self, id: &Option<ElementId>) -> impl DoubleEndedIterator<Item = &ElementId> {
+        self.children.get(id).into_iter().flatten()
+    }
+
+    // Inserts that follow elements this replica doesn't have yet wait for them.
+    fn apply_insert(&mut self, insert: Insert<V>) {
+        let mut ready = vec![insert];
+        while let Some(insert) = ready.pop() {
+            if self.contains(&insert.id) {
+                continue;
+            }
+            if let Some(after) = insert.after.as_ref().filter(|after| !self.contains(after)) {
+                self.pending.entry(after.clone()).or_default().push(insert);
+                continue;
+            }
+
+            self.counter = self.counter.max(insert.id.counter);
+            let siblings = self.children.entry(insert.after.clone()).or_default();
+            let index = siblings.partition_point(|sibling| *sibling > insert.id);
+            siblings.insert(index, insert.id.clone());
+            ready.extend(self.pending.remove(&insert.id).unwrap_or_default());
+            self.elements
+                .insert(insert.id, (insert.after, insert.value));
+        }
+    }
+}
+
+impl<V> Crdt for Rga<V>
+where
+    V: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    type Update = ListUpdate<V>;
+    type Delta = Ops<V>;
+    type Query = Vec<Element<V>>;
+    type State = Ops<V>;
+
+    fn update(&mut self, node_id: &NodeId, update: Self::Update) -> Result<Self::Delta> {
+        match update {
+            ListUpdate::Insert { after, value } => self.insert(node_id, after, value),
+            ListUpdate::Delete { id } => self.delete(id),
+        }
+    }
+
+    fn merge(&mut self, other: Self::State) -> Result<()> {
+        self.merge_ops(other);
+        Ok(())
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) -> Result<()> {
+        self.merge_ops(delta);
+        Ok(())
+    }
+
+    fn query(&self) -> Self::Query {
+        self.iter()
+            .map(|element| Element {
+                id: element.id,
+                value: element.value.clone(),
+            })
+            .collect()
+    }
+
+    // Inserts still waiting for their predecessor are part of the state too.
+    fn state(&self) -> Self::State {
+        let inserts = self
+            .elements
+            .iter()
+            .map(|(id, (after, value))| Insert {
+                id: id.clone(),
+                after: after.clone(),
+                value: value.clone(),
+            })
+            .chain(self.pending.values().flatten().cloned())
+            .collect();
+        Ops {
+            inserts,
+            deletes: self.deleted.iter().cloned().collect(),
+        }
+    }
+}
+
+// Elements are referred to by ids from earlier reads, which this replica may not have
+// received yet: such requests fail without taking effect.
+impl<V> ClientApi for Rga<V>
+where
+    V: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    type Request = ListRequest<V>;
+    type Response = ListResponse<V>;
+
+    fn serve(
+        &mut self,
+        node_id: &NodeId,
+        request: Self::Request,
+    ) -> Result<(Self::Response, Option<Self::Delta>)> {
+        let (update, unknown) = match request {
+            ListRequest::Read => {
+                return Ok((
+                    ListResponse::ReadOk {
+                        value: self.query(),
+                    },
+                    None,
+                ))
+            }
+            ListRequest::Insert { after, value } => {
+                let unknown = after.clone().filter(|after| !self.contains(after));
+                (ListUpdate::Insert { after, value }, unknown)
+            }
+            ListRequest::Delete { id } => {
+                let unknown = Some(id.clone()).filter(|id| !self.contains(id));
+                (ListUpdate::Delete { id }, unknown)
+            }
+        };
+        if let Some(id) = unknown {
+            let response = ListResponse::Error {
+                code: ErrorCode::KeyDoesNotExist,
+                text: format!("element {id} doesn't exist"),
+            };
+            return Ok((response, None));
+        }
+
+        let delta = self.update(node_id, update)?;
+        let response = match delta.inserts.first() {
+            Some(insert) => ListResponse::InsertOk {
+                id: insert.id.clone(),
+            },
+            None => ListResponse::DeleteOk,
+        };
+        Ok((response, Some(delta)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::sim::node_id;
+    use rand::{seq::SliceRandom, Rng};
+
+    use super::*;
+    use crate::testing::check_convergence;
+
+    fn values(rga: &Rga<u64>) -> Vec<u64> {
+        rga.iter().map(|element| *element.value).collect()
+    }
+
+    // Inserts `value` after the element holding `after`, returns the delta.
+    fn insert(rga: &mut Rga<u64>, node: &str, after: Option<u64>, value: u64) -> Ops<u64> {
+        let after = after.map(|after| {
+            let element = rga.iter().find(|element| *element.value == after);
+            element.unwrap().id
+        });
+        rga.insert(&node_id(node), after, value).unwrap()
+    }
+
+    fn id(ops: &Ops<u64>) -> ElementId {
+        ops.inserts[0].id.clone()
+    }
+
+    #[test]
+    fn inserts_go_right_after_their_predecessor() {
+        let mut rga = Rga::default();
+        insert(&mut rga, "n0", None, 1);
+        insert(&mut rga, "n0", Some(1), 2);
+        insert(&mut rga, "n0", Some(1), 3);
+        assert_eq!(values(&rga), [1, 3, 2]);
+
+        insert(&mut rga, "n0", None, 4);
+        insert(&mut rga, "n0", Some(2), 5);
+        // 3 and what follows it come before its older sibling 2.
+        insert(&mut rga, "n0", Some(3), 6);
+        assert_eq!(values(&rga), [4, 1, 3, 6, 2, 5]);
+    }
+
+    #[test]
+    fn concurrent_inserts_are_ordered_newest_first_everywhere() {
+        let (mut a, mut b) = (Rga::default(), Rga::default());
+        let first = insert(&mut a, "n0", None, 1);
+        b.merge_ops(first);
+
+        // Same counter, n1 wins the tie.
+        let from_a = insert(&mut a, "n0", Some(1), 2);
+        let from_b = insert(&mut b, "n1", Some(1), 3);
+        a.merge_ops(from_b);
+        b.merge_ops(from_a.clone());
+        assert_eq!(values(&a), [1, 3, 2]);
+        assert_eq!(values(&b), [1, 3, 2]);
+
+        // An insert that has seen both is newer than both.
+        let later = insert(&mut a, "n0", Some(1), 4);
+        assert!(id(&later) > id(&from_a));
+        b.merge_ops(later);
+        assert_eq!(values(&a), [1, 4, 3, 2]);
+        assert_eq!(values(&b), [1, 4, 3, 2]);
+    }
+
+    #[test]
+    fn deletes_may_arrive_before_inserts() {
+        let (mut a, mut b) = (Rga::default(), Rga::default());
+        let first = insert(&mut a, "n0", None, 1);
+        let second = insert(&mut a, "n0", Some(1), 2);
+        let deleted = a.delete(id(&second)).unwrap();
+        assert_eq!(values(&a), [1]);
+
+        b.merge_ops(deleted);
+        b.merge_ops(first);
+        b.merge_ops(second);
+        assert_eq!(values(&b), [1]);
+    }
+
+    #[test]
+    fn inserts_wait_for_their_predecessor() {
+        let (mut a, mut b) = (Rga::default(), Rga::default());
+        let first = insert(&mut a, "n0", None, 1);
+        let second = insert(&mut a, "n0", Some(1), 2);
+        let third = insert(&mut a, "n0", Some(2), 3);
+
+        b.merge_ops(third);
+        b.merge_ops(second);
+        assert!(values(&b).is_empty());
+        // Waiting inserts are part of the state.
+        let mut c = Rga::default();
+        c.merge_ops(b.state());
+
+        b.merge_ops(first.clone());
+        c.merge_ops(first);
+        assert_eq!(values(&b), [1, 2, 3]);
+        assert_eq!(values(&c), [1, 2, 3]);
+        assert!(b.pending.is_empty());
+    }
+
+    // Inserts and deletes refer to elements the replica has.
+    #[test]
+    fn converges() {
+        check_convergence::<Rga<u64>>(|rng, rga| {
+            let ids = rga.elements.keys().cloned().collect::<Vec<_>>();
+            match ids.choose(rng) {
+                Some(id) if rng.gen_bool(0.3) => ListUpdate::Delete { id: id.clone() },
+                after => ListUpdate::Insert {
+                    after: after.cloned().filter(|_| rng.gen_bool(0.9)),
+                    value: rng.gen_range(0..100),
+                },
+            }
+        });
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::Hash,
+};
+
+use anyhow::Result;
+use base::node::NodeId;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    causal::{Dot, DotKernel},
+    crdt::{ClientApi, Crdt},
+};
+
+// Both the state and deltas: each replica's contribution to each key, tagged with a dot.
+pub type MapDelta<K, C> = DotKernel<(K, <C as Crdt>::State)>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum MapUpdate<K, U> {
+    Update { key: K, update: U },
+    Remove { key: K },
+}
+
+// Requests of the nested CRDT's protocol with a `key` added, plus operations on keys.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum MapRequest<K, R> {
+    Keys(KeysRequest<K>),
+    Key {
+        key: K,
+        #[serde(flatten)]
+        request: R,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeysRequest<K> {
+    RemoveKey { key: K },
+    ReadKeys,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum MapResponse<K, R> {
+    Keys(KeysResponse<K>),
+    Key(R),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeysResponse<K> {
+    RemoveKeyOk,
+    ReadKeysOk { keys: Vec<K> },
+}
+
+// Every key holds an independent CRDT `C`, merged per key.
+//
+// Nested CRDTs can't forget part of their state (e.g. increments of a counter), so what
+// replicas replicate is their own contribution to each key: the updates they made to it,
+// as a state of `C` tagged with a dot that's replaced on each update. A key's value is
+// the merge of the contributions left and the key exists while there is one. Removing a
+// key removes the contributions it observed, as an OR-Set removes elements, so an update
+// made concurrently survives along with the rest of its replica's contribution.
+pub struct CrdtMap<K, C: Crdt> {
+    contributions: DotKernel<(K, C::State)>,
+    // Merged contributions, per key.
+    values: HashMap<K, C>,
+}
+
+impl<K, C: Crdt> Default for CrdtMap<K, C> {
+    fn default() -> Self {
+        Self {
+            contributions: DotKernel::default(),
+            values: HashMap::new(),
+        }
+    }
+}
+
+impl<K, C> CrdtMap<K, C>
+where
+    K: Clone + Eq + Hash + Ord,
+    C: Crdt,
+{
+    pub fn get(&self, key: &K) -> Option<&C> {
+        self.values.get(key)
+    }
+
+    pub fn keys(&self) -> Vec<K> {
+        let mut keys = self.values.keys().cloned().collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<MapDelta<K, C>> {
+        self.values.remove(key)?;
+        Some(self.contributions.remove(|(other, _)| other == key))
+    }
+
+    // Runs `f` on the key's CRDT, a missing key starts out empty and is only created if
+    // `f` returns a delta.
+    fn with_value<T>(
+        &mut self,
+        node_id: &NodeId,
+        key: K,
+        f: impl FnOnce(&mut C) -> Result<(T, Option<C::Delta>)>,
+    ) -> Result<(T, Option<MapDelta<K, C>>)> {
+        let created = !self.values.contains_key(&key);
+        let result = f(self.values.entry(key.clone()).or_default());
+        let (output, delta) = match result {
+            Ok((output, Some(delta))) => (output, delta),
+            result => {
+                if created {
+                    self.values.remove(&key);
+                }
+                return result.map(|(output, _)| (output, None));
+            }
+        };
+
+        // Our contribution takes the delta in and replaces the previous one.
+        let mine = |dot: &Dot, other: &K| dot.node_id == *node_id && *other == key;
+        let mut contribution = C::default();
+        for (dot, (other, state)) in self.contributions.iter() {
+            if mine(&dot, other) {
+                contribution.merge(state.clone())?;
+            }
+        }
+        contribution.merge_delta(delta)?;
+        let mut map_delta = self
+            .contributions
+            .remove_dots(|dot, (other, _)| mine(dot, other));
+        map_delta.merge(self.contributions.add(node_id, (key, contribution.state())));
+        Ok((output, Some(map_delta)))
+    }
+
+    fn merge_contributions(&mut self, other: MapDelta<K, C>) -> Result<()> {
+        let added = other
+            .iter()
+            .filter(|(dot, _)| !self.contributions.context().contains(dot))
+            .map(|(_, (key, state))| (key.clone(), state.clone()))
+            .collect::<Vec<_>>();
+        let theirs = other.iter().map(|(dot, _)| dot).collect::<HashSet<_>>();
+        let removed = self
+            .contributions
+            .iter()
+            .filter(|(dot, _)| other.context().contains(dot) && !theirs.contains(dot))
+            .map(|(_, (key, _))| key.clone())
+            .collect::<HashSet<_>>();
+        self.contributions.merge(other);
+
+        // Merging is enough for new contributions, keys that lost some start over.
+        for key in &removed {
+            let mut value = C::default();
+            let mut present = false;
+            for (other, state) in self.contributions.values() {
+                if other == key {
+                    value.merge(state.clone())?;
+                    present = true;
+                }
+            }
+            if present {
+                self.values.insert(key.clone(), value);
+            } else {
+                self.values.remove(key);
+            }
+        }
+        for (key, state) in added {
+            if !removed.contains(&key) {
+                self.values.entry(key).or_default().merge(state)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K, C> Crdt for CrdtMap<K, C>
+where
+    K: Serialize + DeserializeOwned + Clone + Eq + Hash + Ord + Send + 'static,
+    C: Crdt,
+{
+    type Update = MapUpdate<K, C::Update>;
+    type Delta = MapDelta<K, C>;
+    type Query = BTreeMap<K, C::Query>;
+    type State = MapDelta<K, C>;
+
+    fn update(&mut self, node_id: &NodeId, update: Self::Update) -> Result<Self::Delta> {
+        let delta = match update {
+            MapUpdate::Update { key, update } => {
+                let (_, delta) = self.with_value(node_id, key, |value| {
+                    Ok(((), Some(value.update(node_id, update)?)))
+                })?;
+                delta
+            }
+            MapUpdate::Remove { key } => self.remove(&key),
+        };
+        Ok(delta.unwrap_or_default())
+    }
+
+    fn merge(&mut self, other: Self::State) -> Result<()> {
+        self.merge_contributions(other)
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) -> Result<()> {
+        self.merge_contributions(delta)
+    }
+
+    fn query(&self) -> Self::Query {
+        self.values
+            .iter()
+            .map(|(key, value)| (key.clone(), value.query()))
+            .collect()
+    }
+
+    // The context of removed contributions comes along, so that merging the state removes
+    // them elsewhere.
+    fn state(&self) -> Self::State {
+        self.contributions.clone()
+    }
+}
+
+impl<K, C> ClientApi for CrdtMap<K, C>
+where
+    K: Serialize + DeserializeOwned + Clone + Eq + Hash + Ord + Send + 'static,
+    C: ClientApi,
+{
+    type Request = MapRequest<K, C::Request>;
+    type Response = MapResponse<K, C::Response>;
+
+    fn serve(
+        &mut self,
+        node_id: &NodeId,
+        request: Self::Request,
+    ) -> Result<(Self::Response, Option<Self::Delta>)> {
+        match request {
+            MapRequest::Key { key, request } => {
+                let (response, delta) =
+                    self.with_value(node_id, key, |value| value.serve(node_id, request))?;
+                Ok((MapResponse::Key(response), delta))
+            }
+            MapRequest::Keys(KeysRequest::RemoveKey { key }) => {
+                let delta = self.remove(&key);
+                Ok((MapResponse::Keys(KeysResponse::RemoveKeyOk), delta))
+            }
+            MapRequest::Keys(KeysRequest::ReadKeys) => {
+                let keys = self.keys();
+                Ok((MapResponse::Keys(KeysResponse::ReadKeysOk { keys }), None))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::sim::node_id;
+    use rand::Rng;
+
+    use super::*;
+    use crate::{
+        g_counter::{self, GCounter},
+        or_set::{self, OrSet},
+        testing::check_convergence,
+    };
+
+    fn key(rng: &mut impl Rng) -> u64 {
+        rng.gen_range(0..3)
+    }
+
+    #[test]
+    fn g_counter_map_converges() {
+        check_convergence::<CrdtMap<u64, GCounter>>(|rng, _| {
+            if rng.gen_bool(0.1) {
+                MapUpdate::Remove { key: key(rng) }
+            } else {
+                let update = g_counter::Add {
+                    delta: rng.gen_range(0..10),
+                };
+                MapUpdate::Update {
+                    key: key(rng),
+                    update,
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn concurrent_update_survives_remove() {
+        let add = |delta| MapUpdate::Update {
+            key: 0,
+            update: g_counter::Add { delta },
+        };
+        let mut replicas = (0..3)
+            .map(|_| CrdtMap::<u64, GCounter>::default())
+            .collect::<Vec<_>>();
+        let created = replicas[0].update(&node_id("n0"), add(1)).unwrap();
+        for replica in &mut replicas[1..] {
+            replica.merge_delta(created.clone()).unwrap();
+        }
+
+        // n1 removes the key while n2, having seen n0's update too, adds to it.
+        let removed = replicas[1]
+            .update(&node_id("n1"), MapUpdate::Remove { key: 0 })
+            .unwrap();
+        let updated = replicas[2].update(&node_id("n2"), add(2)).unwrap();
+        assert_eq!(replicas[1].keys(), Vec::<u64>::new());
+        for replica in &mut replicas {
+            replica.merge_delta(removed.clone()).unwrap();
+            replica.merge_delta(updated.clone()).unwrap();
+        }
+
+        // The removal only takes away n0's update, which it observed.
+        for replica in &replicas {
+            let value = replica.query().into_iter().collect::<Vec<_>>();
+            assert_eq!(value, vec![(0, g_counter::Query { value: 2 })]);
+        }
+    }
+
+    #[test]
+    fn or_set_map_converges() {
+        check_convergence::<CrdtMap<u64, OrSet>>(|rng, _| {
+            let element = rng.gen_range(0..5);
+            match rng.gen_range(0..10) {
+                0 => MapUpdate::Remove { key: key(rng) },
+                1..=5 => MapUpdate::Update {
+                    key: key(rng),
+                    update: or_set::Update::Add(element),
+                },
+                _ => MapUpdate::Update {
+                    key: key(rng),
+                    update: or_set::Update::Remove(element),
+                },
+            }
+        });
+    }
+}
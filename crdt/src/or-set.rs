@@ -0,0 +1,9 @@
+use anyhow::Result;
+use base::utils::init_log;
+use crdt::{crdt::CrdtService, or_set::OrSet};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    init_log()?;
+    CrdtService::<OrSet>::run().await
+}
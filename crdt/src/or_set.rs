@@ -0,0 +1,170 @@
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use base::node::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    causal::DotKernel,
+    crdt::{ClientApi, Crdt},
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    Add { element: u64 },
+    Remove { element: u64 },
+    Read,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    AddOk,
+    RemoveOk,
+    ReadOk { value: BTreeSet<u64> },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Update {
+    Add(u64),
+    Remove(u64),
+}
+
+// Add-wins observed-remove set: every add tags the element with a new dot and a remove
+// only removes the dots it has observed, so an add concurrent with a remove wins.
+#[derive(Default)]
+pub struct OrSet(DotKernel<u64>);
+
+impl Crdt for OrSet {
+    type Update = Update;
+    type Delta = DotKernel<u64>;
+    type Query = BTreeSet<u64>;
+    type State = DotKernel<u64>;
+
+    fn update(&mut self, node_id: &NodeId, update: Self::Update) -> Result<Self::Delta> {
+        match update {
+            // Dots the element already had are replaced, so they don't pile up.
+            Update::Add(element) => {
+                let mut delta = self.0.remove(|other| *other == element);
+                delta.merge(self.0.add(node_id, element));
+                Ok(delta)
+            }
+            Update::Remove(element) => Ok(self.0.remove(|other| *other == element)),
+        }
+    }
+
+    fn merge(&mut self, other: Self::State) -> Result<()> {
+        self.0.merge(other);
+        Ok(())
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) -> Result<()> {
+        self.merge(delta)
+    }
+
+    fn query(&self) -> Self::Query {
+        self.0.values().copied().collect()
+    }
+
+    fn state(&self) -> Self::State {
+        self.0.clone()
+    }
+}
+
+impl ClientApi for OrSet {
+    type Request = Request;
+    type Response = Response;
+
+    fn serve(
+        &mut self,
+        node_id: &NodeId,
+        request: Self::Request,
+    ) -> Result<(Self::Response, Option<Self::Delta>)> {
+        match request {
+            Request::Add { element } => {
+                let delta = self.update(node_id, Update::Add(element))?;
+                Ok((Response::AddOk, Some(delta)))
+            }
+            Request::Remove { element } => {
+                let delta = self.update(node_id, Update::Remove(element))?;
+                Ok((Response::RemoveOk, Some(delta)))
+            }
+            Request::Read => Ok((
+                Response::ReadOk {
+                    value: self.query(),
+                },
+                None,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::sim::node_id;
+    use rand::Rng;
+
+    use super::*;
+    use crate::testing::check_convergence;
+
+    fn add(set: &mut OrSet, node: &str, element: u64) -> DotKernel<u64> {
+        set.update(&node_id(node), Update::Add(element)).unwrap()
+    }
+
+    fn remove(set: &mut OrSet, node: &str, element: u64) -> DotKernel<u64> {
+        set.update(&node_id(node), Update::Remove(element)).unwrap()
+    }
+
+    #[test]
+    fn removes_observed_elements() {
+        let (mut a, mut b) = (OrSet::default(), OrSet::default());
+        let delta = add(&mut a, "n0", 1);
+        b.merge_delta(delta).unwrap();
+        let delta = add(&mut a, "n0", 2);
+        b.merge_delta(delta).unwrap();
+
+        let before_remove = a.state();
+        let delta = remove(&mut b, "n1", 1);
+        assert_eq!(b.query(), BTreeSet::from([2]));
+        a.merge_delta(delta).unwrap();
+        assert_eq!(a.query(), BTreeSet::from([2]));
+
+        // Removed elements don't come back with an older state.
+        b.merge(before_remove).unwrap();
+        assert_eq!(b.query(), BTreeSet::from([2]));
+    }
+
+    #[test]
+    fn concurrent_add_wins_over_remove() {
+        let (mut a, mut b) = (OrSet::default(), OrSet::default());
+        let delta = add(&mut a, "n0", 1);
+        b.merge_delta(delta).unwrap();
+
+        // a re-adds 1 before it sees that b removed it.
+        let removed = remove(&mut b, "n1", 1);
+        let added = add(&mut a, "n0", 1);
+        a.merge_delta(removed).unwrap();
+        b.merge_delta(added).unwrap();
+        assert_eq!(a.query(), BTreeSet::from([1]));
+        assert_eq!(b.query(), BTreeSet::from([1]));
+
+        // A remove that has seen the add still removes it.
+        let removed = remove(&mut b, "n1", 1);
+        a.merge_delta(removed).unwrap();
+        assert!(a.query().is_empty());
+    }
+
+    // Few elements, so that adds and removes of the same one are often concurrent.
+    #[test]
+    fn converges() {
+        check_convergence::<OrSet>(|rng, _| {
+            let element = rng.gen_range(0..10);
+            if rng.gen_bool(0.5) {
+                Update::Add(element)
+            } else {
+                Update::Remove(element)
+            }
+        });
+    }
+}
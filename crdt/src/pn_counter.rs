@@ -0,0 +1,101 @@
+use std::{cmp, collections::HashMap};
+
+use anyhow::Result;
+use base::node::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::{AddRequest, AddResponse, ClientApi, Crdt};
+
+#[derive(Serialize, Deserialize)]
+pub struct Add {
+    pub delta: i64,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct Query {
+    pub value: i64,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct State {
+    pub counters: HashMap<NodeId, (i64, i64)>,
+}
+
+#[derive(Default)]
+pub struct PnCounter(State);
+
+impl Crdt for PnCounter {
+    type Update = Add;
+    type Delta = State;
+    type Query = Query;
+    type State = State;
+
+    // Each replica only updates its own counters, so the delta is just those counters.
+    fn update(&mut self, node_id: &NodeId, add: Self::Update) -> Result<Self::Delta> {
+        let pos = cmp::max(add.delta, 0);
+        let neg = cmp::min(add.delta, 0);
+
+        let (p, n) = self.0.counters.entry(node_id.clone()).or_default();
+        *p += pos;
+        *n += neg;
+        Ok(State {
+            counters: HashMap::from([(node_id.clone(), (*p, *n))]),
+        })
+    }
+
+    fn merge(&mut self, other: Self::State) -> Result<()> {
+        let counters = &mut self.0.counters;
+        for (node_id, (other_p, other_n)) in other.counters {
+            counters
+                .entry(node_id)
+                .and_modify(|(p, n)| {
+                    *p = cmp::max(*p, other_p);
+                    *n = cmp::min(*n, other_n);
+                })
+                .or_insert((other_p, other_n));
+        }
+        Ok(())
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) -> Result<()> {
+        self.merge(delta)
+    }
+
+    fn query(&self) -> Self::Query {
+        Query {
+            value: self.0.counters.values().map(|(p, n)| *p + *n).sum(),
+        }
+    }
+
+    fn state(&self) -> Self::State {
+        self.0.clone()
+    }
+}
+
+impl ClientApi for PnCounter {
+    type Request = AddRequest<Add>;
+    type Response = AddResponse<Query>;
+
+    fn serve(
+        &mut self,
+        node_id: &NodeId,
+        request: Self::Request,
+    ) -> Result<(Self::Response, Option<Self::Delta>)> {
+        request.serve(self, node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::testing::check_convergence;
+
+    #[test]
+    fn converges() {
+        check_convergence::<PnCounter>(|rng, _| Add {
+            delta: rng.gen_range(-10..10),
+        });
+    }
+}
@@ -0,0 +1,545 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use anyhow::Result;
+use base::{
+    clock::{HlcTimestamp, HybridClock},
+    kv::{CasParams, ErrorCode, KvRequest, KvResponse},
+    node::NodeId,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    causal::DotKernel,
+    crdt::{ClientApi, Crdt},
+};
+
+// Total order of writes: by hybrid timestamp, ties (writes in the same millisecond on
+// different nodes) broken by node id.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Stamp {
+    pub timestamp: HlcTimestamp,
+    pub node_id: NodeId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Stamped<V> {
+    pub stamp: Stamp,
+    pub value: V,
+}
+
+// Keys of Maelstrom's lin-kv workload are integers, other clients may use strings.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(untagged)]
+pub enum Key {
+    Int(u64),
+    String(String),
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(key) => write!(f, "{key}"),
+            Self::String(key) => f.write_str(key),
+        }
+    }
+}
+
+// Lin-kv-like protocol of a single register. A single register has no keys, so requests
+// with a `key` are rejected rather than silently mixing up several registers (`LwwMap`
+// serves those). A CAS can't be atomic without coordinating replicas, it's rejected too.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RegisterRequest<V> {
+    Read {
+        key: Option<Key>,
+    },
+    Write {
+        key: Option<Key>,
+        value: V,
+    },
+    Cas {
+        key: Option<Key>,
+        #[serde(flatten)]
+        params: CasParams<V>,
+    },
+}
+
+impl<V> RegisterRequest<V> {
+    fn key(&self) -> Option<&Key> {
+        match self {
+            Self::Read { key } | Self::Write { key, .. } | Self::Cas { key, .. } => key.as_ref(),
+        }
+    }
+}
+
+fn key_not_supported<V>(key: &Key) -> KvResponse<V> {
+    KvResponse::error(
+        ErrorCode::NotSupported,
+        format!("single registers have no keys, got {key}"),
+    )
+}
+
+fn cas_not_supported<V>() -> KvResponse<V> {
+    KvResponse::error(
+        ErrorCode::NotSupported,
+        "cas isn't supported by replicated registers",
+    )
+}
+
+fn read_response<V>(value: Option<V>) -> KvResponse<V> {
+    match value {
+        Some(value) => KvResponse::ReadOk { value },
+        None => KvResponse::error(ErrorCode::KeyDoesNotExist, "not written yet"),
+    }
+}
+
+// Last-writer-wins register: concurrent writes are resolved by `Stamp`, one of them wins
+// and the others are lost. Stamps of merged writes advance the clock, so a write made
+// after seeing another one always wins over it.
+#[derive(Debug)]
+pub struct LwwRegister<V> {
+    clock: HybridClock,
+    value: Option<Stamped<V>>,
+}
+
+impl<V> Default for LwwRegister<V> {
+    fn default() -> Self {
+        Self {
+            clock: HybridClock::system(),
+            value: None,
+        }
+    }
+}
+
+impl<V: Clone> LwwRegister<V> {
+    pub fn get(&self) -> Option<&V> {
+        self.value.as_ref().map(|stamped| &stamped.value)
+    }
+
+    pub fn set(&mut self, node_id: &NodeId, value: V) -> Stamped<V> {
+        let stamped = Stamped {
+            stamp: Stamp {
+                timestamp: self.clock.now(),
+                node_id: node_id.clone(),
+            },
+            value,
+        };
+        self.value = Some(stamped.clone());
+        stamped
+    }
+
+    pub fn merge_stamped(&mut self, other: Stamped<V>) {
+        self.clock.observe(other.stamp.timestamp);
+        if self
+            .value
+            .as_ref()
+            .is_none_or(|current| current.stamp < other.stamp)
+        {
+            self.value = Some(other);
+        }
+    }
+}
+
+impl<V> Crdt for LwwRegister<V>
+where
+    V: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    type Update = V;
+    type Delta = Stamped<V>;
+    type Query = Option<V>;
+    type State = Option<Stamped<V>>;
+
+    fn update(&mut self, node_id: &NodeId, value: Self::Update) -> Result<Self::Delta> {
+        Ok(self.set(node_id, value))
+    }
+
+    fn merge(&mut self, other: Self::State) -> Result<()> {
+        if let Some(other) = other {
+            self.merge_stamped(other);
+        }
+        Ok(())
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) -> Result<()> {
+        self.merge_stamped(delta);
+        Ok(())
+    }
+
+    fn query(&self) -> Self::Query {
+        self.get().cloned()
+    }
+
+    fn state(&self) -> Self::State {
+        self.value.clone()
+    }
+}
+
+impl<V> ClientApi for LwwRegister<V>
+where
+    V: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    type Request = RegisterRequest<V>;
+    type Response = KvResponse<V>;
+
+    fn serve(
+        &mut self,
+        node_id: &NodeId,
+        request: Self::Request,
+    ) -> Result<(Self::Response, Option<Self::Delta>)> {
+        if let Some(key) = request.key() {
+            return Ok((key_not_supported(key), None));
+        }
+        Ok(match request {
+            RegisterRequest::Read { .. } => (read_response(self.get().cloned()), None),
+            RegisterRequest::Write { value, .. } => {
+                (KvResponse::WriteOk, Some(self.set(node_id, value)))
+            }
+            RegisterRequest::Cas { .. } => (cas_not_supported(), None),
+        })
+    }
+}
+
+// Multi-value register: a write replaces the values it has seen, concurrent writes are
+// all kept and read together until a later write replaces them.
+#[derive(Debug)]
+pub struct MvRegister<V>(DotKernel<V>);
+
+impl<V> Default for MvRegister<V> {
+    fn default() -> Self {
+        Self(DotKernel::default())
+    }
+}
+
+impl<V: Clone> MvRegister<V> {
+    // Concurrent values, in the order of their dots.
+    pub fn get(&self) -> Vec<V> {
+        let mut values = self.0.iter().collect::<Vec<_>>();
+        values.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        values.into_iter().map(|(_, value)| value.clone()).collect()
+    }
+
+    pub fn set(&mut self, node_id: &NodeId, value: V) -> DotKernel<V> {
+        let mut delta = self.0.remove(|_| true);
+        delta.merge(self.0.add(node_id, value));
+        delta
+    }
+}
+
+impl<V> Crdt for MvRegister<V>
+where
+    V: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    type Update = V;
+    type Delta = DotKernel<V>;
+    type Query = Vec<V>;
+    type State = DotKernel<V>;
+
+    fn update(&mut self, node_id: &NodeId, value: Self::Update) -> Result<Self::Delta> {
+        Ok(self.set(node_id, value))
+    }
+
+    fn merge(&mut self, other: Self::State) -> Result<()> {
+        self.0.merge(other);
+        Ok(())
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) -> Result<()> {
+        self.merge(delta)
+    }
+
+    fn query(&self) -> Self::Query {
+        self.get()
+    }
+
+    fn state(&self) -> Self::State {
+        self.0.clone()
+    }
+}
+
+// Reads return every concurrent value.
+impl<V> ClientApi for MvRegister<V>
+where
+    V: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    type Request = RegisterRequest<V>;
+    type Response = KvResponse<Vec<V>>;
+
+    fn serve(
+        &mut self,
+        node_id: &NodeId,
+        request: Self::Request,
+    ) -> Result<(Self::Response, Option<Self::Delta>)> {
+        if let Some(key) = request.key() {
+            return Ok((key_not_supported(key), None));
+        }
+        Ok(match request {
+            RegisterRequest::Read { .. } => {
+                let values = self.get();
+                (read_response((!values.is_empty()).then_some(values)), None)
+            }
+            RegisterRequest::Write { value, .. } => {
+                (KvResponse::WriteOk, Some(self.set(node_id, value)))
+            }
+            RegisterRequest::Cas { .. } => (cas_not_supported(), None),
+        })
+    }
+}
+
+// Last-writer-wins register per key, all sharing one clock. States and deltas are lists
+// of entries rather than maps, since JSON objects can't have integer keys.
+#[derive(Debug)]
+pub struct LwwMap<V> {
+    clock: HybridClock,
+    entries: HashMap<Key, Stamped<V>>,
+}
+
+impl<V> Default for LwwMap<V> {
+    fn default() -> Self {
+        Self {
+            clock: HybridClock::system(),
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<V: Clone> LwwMap<V> {
+    pub fn get(&self, key: &Key) -> Option<&V> {
+        self.entries.get(key).map(|stamped| &stamped.value)
+    }
+
+    pub fn set(&mut self, node_id: &NodeId, key: Key, value: V) -> Vec<(Key, Stamped<V>)> {
+        let stamped = Stamped {
+            stamp: Stamp {
+                timestamp: self.clock.now(),
+                node_id: node_id.clone(),
+            },
+            value,
+        };
+        self.entries.insert(key.clone(), stamped.clone());
+        vec![(key, stamped)]
+    }
+
+    pub fn merge_entries(&mut self, entries: Vec<(Key, Stamped<V>)>) {
+        for (key, other) in entries {
+            self.clock.observe(other.stamp.timestamp);
+            let newer = self
+                .entries
+                .get(&key)
+                .is_none_or(|current| current.stamp < other.stamp);
+            if newer {
+                self.entries.insert(key, other);
+            }
+        }
+    }
+}
+
+impl<V> Crdt for LwwMap<V>
+where
+    V: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    type Update = (Key, V);
+    type Delta = Vec<(Key, Stamped<V>)>;
+    type Query = BTreeMap<Key, V>;
+    type State = Vec<(Key, Stamped<V>)>;
+
+    fn update(&mut self, node_id: &NodeId, (key, value): Self::Update) -> Result<Self::Delta> {
+        Ok(self.set(node_id, key, value))
+    }
+
+    fn merge(&mut self, other: Self::State) -> Result<()> {
+        self.merge_entries(other);
+        Ok(())
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) -> Result<()> {
+        self.merge_entries(delta);
+        Ok(())
+    }
+
+    fn query(&self) -> Self::Query {
+        self.entries
+            .iter()
+            .map(|(key, stamped)| (key.clone(), stamped.value.clone()))
+            .collect()
+    }
+
+    fn state(&self) -> Self::State {
+        self.entries
+            .iter()
+            .map(|(key, stamped)| (key.clone(), stamped.clone()))
+            .collect()
+    }
+}
+
+// Lin-kv protocol.
+impl<V> ClientApi for LwwMap<V>
+where
+    V: Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    type Request = KvRequest<Key, V>;
+    type Response = KvResponse<V>;
+
+    fn serve(
+        &mut self,
+        node_id: &NodeId,
+        request: Self::Request,
+    ) -> Result<(Self::Response, Option<Self::Delta>)> {
+        Ok(match request {
+            KvRequest::Read { key } => (read_response(self.get(&key).cloned()), None),
+            KvRequest::Write { key, value } => {
+                (KvResponse::WriteOk, Some(self.set(node_id, key, value)))
+            }
+            KvRequest::Cas { .. } => (cas_not_supported(), None),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::sim::node_id;
+    use rand::Rng;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::check_convergence;
+
+    fn stamped(wall: u64, node: &str, value: u64) -> Stamped<u64> {
+        Stamped {
+            stamp: Stamp {
+                timestamp: HlcTimestamp { wall, logical: 0 },
+                node_id: node_id(node),
+            },
+            value,
+        }
+    }
+
+    fn request<R: DeserializeOwned>(request: serde_json::Value) -> R {
+        serde_json::from_value(request).unwrap()
+    }
+
+    #[test]
+    fn later_lww_write_wins() {
+        let (mut a, mut b) = (LwwRegister::default(), LwwRegister::default());
+        let first = a.set(&node_id("n1"), 1);
+        b.merge_delta(first.clone()).unwrap();
+        // b has seen a's write, so its own one is later, whatever the node ids.
+        let second = b.set(&node_id("n0"), 2);
+        a.merge_delta(second).unwrap();
+        b.merge_delta(first).unwrap();
+        assert_eq!((a.query(), b.query()), (Some(2), Some(2)));
+    }
+
+    #[test]
+    fn node_id_breaks_lww_ties() {
+        for order in [["n0", "n1"], ["n1", "n0"]] {
+            let mut register = LwwRegister::default();
+            for node in order {
+                let value = if node == "n1" { 1 } else { 0 };
+                register.merge_delta(stamped(1000, node, value)).unwrap();
+            }
+            assert_eq!(register.query(), Some(1));
+        }
+    }
+
+    #[test]
+    fn mv_register_keeps_concurrent_writes_until_replaced() {
+        let (mut a, mut b) = (MvRegister::default(), MvRegister::default());
+        let first = a.set(&node_id("n0"), 1);
+        let second = b.set(&node_id("n1"), 2);
+        a.merge_delta(second).unwrap();
+        b.merge_delta(first).unwrap();
+        assert_eq!(a.query(), vec![1, 2]);
+        assert_eq!(b.query(), vec![1, 2]);
+
+        let third = b.set(&node_id("n1"), 3);
+        a.merge_delta(third).unwrap();
+        assert_eq!(a.query(), vec![3]);
+        assert_eq!(b.query(), vec![3]);
+    }
+
+    #[test]
+    fn registers_reject_keys() {
+        let node = node_id("n0");
+        let mut register = LwwRegister::<u64>::default();
+        let write = request(json!({"type": "write", "value": 1}));
+        assert!(matches!(
+            register.serve(&node, write).unwrap(),
+            (KvResponse::WriteOk, Some(_))
+        ));
+        let read = request(json!({"type": "read", "key": 0}));
+        assert!(matches!(
+            register.serve(&node, read).unwrap(),
+            (
+                KvResponse::Error {
+                    code: ErrorCode::NotSupported,
+                    ..
+                },
+                None
+            )
+        ));
+
+        let mut register = MvRegister::<u64>::default();
+        let write = request(json!({"type": "write", "key": "k", "value": 1}));
+        assert!(matches!(
+            register.serve(&node, write).unwrap(),
+            (
+                KvResponse::Error {
+                    code: ErrorCode::NotSupported,
+                    ..
+                },
+                None
+            )
+        ));
+        assert!(register.query().is_empty());
+    }
+
+    #[test]
+    fn lww_map_takes_integer_and_string_keys() {
+        let node = node_id("n0");
+        let mut map = LwwMap::<u64>::default();
+        for (key, value) in [(json!(1), 10), (json!("1"), 11), (json!("k"), 12)] {
+            let write = request(json!({"type": "write", "key": key, "value": value}));
+            map.serve(&node, write).unwrap();
+        }
+        assert_eq!(
+            map.query(),
+            BTreeMap::from([
+                (Key::Int(1), 10),
+                (Key::String("1".into()), 11),
+                (Key::String("k".into()), 12)
+            ])
+        );
+
+        let read = request(json!({"type": "read", "key": 1}));
+        assert!(matches!(
+            map.serve(&node, read).unwrap(),
+            (KvResponse::ReadOk { value: 10 }, None)
+        ));
+
+        // States survive JSON, integer keys included.
+        let state = serde_json::to_value(map.state()).unwrap();
+        let mut other = LwwMap::<u64>::default();
+        other.merge(serde_json::from_value(state).unwrap()).unwrap();
+        assert_eq!(other.query(), map.query());
+    }
+
+    #[test]
+    fn lww_register_converges() {
+        check_convergence::<LwwRegister<u64>>(|rng, _| rng.gen_range(0..100));
+    }
+
+    #[test]
+    fn mv_register_converges() {
+        check_convergence::<MvRegister<u64>>(|rng, _| rng.gen_range(0..100));
+    }
+
+    #[test]
+    fn lww_map_converges() {
+        check_convergence::<LwwMap<u64>>(|rng, _| {
+            (Key::Int(rng.gen_range(0..5)), rng.gen_range(0..100))
+        });
+    }
+}
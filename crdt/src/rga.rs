@@ -0,0 +1,9 @@
+use anyhow::Result;
+use base::utils::init_log;
+use crdt::{crdt::CrdtService, list::Rga};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    init_log()?;
+    CrdtService::<Rga<u64>>::run().await
+}
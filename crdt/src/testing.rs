@@ -0,0 +1,129 @@
+use std::fmt::Debug;
+
+use base::node::NodeId;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::crdt::Crdt;
+
+const SEEDS: u64 = 50;
+const REPLICAS: usize = 4;
+const STEPS: usize = 200;
+
+// What replicas send each other: deltas of updates and, now and then, whole states.
+enum Message<C: Crdt> {
+    Delta(C::Delta),
+    State(C::State),
+}
+
+impl<C: Crdt> Clone for Message<C> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Delta(delta) => Self::Delta(delta.clone()),
+            Self::State(state) => Self::State(state.clone()),
+        }
+    }
+}
+
+fn node_id(replica: usize) -> NodeId {
+    serde_json::from_value(format!("n{replica}").into()).unwrap()
+}
+
+fn deliver<C: Crdt>(replica: &mut C, message: Message<C>) {
+    match message {
+        Message::Delta(delta) => replica.merge_delta(delta),
+        Message::State(state) => replica.merge(state),
+    }
+    .unwrap()
+}
+
+fn join<C: Crdt>(states: impl IntoIterator<Item = C::State>) -> C {
+    let mut crdt = C::default();
+    for state in states {
+        crdt.merge(state).unwrap();
+    }
+    crdt
+}
+
+// Checks that replicas of `C` converge however their messages are delivered. Random
+// updates, generated by `update` for a replica, are made on random replicas, while their
+// deltas and states reach random replicas late, out of order and duplicated. Merges are
+// also checked to be commutative, associative and idempotent. States are compared by
+// `query()`: different states may well mean the same.
+pub fn check_convergence<C>(mut update: impl FnMut(&mut StdRng, &C) -> C::Update)
+where
+    C: Crdt,
+    C::Query: PartialEq + Debug,
+{
+    for seed in 0..SEEDS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut replicas = (0..REPLICAS).map(|_| C::default()).collect::<Vec<_>>();
+        let mut sent = Vec::<Message<C>>::new();
+
+        for _ in 0..STEPS {
+            let replica = rng.gen_range(0..REPLICAS);
+            match rng.gen_range(0..10) {
+                0..=4 => {
+                    let update = update(&mut rng, &replicas[replica]);
+                    let delta = replicas[replica].update(&node_id(replica), update).unwrap();
+                    sent.push(Message::Delta(delta));
+                }
+                5 => sent.push(Message::State(replicas[replica].state())),
+                _ => {
+                    if let Some(message) = sent.choose(&mut rng) {
+                        deliver(&mut replicas[replica], message.clone());
+                    }
+                }
+            }
+        }
+
+        let states = replicas.iter().map(C::state).collect::<Vec<_>>();
+        check_laws::<C>(seed, &states);
+
+        // Every replica eventually gets every message, some of them twice.
+        for replica in &mut replicas {
+            let mut messages = sent.clone();
+            messages.extend(sent.choose_multiple(&mut rng, sent.len() / 2).cloned());
+            messages.shuffle(&mut rng);
+            for message in messages {
+                deliver(replica, message);
+            }
+        }
+        let expected = join::<C>(states).query();
+        for (i, replica) in replicas.iter().enumerate() {
+            assert_eq!(
+                replica.query(),
+                expected,
+                "seed {seed}: replica {i} diverged"
+            );
+        }
+    }
+}
+
+fn check_laws<C>(seed: u64, states: &[C::State])
+where
+    C: Crdt,
+    C::Query: PartialEq + Debug,
+{
+    for a in states {
+        let once = join::<C>([a.clone()]).query();
+        let twice = join::<C>([a.clone(), a.clone()]).query();
+        assert_eq!(twice, once, "seed {seed}: merge isn't idempotent");
+
+        for b in states {
+            let ab = join::<C>([a.clone(), b.clone()]);
+            let ba = join::<C>([b.clone(), a.clone()]);
+            assert_eq!(
+                ab.query(),
+                ba.query(),
+                "seed {seed}: merge isn't commutative"
+            );
+
+            for c in states {
+                let ab_c = join::<C>([ab.state(), c.clone()]).query();
+                let bc = join::<C>([b.clone(), c.clone()]);
+                let a_bc = join::<C>([a.clone(), bc.state()]).query();
+                assert_eq!(ab_c, a_bc, "seed {seed}: merge isn't associative");
+            }
+        }
+    }
+}
@@ -3,17 +3,13 @@ use std::{collections::HashMap, num::ParseIntError, sync::Arc};
 use anyhow::{bail, Result};
 use base::{
     init::recv_init,
+    kv::{CasParams, ErrorCode, KvClient},
     node::NodeId,
     serve::{serve, RequestHandler, Service},
     utils::init_log,
 };
 use futures::{future::BoxFuture, FutureExt};
 use serde::{Deserialize, Serialize};
-use serde_repr::{Deserialize_repr, Serialize_repr};
-
-use crate::linkv::{CasParams, LinKvClient};
-
-mod linkv;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -25,10 +21,7 @@ enum Request {
 #[serde(tag = "type", rename_all = "snake_case")]
 enum Response {
     TxnOk(Txn),
-    Error {
-        code: ServiceErrorCode,
-        text: String,
-    },
+    Error { code: ErrorCode, text: String },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -36,12 +29,6 @@ struct Txn {
     txn: Vec<(Op, Key, Option<Value>)>,
 }
 
-#[derive(Serialize_repr, Deserialize_repr, PartialEq, Debug)]
-#[repr(u32)]
-pub(crate) enum ServiceErrorCode {
-    TxnConflict = 30,
-}
-
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 enum Op {
     #[serde(rename = "r")]
@@ -87,7 +74,7 @@ impl Value {
     fn append(&self, other: &Value) -> Self {
         match (self, other) {
             (Self::Int(a), Self::Int(b)) => Self::List(vec![*a, *b]),
-            (Self::Int(a), Self::List(b)) => Self::List([[*a].as_slice(), &b.as_slice()].concat()),
+            (Self::Int(a), Self::List(b)) => Self::List([[*a].as_slice(), b.as_slice()].concat()),
             (Self::List(a), Self::Int(b)) => Self::List([a.as_slice(), &[*b]].concat()),
             (Self::List(a), Self::List(b)) => Self::List([a.as_slice(), b.as_slice()].concat()),
         }
@@ -95,7 +82,7 @@ impl Value {
 }
 
 struct Datomic {
-    lin_kv: LinKvClient<u32, Tree>,
+    lin_kv: KvClient<u32, Tree>,
 }
 
 type Tree = HashMap<Key, Value>;
@@ -103,7 +90,7 @@ type Tree = HashMap<Key, Value>;
 impl Datomic {
     const ROOT_KEY: u32 = 0;
 
-    fn new(lin_kv: &LinKvClient<u32, Tree>) -> Self {
+    fn new(lin_kv: &KvClient<u32, Tree>) -> Self {
         Self {
             lin_kv: lin_kv.clone(),
         }
@@ -163,7 +150,7 @@ impl RequestHandler for Datomic {
             }
 
             Ok(Some(Response::Error {
-                code: ServiceErrorCode::TxnConflict,
+                code: ErrorCode::TxnConflict,
                 text: "txn conflict".into(),
             }))
         }
@@ -174,8 +161,12 @@ impl RequestHandler for Datomic {
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     init_log()?;
-    let node = recv_init()?;
-    let lin_kv_client = LinKvClient::new(&node);
+    let node = recv_init().await?;
+    let lin_kv_client = KvClient::lin_kv(&node);
     let datomic_service = Arc::new(Datomic::new(&lin_kv_client));
-    serve((Service::new(&node, datomic_service), lin_kv_client.client())).await
+    serve(
+        &node,
+        (Service::new(&node, datomic_service), lin_kv_client.client()),
+    )
+    .await
 }
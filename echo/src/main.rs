@@ -21,10 +21,10 @@ enum Response {
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     init_log()?;
-    let node = recv_init()?;
-    let service = make_service(node, |Request::Echo { echo }| -> Result<Response> {
+    let node = recv_init().await?;
+    let service = make_service(node.clone(), |Request::Echo { echo }| -> Result<Response> {
         Ok(Response::EchoOk { echo })
     });
 
-    serve(service).await
+    serve(&node, service).await
 }
@@ -0,0 +1,135 @@
+use std::{
+    cmp,
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+// Per-key append-only logs with committed offsets. Offsets are either assigned locally
+// (`append`) or elsewhere (`insert`, e.g. by a node that allocated the offset in lin-kv),
+// in which case entries may arrive out of order. Polls only return entries up to the
+// first missing offset, so a consumer never skips over an entry that hasn't arrived yet.
+#[derive(Debug)]
+pub struct Log<K, V> {
+    partitions: HashMap<K, BTreeMap<u64, V>>,
+    committed: HashMap<K, u64>,
+}
+
+impl<K, V> Default for Log<K, V> {
+    fn default() -> Self {
+        Self {
+            partitions: HashMap::new(),
+            committed: HashMap::new(),
+        }
+    }
+}
+
+impl<K, V> Log<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offset following the last known one.
+    pub fn next_offset(&self, key: &K) -> u64 {
+        self.partitions
+            .get(key)
+            .and_then(|partition| partition.last_key_value())
+            .map(|(offset, _)| offset + 1)
+            .unwrap_or(0)
+    }
+
+    /// Appends at the offset following the last one, returns the offset.
+    pub fn append(&mut self, key: K, value: V) -> u64 {
+        let offset = self.next_offset(&key);
+        self.partitions
+            .entry(key)
+            .or_default()
+            .insert(offset, value);
+        offset
+    }
+
+    /// Returns `false` if there already was an entry at `offset`.
+    pub fn insert(&mut self, key: K, offset: u64, value: V) -> bool {
+        let partition = self.partitions.entry(key).or_default();
+        if partition.contains_key(&offset) {
+            return false;
+        }
+        partition.insert(offset, value);
+        true
+    }
+
+    /// Contiguous entries starting at `from`, at most `limit` of them.
+    pub fn poll(&self, key: &K, from: u64, limit: usize) -> Vec<(u64, V)> {
+        let Some(partition) = self.partitions.get(key) else {
+            return Vec::new();
+        };
+
+        partition
+            .range(from..)
+            .zip(from..)
+            .take_while(|((offset, _), expected)| *offset == expected)
+            .take(limit)
+            .map(|((offset, value), _)| (*offset, value.clone()))
+            .collect()
+    }
+
+    /// Offsets starting at `from` that have no entry yet although a later one does, among
+    /// the first `limit` offsets.
+    pub fn missing(&self, key: &K, from: u64, limit: usize) -> Vec<u64> {
+        let Some((last, partition)) = self.partitions.get(key).and_then(|partition| {
+            let (last, _) = partition.last_key_value()?;
+            Some((*last, partition))
+        }) else {
+            return Vec::new();
+        };
+
+        let end = cmp::min(last, from.saturating_add(limit as u64));
+        (from..end)
+            .filter(|offset| !partition.contains_key(offset))
+            .collect()
+    }
+
+    /// Committed offsets never go backwards.
+    pub fn commit(&mut self, key: K, offset: u64) {
+        self.committed
+            .entry(key)
+            .and_modify(|committed| *committed = cmp::max(*committed, offset))
+            .or_insert(offset);
+    }
+
+    pub fn committed(&self, key: &K) -> Option<u64> {
+        self.committed.get(key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_stops_at_missing_offsets() {
+        let mut log = Log::new();
+        for offset in [0, 1, 3, 4, 7] {
+            log.insert("k", offset, offset * 10);
+        }
+        assert!(!log.insert("k", 3, 0));
+
+        assert_eq!(log.poll(&"k", 0, 10), vec![(0, 0), (1, 10)]);
+        assert_eq!(log.poll(&"k", 3, 1), vec![(3, 30)]);
+        assert_eq!(log.missing(&"k", 0, 10), vec![2, 5, 6]);
+        assert_eq!(log.missing(&"k", 0, 5), vec![2]);
+        assert!(log.missing(&"k", 8, 10).is_empty());
+        assert!(log.missing(&"other", 0, 10).is_empty());
+
+        log.insert("k", 2, 20);
+        assert_eq!(
+            log.poll(&"k", 1, 10),
+            vec![(1, 10), (2, 20), (3, 30), (4, 40)]
+        );
+        assert_eq!(log.next_offset(&"k"), 8);
+        assert_eq!(log.append("k", 80), 8);
+    }
+}
@@ -0,0 +1,347 @@
+use std::{
+    cmp,
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::{Context, Result};
+use base::{
+    client::Client,
+    init::recv_init,
+    kv::{CasParams, KvClient},
+    node::NodeId,
+    serve::{serve, RequestHandler, Service},
+    utils::{async_spawn, init_log},
+};
+use futures::{future::BoxFuture, FutureExt};
+use kafka::log::Log;
+use serde::{Deserialize, Serialize};
+
+type Key = String;
+type Msg = u64;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Send { key: Key, msg: Msg },
+    Poll { offsets: HashMap<Key, u64> },
+    CommitOffsets { offsets: HashMap<Key, u64> },
+    ListCommittedOffsets { keys: Vec<Key> },
+    Replicate { key: Key, offset: u64, msg: Msg },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    SendOk { offset: u64 },
+    PollOk { msgs: HashMap<Key, Vec<(u64, Msg)>> },
+    CommitOffsetsOk,
+    ListCommittedOffsetsOk { offsets: HashMap<Key, u64> },
+    ReplicateOk,
+}
+
+// Every message is created in lin-kv under its offset, which reserves the offset and
+// stores the message in one step: an offset is never taken without its message, so
+// offsets in lin-kv have no gaps. Messages are also kept locally and replicated to every
+// other node, polls fetch the ones that haven't arrived from lin-kv. Committed offsets
+// live in lin-kv as well, the ones seen here are kept to skip commits known to be done.
+struct Kafka {
+    log: Mutex<Log<Key, Msg>>,
+    // Committed offsets are never `None` either, which only stands for a missing key.
+    lin_kv: KvClient<String, Option<u64>>,
+    // Entries are never `None`, which only serves as `from` of CAS that creates them.
+    entries: KvClient<String, Option<Msg>>,
+    client: Client<Request, Response>,
+}
+
+impl Kafka {
+    const MAX_POLL_LEN: usize = 100;
+    // Polls read whatever hasn't arrived from lin-kv, so replication gives up soon.
+    const REPLICATION_ATTEMPTS: usize = 3;
+
+    fn new(
+        lin_kv: &KvClient<String, Option<u64>>,
+        entries: &KvClient<String, Option<Msg>>,
+        client: &Client<Request, Response>,
+    ) -> Self {
+        Self {
+            log: Mutex::default(),
+            lin_kv: lin_kv.clone(),
+            entries: entries.clone(),
+            client: client.clone(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Log<Key, Msg>> {
+        self.log.lock().expect("lock panic")
+    }
+
+    async fn send(&self, key: Key, msg: Msg) -> Result<u64> {
+        // Offsets before the next local one are taken, later ones may be too.
+        let mut offset = self.lock().next_offset(&key);
+        while !self.create_entry(&key, offset, msg).await? {
+            offset += 1;
+        }
+        self.lock().insert(key.clone(), offset, msg);
+
+        let node = self.client.node();
+        for node_id in node.node_ids() {
+            if node_id == node.node_id() {
+                continue;
+            }
+
+            let client = self.client.clone();
+            let request = Request::Replicate {
+                key: key.clone(),
+                offset,
+                msg,
+            };
+            let node_id = node_id.clone();
+            async_spawn(node.shutdown(), async move {
+                let result = client
+                    .send_with_retry(Self::REPLICATION_ATTEMPTS, node_id.clone(), request)
+                    .await;
+                if let Err(error) = result {
+                    log::debug!("gave up replicating offset {offset} to {node_id}: {error}");
+                }
+                Ok(())
+            });
+        }
+
+        Ok(offset)
+    }
+
+    // `false` if the offset is taken already.
+    async fn create_entry(&self, key: &Key, offset: u64, msg: Msg) -> Result<bool> {
+        let params = CasParams {
+            from: None,
+            to: Some(msg),
+            create_if_not_exists: true,
+        };
+        self.entries.cas(entry_key(key, offset), params).await
+    }
+
+    async fn poll(&self, offsets: HashMap<Key, u64>) -> Result<HashMap<Key, Vec<(u64, Msg)>>> {
+        let mut polled = HashMap::new();
+        for (key, from) in offsets {
+            self.fetch_missing(&key, from).await?;
+            let msgs = self.lock().poll(&key, from, Self::MAX_POLL_LEN);
+            if !msgs.is_empty() {
+                polled.insert(key, msgs);
+            }
+        }
+        Ok(polled)
+    }
+
+    // Offsets followed by a known one are in lin-kv, whether or not their replicas made
+    // it here. Replicas of the newest entries may be missing too, so lin-kv is also read
+    // past the local tail until the first offset that isn't taken.
+    async fn fetch_missing(&self, key: &Key, from: u64) -> Result<()> {
+        let end = from.saturating_add(Self::MAX_POLL_LEN as u64);
+        let (missing, tail) = {
+            let log = self.lock();
+            let missing = log.missing(key, from, Self::MAX_POLL_LEN);
+            (missing, cmp::max(log.next_offset(key), from))
+        };
+
+        for offset in missing {
+            let msg = self
+                .read_entry(key, offset)
+                .await?
+                .with_context(|| format!("offset {offset} of {key} isn't in lin-kv"))?;
+            self.lock().insert(key.clone(), offset, msg);
+        }
+        for offset in tail..end {
+            let Some(msg) = self.read_entry(key, offset).await? else {
+                break;
+            };
+            self.lock().insert(key.clone(), offset, msg);
+        }
+        Ok(())
+    }
+
+    async fn read_entry(&self, key: &Key, offset: u64) -> Result<Option<Msg>> {
+        Ok(self.entries.read(entry_key(key, offset)).await?.flatten())
+    }
+
+    async fn commit(&self, key: Key, offset: u64) -> Result<()> {
+        // Committed offsets known here are in lin-kv already.
+        if self
+            .lock()
+            .committed(&key)
+            .is_some_and(|committed| committed >= offset)
+        {
+            return Ok(());
+        }
+
+        self.lin_kv
+            .update_with_cas(format!("committed/{key}"), None, |committed| {
+                // Committed offsets never go back.
+                committed
+                    .is_none_or(|committed| committed < offset)
+                    .then_some(Some(offset))
+            })
+            .await?;
+
+        self.lock().commit(key, offset);
+        Ok(())
+    }
+
+    async fn list_committed(&self, keys: Vec<Key>) -> Result<HashMap<Key, u64>> {
+        let mut offsets = HashMap::new();
+        for key in keys {
+            if let Some(offset) = self
+                .lin_kv
+                .read(format!("committed/{key}"))
+                .await?
+                .flatten()
+            {
+                self.lock().commit(key.clone(), offset);
+                offsets.insert(key, offset);
+            }
+        }
+        Ok(offsets)
+    }
+}
+
+impl RequestHandler for Kafka {
+    type Request = Request;
+    type Response = Response;
+
+    fn handle<'a>(
+        self: &'a Arc<Self>,
+        _sender: NodeId,
+        request: Self::Request,
+    ) -> BoxFuture<'a, Result<Option<Self::Response>>> {
+        async move {
+            match request {
+                Request::Send { key, msg } => {
+                    let offset = self.send(key, msg).await?;
+                    Ok(Some(Response::SendOk { offset }))
+                }
+                Request::Poll { offsets } => Ok(Some(Response::PollOk {
+                    msgs: self.poll(offsets).await?,
+                })),
+                Request::CommitOffsets { offsets } => {
+                    for (key, offset) in offsets {
+                        self.commit(key, offset).await?;
+                    }
+                    Ok(Some(Response::CommitOffsetsOk))
+                }
+                Request::ListCommittedOffsets { keys } => {
+                    Ok(Some(Response::ListCommittedOffsetsOk {
+                        offsets: self.list_committed(keys).await?,
+                    }))
+                }
+                Request::Replicate { key, offset, msg } => {
+                    self.lock().insert(key, offset, msg);
+                    Ok(Some(Response::ReplicateOk))
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    init_log()?;
+    let node = recv_init().await?;
+    let lin_kv = KvClient::lin_kv(&node);
+    let entries = KvClient::lin_kv(&node);
+    let client = Client::new(&node);
+    let kafka = Arc::new(Kafka::new(&lin_kv, &entries, &client));
+    let kv_clients = (lin_kv.client(), entries.client());
+    serve(&node, (Service::new(&node, kafka), client, kv_clients)).await
+}
+
+fn entry_key(key: &Key, offset: u64) -> String {
+    format!("entry/{key}/{offset}")
+}
+
+#[cfg(test)]
+mod tests {
+    use base::sim::{node_id, Network};
+
+    use super::*;
+
+    const NODES: [&str; 2] = ["n0", "n1"];
+
+    // Kafka on both nodes, lin-kv and a client node.
+    fn cluster(network: &Network) -> Client<Request, Response> {
+        network.serve_kv("lin-kv");
+        for id in NODES {
+            let node = network.node(id, &NODES);
+            let lin_kv = KvClient::lin_kv(&node);
+            let entries = KvClient::lin_kv(&node);
+            let client = Client::new(&node);
+            let kafka = Arc::new(Kafka::new(&lin_kv, &entries, &client));
+            let kv_clients = (lin_kv.client(), entries.client());
+            network.serve(&node, (Service::new(&node, kafka), client, kv_clients));
+        }
+
+        let node = network.node("c0", &NODES);
+        let client = Client::new(&node);
+        network.serve(&node, client.clone());
+        client
+    }
+
+    async fn send(client: &Client<Request, Response>, to: &str, msg: Msg) -> u64 {
+        let request = Request::Send {
+            key: "k".into(),
+            msg,
+        };
+        match client.send(node_id(to), request).await.unwrap() {
+            Response::SendOk { offset } => offset,
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+
+    async fn poll(client: &Client<Request, Response>, to: &str, from: u64) -> Vec<(u64, Msg)> {
+        let request = Request::Poll {
+            offsets: HashMap::from([("k".into(), from)]),
+        };
+        match client.send(node_id(to), request).await.unwrap() {
+            Response::PollOk { mut msgs } => msgs.remove("k").unwrap_or_default(),
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn polls_fetch_missed_replicas_past_the_local_tail() {
+        let network = Network::new();
+        let client = cluster(&network);
+        assert_eq!(send(&client, "n0", 10).await, 0);
+        assert_eq!(poll(&client, "n1", 0).await, vec![(0, 10)]);
+
+        // No more replicas reach n1, its local log ends before the newest entries.
+        network.cut(&node_id("n0"), &node_id("n1"));
+        assert_eq!(send(&client, "n0", 11).await, 1);
+        assert_eq!(send(&client, "n0", 12).await, 2);
+        assert_eq!(poll(&client, "n1", 1).await, vec![(1, 11), (2, 12)]);
+        assert_eq!(poll(&client, "n1", 3).await, vec![]);
+        assert_eq!(send(&client, "n1", 13).await, 3);
+    }
+
+    #[tokio::test]
+    async fn committed_offsets_are_shared() {
+        let network = Network::new();
+        let client = cluster(&network);
+        let commit = |offset| Request::CommitOffsets {
+            offsets: HashMap::from([("k".into(), offset)]),
+        };
+        let list = || Request::ListCommittedOffsets {
+            keys: vec!["k".into(), "other".into()],
+        };
+
+        client.send(node_id("n0"), commit(2)).await.unwrap();
+        // Committed offsets never go back.
+        client.send(node_id("n1"), commit(1)).await.unwrap();
+        match client.send(node_id("n1"), list()).await.unwrap() {
+            Response::ListCommittedOffsetsOk { offsets } => {
+                assert_eq!(offsets, HashMap::from([("k".into(), 2)]));
+            }
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+}
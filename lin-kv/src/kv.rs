@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use base::kv::{CasParams, ErrorCode, KvRequest, KvResponse};
+use consensus::StateMachine;
+
+pub type Key = u64;
+pub type Value = u64;
+
+#[derive(Debug, Default)]
+pub struct Kv {
+    values: HashMap<Key, Value>,
+}
+
+impl StateMachine for Kv {
+    type Command = KvRequest<Key, Value>;
+    type Output = KvResponse<Value>;
+    type Snapshot = Vec<(Key, Value)>;
+
+    fn apply(&mut self, command: &Self::Command) -> Self::Output {
+        match *command {
+            KvRequest::Read { key } => match self.values.get(&key) {
+                Some(value) => KvResponse::ReadOk { value: *value },
+                None => KvResponse::error(ErrorCode::KeyDoesNotExist, "not found"),
+            },
+            KvRequest::Write { key, value } => {
+                self.values.insert(key, value);
+                KvResponse::WriteOk
+            }
+            KvRequest::Cas {
+                key,
+                params:
+                    CasParams {
+                        from,
+                        to,
+                        create_if_not_exists,
+                    },
+            } => match self.values.get_mut(&key) {
+                Some(value) if *value == from => {
+                    *value = to;
+                    KvResponse::CasOk
+                }
+                Some(value) => KvResponse::error(
+                    ErrorCode::PreconditionFailed,
+                    format!("expected {from}, but had {value}"),
+                ),
+                None if create_if_not_exists => {
+                    self.values.insert(key, to);
+                    KvResponse::CasOk
+                }
+                None => KvResponse::error(ErrorCode::KeyDoesNotExist, "not found"),
+            },
+        }
+    }
+
+    fn snapshot(&self) -> Self::Snapshot {
+        self.values
+            .iter()
+            .map(|(key, value)| (*key, *value))
+            .collect()
+    }
+
+    fn restore(&mut self, snapshot: Self::Snapshot) {
+        self.values = snapshot.into_iter().collect();
+    }
+}
@@ -0,0 +1,10 @@
+use anyhow::Result;
+use base::utils::init_log;
+use consensus::raft::Raft;
+use lin_kv::service::LinKv;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    init_log()?;
+    LinKv::<Raft<_>>::run().await
+}
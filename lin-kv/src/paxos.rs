@@ -0,0 +1,10 @@
+use anyhow::Result;
+use base::utils::init_log;
+use consensus::paxos::Paxos;
+use lin_kv::service::LinKv;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    init_log()?;
+    LinKv::<Paxos<_>>::run().await
+}
@@ -0,0 +1,211 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use base::{
+    client::Client,
+    init::recv_init,
+    kv::{ErrorCode, KvRequest, KvResponse},
+    node::NodeId,
+    serve::{serve, RequestHandler, Service},
+};
+use consensus::{Engine, SubmitError};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+
+use crate::kv::{Key, Kv, Value};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Request<C> {
+    Kv(KvRequest<Key, Value>),
+    Consensus(C),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Response<C> {
+    Kv(KvResponse<Value>),
+    Consensus(C),
+}
+
+// Every operation (including reads) goes through the replicated log. Followers forward
+// client requests to the leader they know about.
+pub struct LinKv<E> {
+    engine: Arc<E>,
+    forward: Client<KvRequest<Key, Value>, KvResponse<Value>>,
+}
+
+impl<E: Engine<Machine = Kv>> LinKv<E> {
+    pub async fn run() -> Result<()> {
+        let node = recv_init().await?;
+        let engine_client = Client::new(&node);
+        let forward = Client::new(&node);
+        let lin_kv = Arc::new(Self::new(&engine_client, &forward));
+        serve(&node, (Service::new(&node, lin_kv), engine_client, forward)).await
+    }
+
+    // Starts the engine.
+    fn new(
+        engine_client: &Client<E::Request, E::Response>,
+        forward: &Client<KvRequest<Key, Value>, KvResponse<Value>>,
+    ) -> Self {
+        let engine = Arc::new(E::new(engine_client, Kv::default()));
+        engine.start();
+        Self {
+            engine,
+            forward: forward.clone(),
+        }
+    }
+
+    async fn execute(&self, sender: NodeId, request: KvRequest<Key, Value>) -> KvResponse<Value> {
+        match self.engine.submit(request.clone()).await {
+            Ok(response) => response,
+            // Only clients get forwarded, so that requests can't bounce between nodes
+            // that disagree on who the leader is.
+            Err(SubmitError::NotLeader(Some(leader)))
+                if !self.forward.node().node_ids().contains(&sender) =>
+            {
+                match self.forward.send(leader.clone(), request).await {
+                    Ok(response) => response,
+                    Err(error) => KvResponse::error(
+                        ErrorCode::Timeout,
+                        format!("failed to forward to {leader}: {error}"),
+                    ),
+                }
+            }
+            Err(error @ SubmitError::NotLeader(_)) => {
+                KvResponse::error(ErrorCode::TemporarilyUnavailable, error.to_string())
+            }
+            Err(error @ SubmitError::Indefinite(_)) => {
+                KvResponse::error(ErrorCode::Timeout, error.to_string())
+            }
+        }
+    }
+}
+
+impl<E: Engine<Machine = Kv>> RequestHandler for LinKv<E> {
+    type Request = Request<E::Request>;
+    type Response = Response<E::Response>;
+
+    fn handle<'a>(
+        self: &'a Arc<Self>,
+        sender: NodeId,
+        request: Self::Request,
+    ) -> BoxFuture<'a, Result<Option<Self::Response>>> {
+        async move {
+            let response = match request {
+                Request::Kv(request) => Response::Kv(self.execute(sender, request).await),
+                Request::Consensus(request) => {
+                    Response::Consensus(self.engine.handle(sender, request))
+                }
+            };
+            Ok(Some(response))
+        }
+        .boxed()
+    }
+
+    fn on_shutdown(self: &Arc<Self>) -> Result<()> {
+        log::info!("{} metrics: {}", E::NAME, self.engine.metrics().snapshot());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use base::{
+        kv::CasParams,
+        sim::{node_id, Network},
+    };
+    use consensus::{paxos::Paxos, raft::Raft};
+    use tokio::time::sleep;
+
+    use super::*;
+
+    const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+    type KvClient = Client<KvRequest<Key, Value>, KvResponse<Value>>;
+
+    fn cluster<E: Engine<Machine = Kv>>(network: &Network) -> KvClient {
+        for id in NODES {
+            let node = network.node(id, &NODES);
+            let engine_client = Client::new(&node);
+            let forward = Client::new(&node);
+            let lin_kv = Arc::new(LinKv::<E>::new(&engine_client, &forward));
+            network.serve(&node, (Service::new(&node, lin_kv), engine_client, forward));
+        }
+
+        let node = network.node("c0", &NODES);
+        let client = Client::new(&node);
+        network.serve(&node, client.clone());
+        client
+    }
+
+    // Retries until there is a leader.
+    async fn execute(
+        client: &KvClient,
+        to: &str,
+        request: KvRequest<Key, Value>,
+    ) -> KvResponse<Value> {
+        for _ in 0..100 {
+            match client.send(node_id(to), request.clone()).await.unwrap() {
+                KvResponse::Error {
+                    code: ErrorCode::TemporarilyUnavailable,
+                    ..
+                } => {
+                    sleep(Duration::from_millis(50)).await;
+                }
+                response => return response,
+            }
+        }
+        panic!("no leader elected");
+    }
+
+    async fn operations_are_applied_in_order<E: Engine<Machine = Kv>>() {
+        let network = Network::new();
+        let client = cluster::<E>(&network);
+
+        let write = KvRequest::Write { key: 1, value: 10 };
+        assert!(matches!(
+            execute(&client, "n0", write).await,
+            KvResponse::WriteOk
+        ));
+        let cas = |from, to| KvRequest::Cas {
+            key: 1,
+            params: CasParams {
+                from,
+                to,
+                create_if_not_exists: false,
+            },
+        };
+        assert!(matches!(
+            execute(&client, "n1", cas(10, 11)).await,
+            KvResponse::CasOk
+        ));
+        assert!(matches!(
+            execute(&client, "n2", cas(10, 12)).await,
+            KvResponse::Error {
+                code: ErrorCode::PreconditionFailed,
+                ..
+            }
+        ));
+        for to in NODES {
+            let read = KvRequest::Read { key: 1 };
+            assert!(matches!(
+                execute(&client, to, read).await,
+                KvResponse::ReadOk { value: 11 }
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn raft_applies_operations_in_order() {
+        operations_are_applied_in_order::<Raft<Kv>>().await;
+    }
+
+    #[tokio::test]
+    async fn paxos_applies_operations_in_order() {
+        operations_are_applied_in_order::<Paxos<Kv>>().await;
+    }
+}
@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use base::{
+    client::Client,
+    clock::{HlcTimestamp, HybridClock},
+    node::{Node, NodeId},
+    serve::RequestHandler,
+};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+
+// Same protocol as Maelstrom's `lin-tso` service, so `TsoClient` works with either.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    Ts,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    TsOk { ts: u64 },
+}
+
+// Timestamps are issued by a single authority (first node in the cluster) from its
+// hybrid clock, other nodes forward requests to it.
+pub struct TimestampOracle {
+    clock: HybridClock,
+    authority: NodeId,
+    client: Client<Request, Response>,
+}
+
+impl TimestampOracle {
+    pub fn new(client: &Client<Request, Response>, clock: HybridClock) -> Self {
+        let node = client.node();
+        Self {
+            clock,
+            authority: node.node_ids()[0].clone(),
+            client: client.clone(),
+        }
+    }
+
+    fn is_authority(&self) -> bool {
+        *self.client.node().node_id() == self.authority
+    }
+
+    async fn timestamp(&self) -> Result<HlcTimestamp> {
+        if self.is_authority() {
+            return Ok(self.clock.now());
+        }
+
+        let Response::TsOk { ts } = self
+            .client
+            .send(self.authority.clone(), Request::Ts)
+            .await?;
+        // Keep local clock ahead of everything we've handed out.
+        let ts = HlcTimestamp::from_u64(ts);
+        self.clock.observe(ts);
+        Ok(ts)
+    }
+}
+
+impl RequestHandler for TimestampOracle {
+    type Request = Request;
+    type Response = Response;
+
+    fn handle<'a>(
+        self: &'a Arc<Self>,
+        _sender: NodeId,
+        request: Self::Request,
+    ) -> BoxFuture<'a, Result<Option<Self::Response>>> {
+        async move {
+            let Request::Ts = request;
+            let ts = self.timestamp().await?.to_u64();
+            Ok(Some(Response::TsOk { ts }))
+        }
+        .boxed()
+    }
+}
+
+// Fetches strictly increasing timestamps, e.g. to assign commit timestamps to
+// transactions.
+#[derive(Debug)]
+pub struct TsoClient {
+    client: Client<Request, Response>,
+    oracle: NodeId,
+}
+
+impl Clone for TsoClient {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            oracle: self.oracle.clone(),
+        }
+    }
+}
+
+impl TsoClient {
+    pub fn new(node: &Node, oracle: NodeId) -> Self {
+        Self {
+            client: Client::new(node),
+            oracle,
+        }
+    }
+
+    // Uses Maelstrom's built-in `lin-tso` service.
+    pub fn lin_tso(node: &Node) -> Self {
+        Self::new(node, NodeId::lin_tso())
+    }
+
+    pub async fn timestamp(&self) -> Result<u64> {
+        let Response::TsOk { ts } = self
+            .client
+            .send(self.oracle.clone(), Request::Ts)
+            .await
+            .with_context(|| format!("failed to get timestamp from {}", self.oracle))?;
+        Ok(ts)
+    }
+
+    pub fn client(&self) -> Client<Request, Response> {
+        self.client.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::{
+        clock::ManualClock,
+        serve::Service,
+        sim::{node_id, Network},
+    };
+
+    use super::*;
+
+    const NODES: [&str; 2] = ["n0", "n1"];
+
+    // Oracles on both nodes, each with its own physical clock, and a client node.
+    fn cluster(millis: [u64; 2]) -> (Vec<Arc<ManualClock>>, TsoClient, TsoClient) {
+        let network = Network::new();
+        let clocks = millis.map(|millis| Arc::new(ManualClock::new(millis)));
+        for (id, clock) in NODES.iter().zip(&clocks) {
+            let node = network.node(id, &NODES);
+            let client = Client::new(&node);
+            let oracle = Arc::new(TimestampOracle::new(
+                &client,
+                HybridClock::new(clock.clone()),
+            ));
+            network.serve(&node, (Service::new(&node, oracle), client));
+        }
+
+        let node = network.node("c0", &NODES);
+        let authority = TsoClient::new(&node, node_id("n0"));
+        let follower = TsoClient::new(&node, node_id("n1"));
+        network.serve(&node, (authority.client(), follower.client()));
+        (clocks.into(), authority, follower)
+    }
+
+    fn ts(wall: u64, logical: u16) -> u64 {
+        HlcTimestamp { wall, logical }.to_u64()
+    }
+
+    #[tokio::test]
+    async fn authority_timestamps_strictly_increase() {
+        let (clocks, authority, _) = cluster([1000, 1000]);
+        assert_eq!(authority.timestamp().await.unwrap(), ts(1000, 0));
+        assert_eq!(authority.timestamp().await.unwrap(), ts(1000, 1));
+
+        // Physical clock going backwards doesn't matter.
+        clocks[0].set(500);
+        assert_eq!(authority.timestamp().await.unwrap(), ts(1000, 2));
+        clocks[0].set(2000);
+        assert_eq!(authority.timestamp().await.unwrap(), ts(2000, 0));
+    }
+
+    #[tokio::test]
+    async fn followers_forward_to_authority() {
+        // Follower's own clock is way ahead, yet its timestamps come from the authority.
+        let (_, authority, follower) = cluster([1000, 5000]);
+        let mut issued = Vec::new();
+        for _ in 0..3 {
+            issued.push(follower.timestamp().await.unwrap());
+            issued.push(authority.timestamp().await.unwrap());
+        }
+        assert_eq!(
+            issued,
+            (0..6).map(|logical| ts(1000, logical)).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,20 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use base::{
+    client::Client,
+    clock::HybridClock,
+    init::recv_init,
+    serve::{serve, Service},
+    utils::init_log,
+};
+use tso::TimestampOracle;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    init_log()?;
+    let node = recv_init().await?;
+    let client = Client::new(&node);
+    let oracle = Arc::new(TimestampOracle::new(&client, HybridClock::system()));
+    serve(&node, (Service::new(&node, oracle), client)).await
+}
@@ -0,0 +1,9 @@
+use anyhow::Result;
+use base::utils::init_log;
+use txn_rw_register::txn::{Isolation, TxnService};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    init_log()?;
+    TxnService::run(Isolation::ReadCommitted).await
+}
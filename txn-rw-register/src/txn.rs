@@ -0,0 +1,511 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use base::{
+    client::Client,
+    clock::HlcTimestamp,
+    init::recv_init,
+    node::NodeId,
+    serve::{serve, RequestHandler, Service},
+    utils::{async_spawn, every},
+};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+
+// Both variants execute transactions against local replica only, so they stay available
+// under partitions, and replicate writes to other nodes in the background. Registers
+// converge with last-writer-wins on (hybrid timestamp, node id) versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Isolation {
+    // Replicated writes are applied one by one, other nodes may observe a transaction
+    // partially applied.
+    ReadUncommitted,
+    // Replicated writes are applied atomically. Batches only ever hold final values of
+    // whole transactions, so other nodes never observe intermediate or partial ones.
+    ReadCommitted,
+}
+
+type Key = u64;
+type Value = u64;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    Txn { txn: Vec<MicroOp> },
+    Replicate { writes: Vec<Write> },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    TxnOk { txn: Vec<MicroOp> },
+    ReplicateOk,
+}
+
+type MicroOp = (Op, Key, Option<Value>);
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub enum Op {
+    #[serde(rename = "r")]
+    Read,
+    #[serde(rename = "w")]
+    Write,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    timestamp: HlcTimestamp,
+    node_id: NodeId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Write {
+    key: Key,
+    value: Value,
+    version: Version,
+}
+
+// Writes waiting to be replicated to one peer. Only the newest write of each key is kept
+// (older ones would be ignored by the peer anyway), so an outbox holds at most one write
+// per key however long the peer is unreachable. At most one batch is in flight.
+#[derive(Debug, Default)]
+struct Outbox {
+    writes: HashMap<Key, Write>,
+    in_flight: bool,
+}
+
+impl Outbox {
+    fn push(&mut self, write: Write) {
+        match self.writes.get(&write.key) {
+            Some(queued) if queued.version >= write.version => {}
+            _ => {
+                self.writes.insert(write.key, write);
+            }
+        }
+    }
+
+    // Everything queued, unless a batch is in flight already.
+    fn take(&mut self) -> Option<Vec<Write>> {
+        if self.in_flight || self.writes.is_empty() {
+            return None;
+        }
+        self.in_flight = true;
+        Some(self.writes.drain().map(|(_, write)| write).collect())
+    }
+
+    // Writes of a failed batch are queued again, unless newer ones are queued meanwhile.
+    fn sent(&mut self, failed: Vec<Write>) {
+        self.in_flight = false;
+        for write in failed {
+            self.push(write);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    registers: HashMap<Key, (Value, Version)>,
+}
+
+impl Store {
+    fn read(&self, key: Key) -> Option<Value> {
+        self.registers.get(&key).map(|(value, _)| *value)
+    }
+
+    fn overwrite(&mut self, write: Write) {
+        self.registers
+            .insert(write.key, (write.value, write.version));
+    }
+
+    // Ignores writes older than the current version.
+    fn apply(&mut self, write: Write) {
+        match self.registers.get(&write.key) {
+            Some((_, version)) if *version >= write.version => {}
+            _ => self.overwrite(write),
+        }
+    }
+}
+
+pub struct TxnService {
+    isolation: Isolation,
+    store: Mutex<Store>,
+    outboxes: Mutex<HashMap<NodeId, Outbox>>,
+    client: Client<Request, Response>,
+}
+
+impl TxnService {
+    const REPLICATION_INTERVAL: Duration = Duration::from_millis(100);
+
+    fn new(isolation: Isolation, client: &Client<Request, Response>) -> Self {
+        let node = client.node();
+        let outboxes = node
+            .node_ids()
+            .iter()
+            .filter(|node_id| *node_id != node.node_id())
+            .map(|node_id| (node_id.clone(), Outbox::default()))
+            .collect();
+        Self {
+            isolation,
+            store: Mutex::default(),
+            outboxes: Mutex::new(outboxes),
+            client: client.clone(),
+        }
+    }
+
+    fn start(self: &Arc<Self>) {
+        let service = self.clone();
+        every(
+            self.client.node().shutdown(),
+            Self::REPLICATION_INTERVAL,
+            move || {
+                service.flush();
+                async { Ok(()) }
+            },
+        );
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().expect("lock panic")
+    }
+
+    fn lock_outboxes(&self) -> MutexGuard<'_, HashMap<NodeId, Outbox>> {
+        self.outboxes.lock().expect("lock panic")
+    }
+
+    fn node_id(&self) -> &NodeId {
+        self.client.node().node_id()
+    }
+
+    fn version(&self) -> Version {
+        Version {
+            timestamp: self.client.node().clocks().hybrid().now(),
+            node_id: self.node_id().clone(),
+        }
+    }
+
+    fn execute(&self, txn: &mut [MicroOp]) -> Result<Vec<Write>> {
+        // Validate upfront, so that a failed transaction leaves no partial writes behind.
+        if txn
+            .iter()
+            .any(|(op, _, value)| matches!(op, Op::Write) && value.is_none())
+        {
+            bail!("missing txn value for write op");
+        }
+
+        let mut store = self.lock();
+        // All writes of a transaction share its version, so that replicas order them
+        // consistently: stamping writes one by one would allow write cycles (G0).
+        let txn_version = self.version();
+        let mut writes = Vec::<Write>::new();
+
+        for (op, key, value) in txn.iter_mut() {
+            match op {
+                Op::Read => *value = store.read(*key),
+                Op::Write => {
+                    let value = value.expect("validated above");
+                    let write = Write {
+                        key: *key,
+                        value,
+                        version: txn_version.clone(),
+                    };
+
+                    // Local versions are newer than anything observed so far.
+                    store.overwrite(write.clone());
+                    // Replicas ignore a write with the same version, only the final one counts.
+                    writes.retain(|w| w.key != write.key);
+                    writes.push(write);
+                }
+            }
+        }
+        Ok(writes)
+    }
+
+    fn replicate(&self, writes: Vec<Write>) {
+        for outbox in self.lock_outboxes().values_mut() {
+            for write in &writes {
+                outbox.push(write.clone());
+            }
+        }
+    }
+
+    // Sends queued writes to every peer without a batch in flight. Failed batches are
+    // retried on a later flush, until the partition heals.
+    fn flush(self: &Arc<Self>) {
+        let batches: Vec<_> = self
+            .lock_outboxes()
+            .iter_mut()
+            .filter_map(|(node_id, outbox)| Some((node_id.clone(), outbox.take()?)))
+            .collect();
+
+        for (node_id, writes) in batches {
+            let service = self.clone();
+            async_spawn(self.client.node().shutdown(), async move {
+                let request = Request::Replicate {
+                    writes: writes.clone(),
+                };
+                let failed = match service.client.send(node_id.clone(), request).await {
+                    Ok(_) => Vec::new(),
+                    Err(error) => {
+                        log::debug!("failed to replicate writes to {node_id}: {error}");
+                        writes
+                    }
+                };
+                if let Some(outbox) = service.lock_outboxes().get_mut(&node_id) {
+                    outbox.sent(failed);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    fn apply_replicated(&self, writes: Vec<Write>) {
+        let hybrid = self.client.node().clocks().hybrid();
+        match self.isolation {
+            Isolation::ReadUncommitted => {
+                for write in writes {
+                    hybrid.observe(write.version.timestamp);
+                    self.lock().apply(write);
+                }
+            }
+            Isolation::ReadCommitted => {
+                let mut store = self.lock();
+                for write in writes {
+                    hybrid.observe(write.version.timestamp);
+                    store.apply(write);
+                }
+            }
+        }
+    }
+
+    pub async fn run(isolation: Isolation) -> Result<()> {
+        let node = recv_init().await?;
+        // Versions are hybrid timestamps: with clocks piggybacked on every message between
+        // nodes, a write is versioned after everything its node has heard of.
+        node.clocks().set_piggybacking(true);
+        let client = Client::new(&node);
+        let service = Arc::new(Self::new(isolation, &client));
+        service.start();
+        serve(&node, (Service::new(&node, service), client)).await
+    }
+}
+
+impl RequestHandler for TxnService {
+    type Request = Request;
+    type Response = Response;
+
+    fn handle<'a>(
+        self: &'a Arc<Self>,
+        _sender: NodeId,
+        request: Self::Request,
+    ) -> BoxFuture<'a, Result<Option<Self::Response>>> {
+        async move {
+            match request {
+                Request::Txn { mut txn } => {
+                    let writes = self.execute(&mut txn)?;
+                    self.replicate(writes);
+                    Ok(Some(Response::TxnOk { txn }))
+                }
+                Request::Replicate { writes } => {
+                    self.apply_replicated(writes);
+                    Ok(Some(Response::ReplicateOk))
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use base::sim::{node_id, Network};
+    use tokio::time::{sleep, timeout};
+
+    use super::*;
+
+    const NODES: [&str; 2] = ["n0", "n1"];
+
+    // Services on both nodes and a client node.
+    fn cluster(
+        network: &Network,
+        isolation: Isolation,
+    ) -> (Vec<Arc<TxnService>>, Client<Request, Response>) {
+        let services = NODES
+            .iter()
+            .map(|id| {
+                let node = network.node(id, &NODES);
+                let client = Client::new(&node);
+                let service = Arc::new(TxnService::new(isolation, &client));
+                service.start();
+                network.serve(&node, (Service::new(&node, service.clone()), client));
+                service
+            })
+            .collect();
+
+        let node = network.node("c0", &NODES);
+        let client = Client::new(&node);
+        network.serve(&node, client.clone());
+        (services, client)
+    }
+
+    async fn txn(client: &Client<Request, Response>, to: &str, txn: Vec<MicroOp>) -> Vec<MicroOp> {
+        match client
+            .send(node_id(to), Request::Txn { txn })
+            .await
+            .unwrap()
+        {
+            Response::TxnOk { txn } => txn,
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+
+    fn values(txn: &[MicroOp]) -> Vec<Option<Value>> {
+        txn.iter().map(|(_, _, value)| *value).collect()
+    }
+
+    // Reads `keys` on `to` until they hold `expected`.
+    async fn await_values(
+        client: &Client<Request, Response>,
+        to: &str,
+        keys: &[Key],
+        expected: &[Option<Value>],
+    ) {
+        let reads: Vec<_> = keys.iter().map(|key| (Op::Read, *key, None)).collect();
+        timeout(Duration::from_secs(10), async {
+            while values(&txn(client, to, reads.clone()).await) != expected {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("writes weren't replicated");
+    }
+
+    fn write(key: Key, value: Value, wall: u64, node: &str) -> Write {
+        Write {
+            key,
+            value,
+            version: Version {
+                timestamp: HlcTimestamp { wall, logical: 0 },
+                node_id: node_id(node),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn transactions_read_their_own_writes() {
+        let network = Network::new();
+        let (_, client) = cluster(&network, Isolation::ReadCommitted);
+        let ops = vec![
+            (Op::Read, 1, None),
+            (Op::Write, 1, Some(10)),
+            (Op::Read, 1, None),
+            (Op::Write, 1, Some(11)),
+        ];
+        assert_eq!(
+            values(&txn(&client, "n0", ops).await),
+            vec![None, Some(10), Some(10), Some(11)]
+        );
+
+        // Invalid transactions leave no writes behind.
+        let invalid = Request::Txn {
+            txn: vec![(Op::Write, 1, Some(12)), (Op::Write, 2, None)],
+        };
+        assert!(client.send(node_id("n0"), invalid).await.is_err());
+        await_values(&client, "n0", &[1, 2], &[Some(11), None]).await;
+    }
+
+    #[tokio::test]
+    async fn writes_become_visible_on_other_nodes() {
+        for isolation in [Isolation::ReadUncommitted, Isolation::ReadCommitted] {
+            let network = Network::new();
+            let (_, client) = cluster(&network, isolation);
+            let ops = vec![
+                (Op::Write, 1, Some(10)),
+                (Op::Write, 1, Some(11)),
+                (Op::Write, 2, Some(20)),
+            ];
+            txn(&client, "n0", ops).await;
+            await_values(&client, "n1", &[1, 2], &[Some(11), Some(20)]).await;
+
+            // Later writes win, wherever they come from.
+            txn(&client, "n1", vec![(Op::Write, 2, Some(21))]).await;
+            await_values(&client, "n0", &[1, 2], &[Some(11), Some(21)]).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn replication_resumes_once_partition_heals() {
+        let network = Network::new();
+        let (services, client) = cluster(&network, Isolation::ReadCommitted);
+        network.cut(&node_id("n0"), &node_id("n1"));
+        // 40 writes to 6 keys.
+        for value in 0..20 {
+            let ops = vec![
+                (Op::Write, 1, Some(value)),
+                (Op::Write, value % 5 + 2, Some(value)),
+            ];
+            txn(&client, "n0", ops).await;
+        }
+
+        // Only the newest write of each key waits, with at most one batch in flight.
+        sleep(TxnService::REPLICATION_INTERVAL * 3).await;
+        {
+            let outboxes = services[0].lock_outboxes();
+            let outbox = &outboxes[&node_id("n1")];
+            assert!(outbox.in_flight);
+            assert!(outbox.writes.len() <= 6);
+        }
+
+        network.heal();
+        await_values(&client, "n1", &[1, 2, 6], &[Some(19), Some(15), Some(19)]).await;
+    }
+
+    #[tokio::test]
+    async fn replicated_writes_apply_last_writer_wins() {
+        for isolation in [Isolation::ReadUncommitted, Isolation::ReadCommitted] {
+            let network = Network::new();
+            let (services, _) = cluster(&network, isolation);
+            let service = &services[0];
+            service.apply_replicated(vec![write(1, 10, 100, "n1"), write(2, 20, 100, "n1")]);
+            // Older ones are ignored, ties are broken by node id.
+            service.apply_replicated(vec![write(1, 11, 99, "n1"), write(2, 21, 100, "n2")]);
+            let store = service.lock();
+            assert_eq!((store.read(1), store.read(2)), (Some(10), Some(21)));
+        }
+    }
+
+    #[test]
+    fn outbox_keeps_newest_write_per_key() {
+        let mut outbox = Outbox::default();
+        outbox.push(write(1, 10, 100, "n0"));
+        outbox.push(write(1, 9, 99, "n0"));
+        outbox.push(write(2, 20, 100, "n0"));
+
+        let mut batch = outbox.take().unwrap();
+        batch.sort_by_key(|write| write.key);
+        assert_eq!(
+            batch.iter().map(|write| write.value).collect::<Vec<_>>(),
+            vec![10, 20]
+        );
+
+        // Nothing else is sent while a batch is in flight.
+        outbox.push(write(2, 21, 101, "n0"));
+        assert!(outbox.take().is_none());
+
+        // Failed writes are retried, unless newer ones are queued already.
+        outbox.sent(batch);
+        let mut batch = outbox.take().unwrap();
+        batch.sort_by_key(|write| write.key);
+        assert_eq!(
+            batch.iter().map(|write| write.value).collect::<Vec<_>>(),
+            vec![10, 21]
+        );
+        outbox.sent(Vec::new());
+        assert!(outbox.take().is_none());
+    }
+}
@@ -0,0 +1,32 @@
+use anyhow::Result;
+use base::{
+    ids::SnowflakeIds,
+    init::recv_init,
+    serve::{make_service, serve},
+    utils::init_log,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Generate,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    GenerateOk { id: u64 },
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    init_log()?;
+    let node = recv_init().await?;
+    let ids = SnowflakeIds::new(&node)?;
+    let service = make_service(node.clone(), move |Request::Generate| -> Result<Response> {
+        Ok(Response::GenerateOk { id: ids.next() })
+    });
+
+    serve(&node, service).await
+}